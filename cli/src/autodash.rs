@@ -0,0 +1,735 @@
+//! High-level handle over a samod document holding the Autodash [`Doc`] schema.
+
+use crate::{ensure_schema, write_counter, AutodashError, Doc, Result};
+use autosurgeon::{hydrate, reconcile};
+use futures::FutureExt;
+use futures_util::StreamExt;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How long to let a burst of change notifications settle before re-hydrating
+const CHANGE_DEBOUNCE: Duration = Duration::from_millis(50);
+
+/// Top-level `Doc` fields that embedders can subscribe to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Field {
+    Counter,
+    Temperature,
+    #[value(alias = "darkMode", alias = "darkmode")]
+    DarkMode,
+    Notes,
+    Sections,
+    Code,
+    Tags,
+    Todos,
+    Settings,
+    Attachments,
+    Metadata,
+}
+
+impl Field {
+    /// The field's key in the document, as used by the browser client
+    pub fn name(&self) -> &'static str {
+        match self {
+            Field::Counter => "counter",
+            Field::Temperature => "temperature",
+            Field::DarkMode => "darkMode",
+            Field::Notes => "notes",
+            Field::Sections => "sections",
+            Field::Code => "code",
+            Field::Tags => "tags",
+            Field::Todos => "todos",
+            Field::Settings => "settings",
+            Field::Attachments => "attachments",
+            Field::Metadata => "metadata",
+        }
+    }
+
+    /// A comparable rendering of this field's value, used to detect changes
+    pub fn snapshot(&self, doc: &Doc) -> String {
+        match self {
+            Field::Counter => doc.counter.to_string(),
+            Field::Temperature => doc.temperature.to_string(),
+            Field::DarkMode => doc.darkMode.to_string(),
+            Field::Notes => doc.notes.as_str().to_string(),
+            Field::Sections => {
+                let entries: Vec<_> = doc
+                    .section_names()
+                    .into_iter()
+                    .map(|name| (name, doc.sections[name].as_str()))
+                    .collect();
+                format!("{:?}", entries)
+            }
+            Field::Code => doc.code.as_str().to_string(),
+            Field::Tags => format!("{:?}", doc.tags),
+            Field::Todos => doc
+                .todos
+                .iter()
+                .map(|t| {
+                    format!(
+                        "{:?}",
+                        (t.id.as_str(), t.text.as_str(), t.completed, &t.tags)
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Field::Settings => {
+                let mut entries: Vec<_> = doc.settings.iter().collect();
+                entries.sort();
+                format!("{:?}", entries)
+            }
+            Field::Attachments => format!("{:?}", doc.attachments),
+            Field::Metadata => format!(
+                "{:?}",
+                (
+                    doc.metadata.title.as_ref().map(|t| t.as_str()),
+                    doc.metadata.createdAt,
+                    doc.metadata.lastModified,
+                )
+            ),
+        }
+    }
+}
+
+/// Text fields that [`Autodash::splice_text`] can edit in place
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TextField {
+    Notes,
+    Code,
+    Title,
+}
+
+impl TextField {
+    /// The keys leading from the root to this field's Text object
+    fn path(self) -> &'static [&'static str] {
+        match self {
+            TextField::Notes => &["notes"],
+            TextField::Code => &["code"],
+            TextField::Title => &["metadata", "title"],
+        }
+    }
+
+    fn text_mut(self, state: &mut Doc) -> &mut autosurgeon::Text {
+        match self {
+            TextField::Notes => &mut state.notes,
+            TextField::Code => &mut state.code,
+            TextField::Title => state
+                .metadata
+                .title
+                .get_or_insert_with(|| autosurgeon::Text::with_value("")),
+        }
+    }
+}
+
+/// Object ids resolved by path, valid as long as the document is still at `heads`
+#[derive(Default)]
+struct ObjIdCache {
+    heads: Vec<automerge::ChangeHash>,
+    ids: HashMap<&'static str, automerge::ObjId>,
+}
+
+/// The id of the `obj_type` object at `key` under `parent`, if that's what is there
+fn resolve_object<D: automerge::ReadDoc>(
+    doc: &D,
+    parent: &automerge::ObjId,
+    key: &str,
+    obj_type: automerge::ObjType,
+) -> Result<Option<automerge::ObjId>> {
+    Ok(match doc.get(parent, key)? {
+        Some((automerge::Value::Object(found), id)) if found == obj_type => Some(id),
+        _ => None,
+    })
+}
+
+/// A synced Autodash document
+#[derive(Clone)]
+pub struct Autodash {
+    handle: samod::DocHandle,
+    obj_ids: Arc<Mutex<ObjIdCache>>,
+}
+
+impl Autodash {
+    pub fn new(handle: samod::DocHandle) -> Self {
+        Self {
+            handle,
+            obj_ids: Arc::default(),
+        }
+    }
+
+    pub fn handle(&self) -> &samod::DocHandle {
+        &self.handle
+    }
+
+    /// Hydrate the current document state
+    pub fn state(&self) -> Result<Doc> {
+        self.handle
+            .with_document(|doc| hydrate(doc).map_err(Into::into))
+    }
+
+    /// Mutate the document through its hydrated state; see [`with_hydrated`]
+    pub fn update<R>(&self, f: impl FnOnce(&mut Doc) -> R) -> Result<R> {
+        with_hydrated(&self.handle, f)
+    }
+
+    /// Splice a text field in place, stamping `metadata.lastModified`.
+    ///
+    /// Unlike [`update`](Self::update) this doesn't hydrate or reconcile the
+    /// whole document, and the ids of the Text and metadata objects are cached
+    /// between calls. The cache is dropped whenever the document has changed
+    /// since our last splice (a remote change may have recreated a field), so
+    /// a run of local edits resolves them once. A field that doesn't exist yet
+    /// is created through a regular hydrate/reconcile.
+    pub fn splice_text(
+        &self,
+        field: TextField,
+        pos: usize,
+        delete: isize,
+        insert: &str,
+    ) -> Result<()> {
+        let spliced = self.handle.with_document(|doc| -> Result<bool> {
+            let mut cache = self.obj_ids.lock().unwrap_or_else(|e| e.into_inner());
+            if cache.heads != doc.get_heads() {
+                cache.ids.clear();
+            }
+
+            let mut lookup = |key: &'static str,
+                              parent: &automerge::ObjId,
+                              obj_type: automerge::ObjType|
+             -> Result<Option<automerge::ObjId>> {
+                if let Some(id) = cache.ids.get(key) {
+                    return Ok(Some(id.clone()));
+                }
+                let id = resolve_object(doc, parent, key, obj_type)?;
+                if let Some(id) = &id {
+                    cache.ids.insert(key, id.clone());
+                }
+                Ok(id)
+            };
+
+            // Walk maps down to the field's Text object
+            let (last, parents) = field.path().split_last().expect("paths are never empty");
+            let mut parent = Some(automerge::ROOT);
+            for key in parents {
+                parent = match parent {
+                    Some(obj) => lookup(*key, &obj, automerge::ObjType::Map)?,
+                    None => None,
+                };
+            }
+            let text = match parent {
+                Some(obj) => lookup(*last, &obj, automerge::ObjType::Text)?,
+                None => None,
+            };
+            let metadata = lookup("metadata", &automerge::ROOT, automerge::ObjType::Map)?;
+            let (Some(text), Some(metadata)) = (text, metadata) else {
+                return Ok(false);
+            };
+
+            doc.transact(|tx| {
+                use automerge::transaction::Transactable;
+                tx.splice_text(&text, pos, delete, insert)?;
+                tx.put(
+                    &metadata,
+                    "lastModified",
+                    chrono::Utc::now().timestamp_millis(),
+                )?;
+                Ok::<_, automerge::AutomergeError>(())
+            })
+            .map_err(|failure| AutodashError::Automerge(failure.error))?;
+            cache.heads = doc.get_heads();
+            Ok(true)
+        })?;
+
+        if !spliced {
+            self.update(|state| {
+                field.text_mut(state).splice(pos, delete, insert);
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Merge another peer's copy of the document into this one, as if its
+    /// changes had arrived over sync. Returns the hashes of the changes merged in.
+    pub fn apply_remote(
+        &self,
+        remote: &mut automerge::Automerge,
+    ) -> Result<Vec<automerge::ChangeHash>> {
+        self.handle.with_document(|doc| Ok(doc.merge(remote)?))
+    }
+
+    /// A stream of freshly hydrated states, one per (debounced) burst of changes.
+    ///
+    /// Ends when the document's change stream does, or if the changed document
+    /// can't be hydrated.
+    pub fn subscribe_changes(&self) -> impl futures::Stream<Item = Doc> + '_ {
+        self.subscribe_with(Duration::ZERO)
+    }
+
+    /// Alias for [`subscribe_changes`](Self::subscribe_changes)
+    pub fn subscribe(&self) -> impl futures::Stream<Item = Doc> + '_ {
+        self.subscribe_changes()
+    }
+
+    /// Like [`subscribe_changes`](Self::subscribe_changes), but polling every
+    /// `poll_interval` instead of waiting for change events (zero means events);
+    /// see [`change_notifications`].
+    pub fn subscribe_with(&self, poll_interval: Duration) -> impl futures::Stream<Item = Doc> + '_ {
+        let changes = Box::pin(change_notifications(&self.handle, poll_interval));
+        futures::stream::unfold(changes, move |mut changes| async move {
+            changes.next().await?;
+            // Debounce: coalesce a burst of changes into one hydration
+            tokio::time::sleep(CHANGE_DEBOUNCE).await;
+            while changes.next().now_or_never().flatten().is_some() {}
+
+            match self.state() {
+                Ok(state) => Some((state, changes)),
+                Err(e) => {
+                    tracing::warn!("Failed to hydrate changed document: {}", e);
+                    None
+                }
+            }
+        })
+    }
+
+    /// Invoke `cb` with the new state each time `field` changes.
+    ///
+    /// Changes to other fields don't trigger the callback. Runs until the
+    /// document's change stream ends.
+    pub async fn on_field_change<F: FnMut(&Doc)>(&self, field: Field, cb: F) -> Result<()> {
+        self.on_field_change_with(field, Duration::ZERO, cb).await
+    }
+
+    /// Like [`on_field_change`](Self::on_field_change), but noticing changes
+    /// the way [`subscribe_with`](Self::subscribe_with) does for `poll_interval`
+    pub async fn on_field_change_with<F: FnMut(&Doc)>(
+        &self,
+        field: Field,
+        poll_interval: Duration,
+        mut cb: F,
+    ) -> Result<()> {
+        let mut last = field.snapshot(&self.state()?);
+        let mut states = Box::pin(self.subscribe_with(poll_interval));
+
+        while let Some(state) = states.next().await {
+            let current = field.snapshot(&state);
+            if current != last {
+                last = current;
+                cb(&state);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// One item per change to the document.
+///
+/// A zero `poll_interval` follows samod's change events. Otherwise the heads are
+/// checked every `poll_interval` instead, which costs a little CPU per tick but
+/// works even where change events aren't delivered; changes between two polls
+/// arrive as one item.
+pub fn change_notifications(
+    handle: &samod::DocHandle,
+    poll_interval: Duration,
+) -> impl futures::Stream<Item = ()> + '_ {
+    if poll_interval.is_zero() {
+        return handle.changes().map(|_| ()).left_stream();
+    }
+
+    let heads = handle.with_document(|doc| doc.get_heads());
+    let interval = tokio::time::interval(poll_interval);
+    futures::stream::unfold(
+        (interval, heads),
+        move |(mut interval, last_heads)| async move {
+            loop {
+                interval.tick().await;
+                let heads = handle.with_document(|doc| doc.get_heads());
+                if heads != last_heads {
+                    return Some(((), (interval, heads)));
+                }
+            }
+        },
+    )
+    .right_stream()
+}
+
+/// Reconcile only the top-level fields of `desired` that differ from `current`.
+///
+/// A full `reconcile` walks every field and can emit ops for ones that didn't
+/// change, e.g. when a client stored them in another representation. Skipping
+/// identical fields keeps such commands from growing the history needlessly.
+/// Text is written as in [`reconcile_doc`].
+pub fn reconcile_diff<D: autosurgeon::Doc + automerge::transaction::Transactable>(
+    tx: &mut D,
+    current: &Doc,
+    desired: &Doc,
+    max_splice_bytes: Option<usize>,
+) -> Result<()> {
+    use autosurgeon::reconcile_prop;
+    let root = automerge::ROOT;
+    let (staged, edits) = stage_text(tx, desired)?;
+    for field in <Field as clap::ValueEnum>::value_variants() {
+        // The snapshot leaves out the bookkeeping parts of metadata, so check those too
+        let differs = field.snapshot(current) != field.snapshot(desired)
+            || (*field == Field::Metadata
+                && (current.metadata.appliedKeys != desired.metadata.appliedKeys
+                    || current.metadata.locks != desired.metadata.locks));
+        if !differs {
+            continue;
+        }
+        let name = field.name();
+        match field {
+            Field::Counter => write_counter(tx, current.counter, desired.counter)?,
+            Field::Temperature => reconcile_prop(tx, &root, name, staged.temperature)?,
+            Field::DarkMode => reconcile_prop(tx, &root, name, staged.darkMode)?,
+            Field::Notes => reconcile_prop(tx, &root, name, &staged.notes)?,
+            Field::Sections => reconcile_prop(tx, &root, name, &staged.sections)?,
+            Field::Code => reconcile_prop(tx, &root, name, &staged.code)?,
+            Field::Tags => reconcile_prop(tx, &root, name, &staged.tags)?,
+            Field::Todos => reconcile_prop(tx, &root, name, &staged.todos)?,
+            Field::Settings => reconcile_prop(tx, &root, name, &staged.settings)?,
+            Field::Attachments => reconcile_prop(tx, &root, name, &staged.attachments)?,
+            Field::Metadata => reconcile_prop(tx, &root, name, &staged.metadata)?,
+        }
+    }
+    if current.schemaVersion != desired.schemaVersion {
+        reconcile_prop(tx, &root, "schemaVersion", desired.schemaVersion)?;
+    }
+    apply_text_edits(tx, edits, max_splice_bytes)
+}
+
+/// Reconcile the whole of `state`, like `reconcile`, but writing changed Text
+/// fields by character. The counter is left to [`write_counter`].
+///
+/// autosurgeon replays the splices made on a Text with the offsets they were
+/// made at, which are bytes in the local string but characters in the
+/// document, so any edit after a multi-byte character lands in the wrong
+/// place. Instead each Text that already exists is reconciled unedited and
+/// then brought to its new value with a single [`splice_plan`](crate::splice_plan) splice.
+/// With `max_splice_bytes`, a larger insert is split into several splices of at
+/// most that many bytes, so no single op grows unbounded.
+pub fn reconcile_doc<D: autosurgeon::Doc>(
+    tx: &mut D,
+    state: &Doc,
+    max_splice_bytes: Option<usize>,
+) -> Result<()> {
+    let (staged, edits) = stage_text(tx, state)?;
+    reconcile(tx, &staged)?;
+    apply_text_edits(tx, edits, max_splice_bytes)
+}
+
+/// A Text's path from the document root, and the value it should end up with
+type TextEdit = (Vec<automerge::Prop>, String);
+
+/// Every Text in `state`, with its path from the document root
+fn text_fields_mut(state: &mut Doc) -> Vec<(Vec<automerge::Prop>, &mut autosurgeon::Text)> {
+    let mut fields: Vec<(Vec<automerge::Prop>, &mut autosurgeon::Text)> = vec![
+        (vec!["notes".into()], &mut state.notes),
+        (vec!["code".into()], &mut state.code),
+    ];
+    if let Some(title) = &mut state.metadata.title {
+        fields.push((vec!["metadata".into(), "title".into()], title));
+    }
+    for (name, text) in &mut state.sections {
+        fields.push((vec!["sections".into(), name.into()], text));
+    }
+    for (index, todo) in state.todos.iter_mut().enumerate() {
+        fields.push((vec!["todos".into(), index.into(), "id".into()], &mut todo.id));
+        fields.push((vec!["todos".into(), index.into(), "text".into()], &mut todo.text));
+    }
+    fields
+}
+
+/// The Text object at `path`, if that's what is there
+fn find_text<D: autosurgeon::ReadDoc>(
+    doc: &D,
+    path: &[automerge::Prop],
+) -> Result<Option<automerge::ObjId>> {
+    use automerge::{ObjType, Value};
+    let mut obj = automerge::ROOT;
+    for (depth, prop) in path.iter().enumerate() {
+        let last = depth + 1 == path.len();
+        obj = match doc.get(&obj, prop.clone())? {
+            Some((Value::Object(ObjType::Text), id)) if last => id,
+            Some((Value::Object(ObjType::Map | ObjType::List), id)) if !last => id,
+            _ => return Ok(None),
+        };
+    }
+    Ok(Some(obj))
+}
+
+/// A copy of `state` whose existing Texts are freshly hydrated and unedited,
+/// so reconciling it leaves them alone, plus the values they should be given.
+/// Texts the document doesn't have yet are created with their whole value.
+fn stage_text<D: autosurgeon::Doc>(doc: &D, state: &Doc) -> Result<(Doc, Vec<TextEdit>)> {
+    let mut staged = state.clone();
+    let mut edits = Vec::new();
+    for (path, text) in text_fields_mut(&mut staged) {
+        let value = text.as_str().to_string();
+        match find_text(doc, &path)? {
+            Some(obj) => {
+                *text = <autosurgeon::Text as autosurgeon::Hydrate>::hydrate_text(doc, &obj)?;
+                if text.as_str() != value {
+                    edits.push((path, value));
+                }
+            }
+            None => *text = autosurgeon::Text::with_value(value),
+        }
+    }
+    Ok((staged, edits))
+}
+
+/// Give each Text in `edits` its new value, with splices counted in characters
+fn apply_text_edits<D: autosurgeon::Doc>(
+    tx: &mut D,
+    edits: Vec<TextEdit>,
+    max_splice_bytes: Option<usize>,
+) -> Result<()> {
+    for (path, value) in edits {
+        let Some(text) = find_text(tx, &path)? else {
+            continue;
+        };
+        let plan = crate::splice_plan(&tx.text(&text)?, &value);
+        let max = match max_splice_bytes {
+            Some(max) if max > 0 && plan.insert.len() > max => max,
+            _ => {
+                tx.splice_text(&text, plan.start, plan.delete as isize, &plan.insert)?;
+                continue;
+            }
+        };
+
+        if plan.delete > 0 {
+            tx.splice_text(&text, plan.start, plan.delete as isize, "")?;
+        }
+        let chunks = crate::chunk_str(&plan.insert, max);
+        let mut offset = plan.start;
+        for chunk in &chunks {
+            tx.splice_text(&text, offset, 0, chunk)?;
+            offset += chunk.chars().count();
+        }
+        tracing::debug!("Split {} byte insert into {} splices", plan.insert.len(), chunks.len());
+    }
+    Ok(())
+}
+
+/// Hydrate the document, let `f` mutate the state, then reconcile it back in a
+/// single transaction, returning whatever `f` returned.
+///
+/// Missing top-level fields are created first (see [`ensure_schema`]), and
+/// `metadata.lastModified` is stamped once afterwards if `f` changed anything.
+/// If reconciling fails the transaction is rolled back and the document is untouched.
+pub fn with_hydrated<R>(handle: &samod::DocHandle, f: impl FnOnce(&mut Doc) -> R) -> Result<R> {
+    mutate(handle, true, f)
+}
+
+/// Like [`with_hydrated`], but never touches `metadata.lastModified`
+pub fn with_hydrated_unstamped<R>(
+    handle: &samod::DocHandle,
+    f: impl FnOnce(&mut Doc) -> R,
+) -> Result<R> {
+    mutate(handle, false, f)
+}
+
+fn mutate<R>(handle: &samod::DocHandle, stamp: bool, f: impl FnOnce(&mut Doc) -> R) -> Result<R> {
+    handle.with_document(|doc| {
+        ensure_schema(doc)?;
+        let mut state: Doc = hydrate(doc)?;
+        let counter = state.counter;
+        let before = stamp.then(|| state.clone());
+        let result = f(&mut state);
+        if before.is_some_and(|before| !state.same_content(&before)) {
+            state.touch();
+        }
+        doc.transact(|tx| {
+            reconcile_doc(tx, &state, None)?;
+            write_counter(tx, counter, state.counter)?;
+            Ok::<_, AutodashError>(())
+        })
+        .map_err(|failure| failure.error)?;
+        Ok(result)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use automerge::ReadDoc;
+
+    /// A handle to a fresh document in an in-memory repo; keep the repo alive alongside it
+    async fn new_handle() -> (samod::Repo, samod::DocHandle) {
+        let repo = samod::Repo::build_tokio().load().await;
+        let handle = repo.create(automerge::Automerge::new()).await.unwrap();
+        (repo, handle)
+    }
+
+    #[tokio::test]
+    async fn with_hydrated_reconciles_the_mutation_and_returns_its_result() {
+        let (_repo, handle) = new_handle().await;
+        let returned = with_hydrated(&handle, |state| {
+            state.counter += 2;
+            state.tags.push("shipped".to_string());
+            "done"
+        })
+        .unwrap();
+        assert_eq!(returned, "done");
+
+        let state = Autodash::new(handle).state().unwrap();
+        assert_eq!(state.counter, 2);
+        assert_eq!(state.tags, vec!["shipped"]);
+    }
+
+    #[tokio::test]
+    async fn mutations_stamp_last_modified_exactly_once() {
+        let (_repo, handle) = new_handle().await;
+        let dash = Autodash::new(handle.clone());
+        dash.update(|state| state.temperature = 10).unwrap();
+        let first = dash.state().unwrap().metadata.lastModified;
+        assert!(first.is_some());
+
+        // One change per mutation, carrying the stamp along with the edit
+        let heads = handle.with_document(|doc| doc.get_heads());
+        dash.update(|state| state.temperature = 20).unwrap();
+        let changes = handle.with_document(|doc| doc.get_changes(&heads).len());
+        assert_eq!(changes, 1);
+        let stamped = dash.state().unwrap().metadata.lastModified;
+        assert!(stamped >= first);
+
+        // A mutation that changes nothing, and an unstamped one, leave it alone
+        dash.update(|state| state.temperature = 20).unwrap();
+        assert_eq!(dash.state().unwrap().metadata.lastModified, stamped);
+        with_hydrated_unstamped(&handle, |state| state.temperature = 30).unwrap();
+        let state = dash.state().unwrap();
+        assert_eq!(state.temperature, 30);
+        assert_eq!(state.metadata.lastModified, stamped);
+    }
+
+    #[tokio::test]
+    async fn first_mutation_initialises_an_empty_document() {
+        let (_repo, handle) = new_handle().await;
+        let missing = handle.with_document(|doc| crate::missing_fields(&*doc).len());
+        assert!(missing > 0);
+
+        with_hydrated(&handle, |_| ()).unwrap();
+        handle.with_document(|doc| {
+            assert!(crate::missing_fields(&*doc).is_empty());
+            crate::check_schema(&*doc).unwrap();
+        });
+    }
+
+    #[tokio::test]
+    async fn subscribe_yields_a_snapshot_when_another_handle_mutates() {
+        let (repo, handle) = new_handle().await;
+        let other = repo.find(handle.document_id().clone()).await.unwrap().unwrap();
+        with_hydrated(&handle, |_| ()).unwrap();
+
+        let watcher = Autodash::new(handle);
+        let mut states = Box::pin(watcher.subscribe_changes());
+        with_hydrated(&other, |state| state.counter += 1).unwrap();
+
+        let state = tokio::time::timeout(Duration::from_secs(5), states.next())
+            .await
+            .expect("no snapshot within 5s")
+            .expect("stream ended");
+        assert_eq!(state.counter, 1);
+    }
+
+    #[tokio::test]
+    async fn polling_notices_a_change() {
+        let (_repo, handle) = new_handle().await;
+        let mut changes = Box::pin(change_notifications(&handle, Duration::from_millis(10)));
+        with_hydrated(&handle, |state| state.darkMode = true).unwrap();
+
+        tokio::time::timeout(Duration::from_secs(5), changes.next())
+            .await
+            .expect("polling missed the change")
+            .expect("stream ended");
+    }
+
+    #[tokio::test]
+    async fn field_callback_skips_unrelated_changes() {
+        let (_repo, handle) = new_handle().await;
+        with_hydrated(&handle, |_| ()).unwrap();
+        let dash = Autodash::new(handle);
+
+        let mut seen = Vec::new();
+        let watch = tokio::time::timeout(
+            Duration::from_secs(1),
+            dash.on_field_change(Field::Counter, |state| seen.push(state.counter)),
+        );
+        let edits = async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            dash.update(|state| state.temperature = 25).unwrap();
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            dash.update(|state| state.counter += 1).unwrap();
+        };
+        let (watched, ()) = tokio::join!(watch, edits);
+        assert!(watched.is_err(), "the watch only ends when the stream does");
+        assert_eq!(seen, vec![1]);
+    }
+
+    #[tokio::test]
+    async fn apply_remote_merges_a_peers_changes() {
+        let (_repo, handle) = new_handle().await;
+        let dash = Autodash::new(handle.clone());
+        dash.update(|state| state.temperature = 12).unwrap();
+
+        let mut remote = handle.with_document(|doc| doc.fork());
+        let mut peer: Doc = hydrate(&remote).unwrap();
+        peer.tags.push("from-peer".to_string());
+        remote.transact(|tx| reconcile(tx, &peer)).unwrap();
+
+        let merged = dash.apply_remote(&mut remote).unwrap();
+        assert_eq!(merged.len(), 1);
+        let state = dash.state().unwrap();
+        assert_eq!(state.tags, vec!["from-peer"]);
+        assert_eq!(state.temperature, 12);
+        assert!(dash.apply_remote(&mut remote).unwrap().is_empty());
+    }
+
+    #[test]
+    fn reconcile_diff_only_touches_the_changed_field() {
+        let mut doc = automerge::Automerge::new();
+        ensure_schema(&mut doc).unwrap();
+        let mut state: Doc = hydrate(&doc).unwrap();
+        state.notes.splice(0, 0, "some notes");
+        state.tags.push("kept".to_string());
+        doc.transact(|tx| reconcile(tx, &state)).unwrap();
+
+        let before: Doc = hydrate(&doc).unwrap();
+        let mut after = before.clone();
+        after.counter += 1;
+        let heads = doc.get_heads();
+        doc.transact(|tx| reconcile_diff(tx, &before, &after, None)).unwrap();
+
+        // A single change holding a single op: the counter increment
+        let changes = doc.get_changes(&heads);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].len(), 1);
+        let state: Doc = hydrate(&doc).unwrap();
+        assert_eq!(state.counter, before.counter + 1);
+        assert_eq!(state.notes.as_str(), "some notes");
+        assert_eq!(state.tags, vec!["kept"]);
+    }
+
+    #[tokio::test]
+    async fn field_callback_fires_when_polling() {
+        let (_repo, handle) = new_handle().await;
+        with_hydrated(&handle, |_| ()).unwrap();
+        let dash = Autodash::new(handle);
+
+        let mut seen = Vec::new();
+        let watch = tokio::time::timeout(
+            Duration::from_secs(1),
+            dash.on_field_change_with(Field::DarkMode, Duration::from_millis(10), |state| {
+                seen.push(state.darkMode)
+            }),
+        );
+        let edits = async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            dash.update(|state| state.darkMode = true).unwrap();
+        };
+        let (watched, ()) = tokio::join!(watch, edits);
+        assert!(watched.is_err(), "the watch only ends when the stream does");
+        assert_eq!(seen, vec![true]);
+    }
+}
@@ -0,0 +1,138 @@
+//! User preferences from `~/.config/autodash/config.toml` (or `$AUTODASH_CONFIG`).
+//!
+//! Command-line flags take precedence over anything set here.
+
+use crate::{AutodashError, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Fields shown by `show`, in order, e.g. `["counter", "notes", "todos"]`
+    pub field_order: Option<Vec<String>>,
+    /// Characters of notes/title shown on the dashboard before truncating
+    pub preview_len: Option<usize>,
+    /// Always use the plain ASCII dashboard
+    pub ascii: Option<bool>,
+    /// TUI color theme: `dark`, `light` or `high-contrast`
+    pub theme: Option<String>,
+    /// Token presented to sync servers that require authentication
+    pub auth_token: Option<String>,
+}
+
+impl Config {
+    /// Where the config file lives: `$AUTODASH_CONFIG`, else `~/.config/autodash/config.toml`
+    pub fn path() -> Option<PathBuf> {
+        if let Some(path) = std::env::var_os("AUTODASH_CONFIG") {
+            return Some(PathBuf::from(path));
+        }
+        let home = std::env::var_os("HOME")?;
+        Some(PathBuf::from(home).join(".config/autodash/config.toml"))
+    }
+
+    /// The sync server token: `flag`, else `$AUTODASH_AUTH_TOKEN`, else `auth_token` from the file
+    pub fn resolve_auth_token(&self, flag: Option<&str>) -> Option<String> {
+        flag.map(str::to_string)
+            .or_else(|| std::env::var("AUTODASH_AUTH_TOKEN").ok())
+            .or_else(|| self.auth_token.clone())
+    }
+
+    /// Where document labels live: `aliases.toml` beside the config file
+    pub fn aliases_path() -> Option<PathBuf> {
+        Some(Self::path()?.parent()?.join("aliases.toml"))
+    }
+
+    /// Document labels and the URLs they stand for, treating a missing file as empty
+    pub fn load_aliases() -> Result<BTreeMap<String, String>> {
+        let Some(path) = Self::aliases_path() else {
+            return Ok(BTreeMap::new());
+        };
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+            Err(e) => return Err(e.into()),
+        };
+        toml::from_str(&contents).map_err(|e| AutodashError::Config {
+            path,
+            message: e.to_string(),
+        })
+    }
+
+    /// Record `label` as a name for `url`, replacing any document it named before
+    pub fn save_alias(label: &str, url: &str) -> Result<()> {
+        let Some(path) = Self::aliases_path() else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "no home directory to keep document labels in",
+            )
+            .into());
+        };
+        let mut aliases = Self::load_aliases()?;
+        aliases.retain(|existing, _| !existing.eq_ignore_ascii_case(label));
+        aliases.insert(label.to_string(), url.to_string());
+
+        let contents = toml::to_string(&aliases).map_err(|e| AutodashError::Config {
+            path: path.clone(),
+            message: e.to_string(),
+        })?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&path, contents)?;
+        Ok(())
+    }
+
+    /// The URL labelled `label` (ignoring case), if any
+    pub fn resolve_alias(label: &str) -> Result<Option<String>> {
+        Ok(Self::load_aliases()?
+            .into_iter()
+            .find(|(existing, _)| existing.eq_ignore_ascii_case(label))
+            .map(|(_, url)| url))
+    }
+
+    /// Load the config file, treating a missing file as empty
+    pub fn load() -> Result<Self> {
+        let Some(path) = Self::path() else {
+            return Ok(Self::default());
+        };
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+        toml::from_str(&contents).map_err(|e| AutodashError::Config {
+            path,
+            message: e.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_saved_label_resolves_to_its_document() {
+        // The only test here that touches the environment, so it can't race another
+        let dir = std::env::temp_dir().join(format!("autodash-config-{}", crate::new_id()));
+        std::env::set_var("AUTODASH_CONFIG", dir.join("config.toml"));
+
+        assert_eq!(Config::resolve_alias("groceries").unwrap(), None);
+        Config::save_alias("groceries", "automerge:4VgLSsiuVNfWeZk17m85GgA18VVp").unwrap();
+        Config::save_alias("work", "automerge:2j9knpCseyhnK8izDmLpGP5WMdZQ").unwrap();
+        assert_eq!(
+            Config::resolve_alias("Groceries").unwrap().as_deref(),
+            Some("automerge:4VgLSsiuVNfWeZk17m85GgA18VVp")
+        );
+
+        // Relabelling points the name at the new document
+        Config::save_alias("GROCERIES", "automerge:3dNsNQ5zFwE2ZKvKbAzsnwFZfbnq").unwrap();
+        assert_eq!(
+            Config::resolve_alias("groceries").unwrap().as_deref(),
+            Some("automerge:3dNsNQ5zFwE2ZKvKbAzsnwFZfbnq")
+        );
+        assert_eq!(Config::load_aliases().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,432 @@
+//! Collapsing concurrent writes (conflicts) according to a chosen policy.
+//!
+//! Automerge keeps every concurrent value of a key and picks a deterministic
+//! winner by actor id. Writing the chosen value again supersedes all of them;
+//! a winning object (text, list or map) is copied into a fresh object of the
+//! same type.
+
+use crate::{AutodashError, Result};
+use automerge::{
+    transaction::Transactable, Automerge, AutomergeError, ChangeHash, ObjId, ObjType, Prop,
+    ReadDoc, ScalarValue, Value,
+};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ConflictPolicy {
+    /// Keep the value from the change with the latest timestamp
+    LastWriteByTimestamp,
+    /// Keep the largest value (numbers, strings, booleans)
+    Largest,
+    /// Keep the smallest value (numbers, strings, booleans)
+    Smallest,
+    /// Ask on stdin which value to keep
+    Interactive,
+}
+
+/// How `merge-file` settles the conflicts left by merging an unrelated document
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum MergeStrategy {
+    /// Keep the value from the merged-in document
+    Theirs,
+    /// Keep the live document's value
+    Ours,
+    /// Keep the value from the change with the latest timestamp
+    Newest,
+}
+
+/// A key holding several concurrent values
+#[derive(Debug, Clone)]
+pub struct Conflict {
+    pub path: String,
+    pub obj: ObjId,
+    pub key: String,
+    /// Each concurrent value with the id of the op that wrote it; for an
+    /// object that id is the object itself
+    pub values: Vec<(Value<'static>, ObjId)>,
+}
+
+/// Find conflicts at the top level and in the `metadata`/`settings`/`sections` maps
+pub fn find_conflicts(doc: &Automerge) -> Result<Vec<Conflict>> {
+    let mut objects = vec![(String::new(), automerge::ROOT)];
+    for name in ["metadata", "settings", "sections"] {
+        if let Some((Value::Object(ObjType::Map), obj)) = doc.get(automerge::ROOT, name)? {
+            objects.push((format!("{}.", name), obj));
+        }
+    }
+
+    let mut conflicts = Vec::new();
+    for (prefix, obj) in objects {
+        for key in doc.keys(&obj) {
+            let values = doc.get_all(&obj, key.as_str())?;
+            if values.len() < 2 {
+                continue;
+            }
+            conflicts.push(Conflict {
+                path: format!("{}{}", prefix, key),
+                obj: obj.clone(),
+                key,
+                values: values.into_iter().map(|(value, id)| (value.to_owned(), id)).collect(),
+            });
+        }
+    }
+    Ok(conflicts)
+}
+
+/// Hash and timestamp of the change containing the op `id`, if it can be found
+fn op_change(doc: &Automerge, id: &ObjId) -> Option<(ChangeHash, i64)> {
+    let ObjId::Id(counter, actor, _) = id else {
+        return None;
+    };
+    doc.get_changes(&[])
+        .iter()
+        .find(|change| {
+            let start = change.start_op().get();
+            change.actor_id() == actor && start <= *counter && *counter < start + change.len() as u64
+        })
+        .map(|change| (change.hash(), change.timestamp()))
+}
+
+fn value_cmp(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Scalar(a), Value::Scalar(b)) => scalar_cmp(a, b),
+        _ => None,
+    }
+}
+
+fn scalar_cmp(a: &ScalarValue, b: &ScalarValue) -> Option<Ordering> {
+    fn as_number(value: &ScalarValue) -> Option<f64> {
+        match value {
+            ScalarValue::F64(f) => Some(*f),
+            ScalarValue::Uint(u) => Some(*u as f64),
+            other => other.to_i64().map(|i| i as f64),
+        }
+    }
+
+    match (a, b) {
+        (ScalarValue::Str(a), ScalarValue::Str(b)) => Some(a.cmp(b)),
+        (ScalarValue::Boolean(a), ScalarValue::Boolean(b)) => Some(a.cmp(b)),
+        _ => as_number(a)?.partial_cmp(&as_number(b)?),
+    }
+}
+
+/// One-line description of a value; objects are summarised by their contents
+fn describe(doc: &Automerge, value: &Value, id: &ObjId) -> String {
+    match value {
+        Value::Scalar(s) => s.to_string(),
+        Value::Object(ObjType::Text) => format!("{:?}", doc.text(id).unwrap_or_default()),
+        Value::Object(ObjType::List) => format!("a list of {} item(s)", doc.length(id)),
+        Value::Object(_) => format!("a map with {} key(s)", doc.keys(id).count()),
+    }
+}
+
+fn prompt_choice(doc: &Automerge, conflict: &Conflict) -> Result<usize> {
+    println!("\n⚔️  {} has {} concurrent values:", conflict.path, conflict.values.len());
+    for (i, (value, id)) in conflict.values.iter().enumerate() {
+        println!("  [{}] {}", i + 1, describe(doc, value, id));
+    }
+    loop {
+        print!("Keep which value? ");
+        std::io::stdout().flush()?;
+        let mut line = String::new();
+        if std::io::stdin().read_line(&mut line)? == 0 {
+            return Err(AutodashError::NoChoice(conflict.path.clone()));
+        }
+        match line.trim().parse::<usize>() {
+            Ok(n) if (1..=conflict.values.len()).contains(&n) => return Ok(n - 1),
+            _ => println!("Enter a number between 1 and {}", conflict.values.len()),
+        }
+    }
+}
+
+/// Pick the index of the value to keep under `policy`.
+///
+/// Objects don't compare, so `largest`/`smallest` keep the first value when
+/// any of the concurrent values is an object.
+pub fn choose_winner(doc: &Automerge, conflict: &Conflict, policy: ConflictPolicy) -> Result<usize> {
+    let values = &conflict.values;
+    let best_by = |better: Ordering| {
+        (1..values.len()).fold(0, |best, i| {
+            match value_cmp(&values[i].0, &values[best].0) {
+                Some(ordering) if ordering == better => i,
+                _ => best,
+            }
+        })
+    };
+
+    Ok(match policy {
+        ConflictPolicy::LastWriteByTimestamp => (0..values.len())
+            .max_by_key(|&i| (op_change(doc, &values[i].1).map_or(0, |(_, ts)| ts), i))
+            .unwrap_or(0),
+        ConflictPolicy::Largest => best_by(Ordering::Greater),
+        ConflictPolicy::Smallest => best_by(Ordering::Less),
+        ConflictPolicy::Interactive => prompt_choice(doc, conflict)?,
+    })
+}
+
+/// Resolve every conflict under `policy`, writing each winner explicitly.
+///
+/// Returns the resolved paths and a description of the values that were kept.
+pub fn resolve_conflicts(doc: &mut Automerge, policy: ConflictPolicy) -> Result<Vec<(String, String)>> {
+    let mut resolutions = Vec::new();
+    for conflict in find_conflicts(doc)? {
+        let winner = choose_winner(doc, &conflict, policy)?;
+        resolutions.push((conflict, winner));
+    }
+    write_winners(doc, resolutions)
+}
+
+/// Merge `other` into `doc`, then resolve the conflicts between the two sides under `strategy`.
+///
+/// Only keys holding values from both documents are touched; conflicts `doc`
+/// already had are left for `resolve-conflicts`. Merging a document created
+/// separately leaves whole objects (`notes`, `todos`, `sections`, ...) in
+/// conflict, and those are settled the same way as scalars. Returns the
+/// resolved paths and a description of the values that were kept.
+pub fn merge_with_strategy(
+    doc: &mut Automerge,
+    other: &mut Automerge,
+    strategy: MergeStrategy,
+) -> Result<Vec<(String, String)>> {
+    let ours: HashSet<ChangeHash> = doc.get_changes(&[]).iter().map(|change| change.hash()).collect();
+    doc.merge(other)?;
+
+    let mut resolutions = Vec::new();
+    for conflict in find_conflicts(doc)? {
+        // Which values were written by the live document before the merge
+        let is_ours: Vec<bool> = conflict
+            .values
+            .iter()
+            .map(|(_, id)| matches!(op_change(doc, id), Some((hash, _)) if ours.contains(&hash)))
+            .collect();
+        if is_ours.iter().all(|&mine| mine) || !is_ours.contains(&true) {
+            continue;
+        }
+
+        let winner = match strategy {
+            MergeStrategy::Theirs => is_ours.iter().position(|&mine| !mine).unwrap_or(0),
+            MergeStrategy::Ours => is_ours.iter().position(|&mine| mine).unwrap_or(0),
+            MergeStrategy::Newest => {
+                choose_winner(doc, &conflict, ConflictPolicy::LastWriteByTimestamp)?
+            }
+        };
+        resolutions.push((conflict, winner));
+    }
+    write_winners(doc, resolutions)
+}
+
+/// A detached copy of a value, so a winning object can be written out again
+enum Snapshot {
+    Scalar(ScalarValue),
+    Text(String),
+    List(Vec<Snapshot>),
+    Map(ObjType, Vec<(String, Snapshot)>),
+}
+
+impl Snapshot {
+    fn take(doc: &Automerge, value: &Value, id: &ObjId) -> Result<Snapshot> {
+        Ok(match value {
+            Value::Scalar(s) => Snapshot::Scalar(s.clone().into_owned()),
+            Value::Object(ObjType::Text) => Snapshot::Text(doc.text(id)?),
+            Value::Object(ObjType::List) => {
+                let mut items = Vec::new();
+                for index in 0..doc.length(id) {
+                    if let Some((value, item)) = doc.get(id, index)? {
+                        items.push(Snapshot::take(doc, &value, &item)?);
+                    }
+                }
+                Snapshot::List(items)
+            }
+            Value::Object(kind) => {
+                let mut entries = Vec::new();
+                for key in doc.keys(id) {
+                    if let Some((value, item)) = doc.get(id, key.as_str())? {
+                        entries.push((key, Snapshot::take(doc, &value, &item)?));
+                    }
+                }
+                Snapshot::Map(*kind, entries)
+            }
+        })
+    }
+
+    /// Write this value at `prop` of `obj`, inserting into lists
+    fn write(&self, tx: &mut impl Transactable, obj: &ObjId, prop: Prop, insert: bool) -> Result<(), AutomergeError> {
+        let kind = match self {
+            Snapshot::Scalar(value) => {
+                return match prop {
+                    Prop::Seq(index) if insert => tx.insert(obj, index, value.clone()),
+                    prop => tx.put(obj, prop, value.clone()),
+                };
+            }
+            Snapshot::Text(_) => ObjType::Text,
+            Snapshot::List(_) => ObjType::List,
+            Snapshot::Map(kind, _) => *kind,
+        };
+        let child = match prop {
+            Prop::Seq(index) if insert => tx.insert_object(obj, index, kind)?,
+            prop => tx.put_object(obj, prop, kind)?,
+        };
+        match self {
+            Snapshot::Text(text) => tx.splice_text(&child, 0, 0, text)?,
+            Snapshot::List(items) => {
+                for (index, item) in items.iter().enumerate() {
+                    item.write(tx, &child, index.into(), true)?;
+                }
+            }
+            Snapshot::Map(_, entries) => {
+                for (key, item) in entries {
+                    item.write(tx, &child, key.as_str().into(), false)?;
+                }
+            }
+            Snapshot::Scalar(_) => unreachable!(),
+        }
+        Ok(())
+    }
+}
+
+/// Write each chosen value in one transaction, superseding the concurrent ones
+fn write_winners(
+    doc: &mut Automerge,
+    resolutions: Vec<(Conflict, usize)>,
+) -> Result<Vec<(String, String)>> {
+    if resolutions.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // Copy the winners out first; a superseded object can't be read mid-write
+    let mut writes = Vec::new();
+    for (conflict, winner) in resolutions {
+        let (value, id) = &conflict.values[winner];
+        let kept = describe(doc, value, id);
+        writes.push((Snapshot::take(doc, value, id)?, conflict, kept));
+    }
+
+    doc.transact(|tx| {
+        for (snapshot, conflict, _) in &writes {
+            snapshot.write(tx, &conflict.obj, conflict.key.as_str().into(), false)?;
+        }
+        Ok::<_, AutomergeError>(())
+    })
+    .map_err(|failure| failure.error)?;
+
+    Ok(writes.into_iter().map(|(_, conflict, kept)| (conflict.path, kept)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use automerge::transaction::CommitOptions;
+
+    /// A separately created document, committed at `time`
+    fn side(temperature: i64, notes: &str, todo: &str, time: i64) -> Automerge {
+        let mut doc = Automerge::new();
+        doc.transact_with::<_, _, AutomergeError, _>(
+            |_| CommitOptions::default().with_time(time),
+            |tx| {
+                tx.put(automerge::ROOT, "temperature", temperature)?;
+                let text = tx.put_object(automerge::ROOT, "notes", ObjType::Text)?;
+                tx.splice_text(&text, 0, 0, notes)?;
+                let todos = tx.put_object(automerge::ROOT, "todos", ObjType::List)?;
+                let item = tx.insert_object(&todos, 0, ObjType::Map)?;
+                let text = tx.put_object(&item, "text", ObjType::Text)?;
+                tx.splice_text(&text, 0, 0, todo)?;
+                tx.put(&item, "completed", true)?;
+                Ok(())
+            },
+        )
+        .unwrap();
+        doc
+    }
+
+    fn read(doc: &Automerge) -> (i64, String, String, bool) {
+        let (temperature, _) = doc.get(automerge::ROOT, "temperature").unwrap().unwrap();
+        let (_, notes) = doc.get(automerge::ROOT, "notes").unwrap().unwrap();
+        let (_, todos) = doc.get(automerge::ROOT, "todos").unwrap().unwrap();
+        assert_eq!(doc.length(&todos), 1);
+        let (_, item) = doc.get(&todos, 0).unwrap().unwrap();
+        let (_, text) = doc.get(&item, "text").unwrap().unwrap();
+        let (completed, _) = doc.get(&item, "completed").unwrap().unwrap();
+        (
+            temperature.to_i64().unwrap(),
+            doc.text(&notes).unwrap(),
+            doc.text(&text).unwrap(),
+            completed.to_bool().unwrap(),
+        )
+    }
+
+    fn merge(strategy: MergeStrategy, our_time: i64, their_time: i64) -> (Automerge, usize) {
+        let mut ours = side(20, "our notes", "our todo", our_time);
+        let mut theirs = side(30, "their notes", "their todo", their_time);
+        let resolved = merge_with_strategy(&mut ours, &mut theirs, strategy).unwrap();
+        assert!(find_conflicts(&ours).unwrap().is_empty());
+        (ours, resolved.len())
+    }
+
+    #[test]
+    fn theirs_keeps_scalars_and_objects_from_the_merged_document() {
+        let (doc, resolved) = merge(MergeStrategy::Theirs, 2_000, 1_000);
+        assert_eq!(resolved, 3);
+        assert_eq!(read(&doc), (30, "their notes".into(), "their todo".into(), true));
+    }
+
+    #[test]
+    fn ours_keeps_the_live_document() {
+        let (doc, resolved) = merge(MergeStrategy::Ours, 1_000, 2_000);
+        assert_eq!(resolved, 3);
+        assert_eq!(read(&doc), (20, "our notes".into(), "our todo".into(), true));
+    }
+
+    #[test]
+    fn newest_keeps_the_latest_change() {
+        let (doc, _) = merge(MergeStrategy::Newest, 1_000, 2_000);
+        assert_eq!(read(&doc), (30, "their notes".into(), "their todo".into(), true));
+        let (doc, _) = merge(MergeStrategy::Newest, 2_000, 1_000);
+        assert_eq!(read(&doc), (20, "our notes".into(), "our todo".into(), true));
+    }
+
+    #[test]
+    fn merging_a_fork_leaves_nothing_to_resolve() {
+        let mut ours = side(20, "notes", "todo", 1_000);
+        let mut theirs = ours.fork();
+        theirs.transact(|tx| tx.put(automerge::ROOT, "temperature", 25)).unwrap();
+        let resolved = merge_with_strategy(&mut ours, &mut theirs, MergeStrategy::Ours).unwrap();
+        assert!(resolved.is_empty());
+        assert_eq!(read(&ours).0, 25);
+    }
+
+    /// A document whose temperature was set concurrently: to 35 at time 2000 and to 5 at time 3000
+    fn conflicted_temperature() -> Automerge {
+        let set_temperature = |doc: &mut Automerge, temperature: i64, time: i64| {
+            doc.transact_with::<_, _, AutomergeError, _>(
+                |_| CommitOptions::default().with_time(time),
+                |tx| tx.put(automerge::ROOT, "temperature", temperature),
+            )
+            .unwrap();
+        };
+        let mut doc = side(20, "notes", "todo", 1_000);
+        let mut peer = doc.fork();
+        set_temperature(&mut doc, 35, 2_000);
+        set_temperature(&mut peer, 5, 3_000);
+        doc.merge(&mut peer).unwrap();
+        doc
+    }
+
+    #[test]
+    fn resolving_by_timestamp_keeps_the_later_write() {
+        let mut doc = conflicted_temperature();
+        let conflicts = find_conflicts(&doc).unwrap();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].path, "temperature");
+
+        let resolved = resolve_conflicts(&mut doc, ConflictPolicy::LastWriteByTimestamp).unwrap();
+        assert_eq!(resolved.len(), 1);
+        assert!(find_conflicts(&doc).unwrap().is_empty());
+        assert_eq!(read(&doc).0, 5);
+
+        let mut doc = conflicted_temperature();
+        resolve_conflicts(&mut doc, ConflictPolicy::Largest).unwrap();
+        assert_eq!(read(&doc).0, 35);
+    }
+}
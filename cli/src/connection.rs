@@ -0,0 +1,348 @@
+//! WebSocket bridge between a samod repo and the sync server, shared by the binaries.
+
+use crate::{AutodashError, Result};
+use futures_util::{SinkExt, StreamExt};
+use std::convert::Infallible;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::{self, client::IntoClientRequest, http, Message};
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+pub const DEFAULT_SYNC_SERVER_URL: &str = "ws://localhost:3030";
+pub const DEFAULT_STORAGE_DIR: &str = "./autodash-data/";
+
+/// Handshake header carrying `ConnectOptions::peer_name`, for server logs and presence
+pub const PEER_NAME_HEADER: &str = "x-autodash-peer-name";
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Connection lifecycle notifications
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnEvent {
+    Connected,
+    Disconnected,
+    Reconnecting,
+    /// The server reported an error in a JSON control frame
+    ServerError(String),
+}
+
+/// A JSON control frame from the server, as opposed to samod sync bytes.
+///
+/// Sync messages are CBOR maps, whose first byte is never `{`, so any frame
+/// that starts with `{` and parses as a JSON object is treated as control.
+#[derive(Debug, Clone, PartialEq)]
+enum ControlFrame {
+    Error(String),
+    Other(serde_json::Value),
+}
+
+fn parse_control_frame(bytes: &[u8]) -> Option<ControlFrame> {
+    if bytes.first() != Some(&b'{') {
+        return None;
+    }
+    let value: serde_json::Value = serde_json::from_slice(bytes).ok()?;
+    let object = value.as_object()?;
+
+    let is_error = object.get("type").and_then(|t| t.as_str()) == Some("error")
+        || object.contains_key("error");
+    if is_error {
+        let message = ["message", "error"]
+            .iter()
+            .find_map(|key| object.get(*key).and_then(|m| m.as_str()))
+            .map(str::to_string)
+            .unwrap_or_else(|| value.to_string());
+        Some(ControlFrame::Error(message))
+    } else {
+        Some(ControlFrame::Other(value))
+    }
+}
+
+/// Surface a control frame to the user instead of handing it to samod
+fn handle_control_frame(frame: ControlFrame, events: &broadcast::Sender<ConnEvent>) {
+    match frame {
+        ControlFrame::Error(message) => {
+            tracing::error!("Sync server error: {}", message);
+            let _ = events.send(ConnEvent::ServerError(message));
+        }
+        ControlFrame::Other(value) => {
+            tracing::warn!("Ignoring unsupported control message from sync server: {}", value);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConnectOptions {
+    pub server_url: String,
+    /// Reconnect (with backoff) when the server closes the connection
+    pub reconnect: bool,
+    /// Sent as `Authorization: Bearer <token>` in the WebSocket handshake
+    pub auth_token: Option<String>,
+    /// A friendly name for this client, sent in the handshake as [`PEER_NAME_HEADER`]
+    pub peer_name: Option<String>,
+}
+
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        Self {
+            server_url: DEFAULT_SYNC_SERVER_URL.to_string(),
+            reconnect: true,
+            auth_token: None,
+            peer_name: None,
+        }
+    }
+}
+
+/// Open a WebSocket to the sync server, presenting the auth token if there is one
+async fn open(options: &ConnectOptions) -> Result<WsStream> {
+    let connect_error = |e: tungstenite::Error| AutodashError::Connect {
+        url: options.server_url.clone(),
+        source: Box::new(e),
+    };
+
+    let mut request = options
+        .server_url
+        .as_str()
+        .into_client_request()
+        .map_err(connect_error)?;
+    if let Some(token) = &options.auth_token {
+        let value = http::HeaderValue::from_str(&format!("Bearer {}", token))
+            .map_err(|e| connect_error(tungstenite::Error::HttpFormat(e.into())))?;
+        request.headers_mut().insert(http::header::AUTHORIZATION, value);
+    }
+    if let Some(name) = &options.peer_name {
+        let value = http::HeaderValue::from_str(name)
+            .map_err(|e| connect_error(tungstenite::Error::HttpFormat(e.into())))?;
+        request.headers_mut().insert(PEER_NAME_HEADER, value);
+    }
+
+    match connect_async(request).await {
+        Ok((ws, _)) => Ok(ws),
+        Err(tungstenite::Error::Http(response))
+            if matches!(
+                response.status(),
+                http::StatusCode::UNAUTHORIZED | http::StatusCode::FORBIDDEN
+            ) =>
+        {
+            let url = options.server_url.clone();
+            Err(match options.auth_token {
+                Some(_) => AutodashError::AuthRejected { url },
+                None => AutodashError::AuthRequired { url },
+            })
+        }
+        Err(e) => Err(connect_error(e)),
+    }
+}
+
+/// A live connection between a repo and the sync server
+pub struct Connection {
+    events: broadcast::Sender<ConnEvent>,
+    task: JoinHandle<()>,
+}
+
+impl Connection {
+    /// Subscribe to lifecycle events. The initial connection has already been
+    /// made when `connect_repo` returns, so the first event is a disconnect.
+    pub fn subscribe(&self) -> broadcast::Receiver<ConnEvent> {
+        self.events.subscribe()
+    }
+
+    /// Tear down the connection and stop reconnecting
+    pub fn close(&self) {
+        self.task.abort();
+    }
+}
+
+/// Open a connection and drop it straight away, presenting the same handshake
+/// headers as [`connect_repo`]
+pub async fn probe_server(options: &ConnectOptions) -> Result<()> {
+    open(options).await.map(drop)
+}
+
+/// Connect `repo` to the sync server, reconnecting in the background if enabled.
+///
+/// Fails if the first connection attempt fails.
+pub async fn connect_repo(repo: &samod::Repo, options: ConnectOptions) -> Result<Connection> {
+    tracing::debug!("Connecting to sync server at {}", options.server_url);
+    let ws = open(&options).await?;
+    tracing::debug!("WebSocket connected");
+
+    let (events, _) = broadcast::channel(16);
+    let events_tx = events.clone();
+    let repo = repo.clone();
+    let task = tokio::spawn(async move {
+        let mut ws = Some(ws);
+        let mut delay = RECONNECT_DELAY;
+        loop {
+            if let Some(ws) = ws.take() {
+                run_connection(repo.clone(), ws, events_tx.clone()).await;
+                let _ = events_tx.send(ConnEvent::Disconnected);
+            }
+            if !options.reconnect {
+                break;
+            }
+
+            let _ = events_tx.send(ConnEvent::Reconnecting);
+            tokio::time::sleep(delay).await;
+            match open(&options).await {
+                Ok(stream) => {
+                    tracing::debug!("WebSocket reconnected");
+                    ws = Some(stream);
+                    delay = RECONNECT_DELAY;
+                    let _ = events_tx.send(ConnEvent::Connected);
+                }
+                Err(e) => {
+                    tracing::debug!("Reconnect failed: {}", e);
+                    delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+                }
+            }
+        }
+    });
+
+    Ok(Connection { events, task })
+}
+
+/// Bridge one WebSocket to the repo until either side closes it.
+async fn run_connection(repo: samod::Repo, ws: WsStream, events: broadcast::Sender<ConnEvent>) {
+    let (ws_sink, ws_stream) = ws.split();
+
+    // Create channels to bridge WebSocket and samod
+    let (to_samod_tx, to_samod_rx) = futures::channel::mpsc::unbounded::<Vec<u8>>();
+    let (from_samod_tx, from_samod_rx) = futures::channel::mpsc::unbounded::<Vec<u8>>();
+
+    // Forward WebSocket messages to samod
+    let ws_to_samod = tokio::spawn(async move {
+        let mut stream = ws_stream;
+        while let Some(msg) = stream.next().await {
+            match msg {
+                Ok(Message::Binary(data)) => {
+                    // JSON control frames would corrupt the sync stream; keep them out of samod
+                    if let Some(frame) = parse_control_frame(&data) {
+                        handle_control_frame(frame, &events);
+                        continue;
+                    }
+                    if to_samod_tx.unbounded_send(data).is_err() {
+                        break;
+                    }
+                }
+                Ok(Message::Text(text)) => match parse_control_frame(text.as_bytes()) {
+                    Some(frame) => handle_control_frame(frame, &events),
+                    None => tracing::warn!("Ignoring text frame from sync server: {}", text),
+                },
+                Ok(Message::Close(_)) => break,
+                Ok(_) => {} // Ignore ping/pong
+                Err(e) => {
+                    tracing::warn!("WebSocket error: {}", e);
+                    break;
+                }
+            }
+        }
+    });
+
+    // Forward samod messages to WebSocket
+    let samod_to_ws = tokio::spawn(async move {
+        let mut rx = from_samod_rx;
+        let mut sink = ws_sink;
+        while let Some(bytes) = rx.next().await {
+            if sink.send(Message::Binary(bytes)).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    // Runs until the WebSocket side hangs up and the inbound stream ends
+    let result = repo
+        .connect(
+            to_samod_rx.map(Ok::<_, Infallible>),
+            from_samod_tx,
+            samod::ConnDirection::Outgoing,
+        )
+        .await;
+    tracing::debug!("Sync connection finished: {:?}", result);
+
+    ws_to_samod.abort();
+    samod_to_ws.abort();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_error_frames_are_surfaced_as_server_errors() {
+        let frame = parse_control_frame(br#"{"type":"error","message":"document not found"}"#);
+        assert_eq!(frame, Some(ControlFrame::Error("document not found".to_string())));
+
+        let (events, mut received) = broadcast::channel(4);
+        handle_control_frame(frame.unwrap(), &events);
+        assert_eq!(
+            received.try_recv().unwrap(),
+            ConnEvent::ServerError("document not found".to_string())
+        );
+
+        // Anything else shaped like JSON is dropped rather than reported
+        let other = parse_control_frame(br#"{"type":"peer"}"#).unwrap();
+        assert!(matches!(other, ControlFrame::Other(_)));
+        handle_control_frame(other, &events);
+        assert!(received.try_recv().is_err());
+    }
+
+    #[test]
+    fn sync_bytes_are_not_control_frames() {
+        // A CBOR map header, as every samod sync message starts with
+        assert_eq!(parse_control_frame(&[0xa3, 0x64, b't', b'y', b'p', b'e']), None);
+        assert_eq!(parse_control_frame(b"{not json"), None);
+        assert_eq!(parse_control_frame(b""), None);
+    }
+
+    /// A local sync server that only completes the handshake for `Bearer <token>`
+    async fn authed_server(token: &'static str) -> String {
+        use tungstenite::handshake::server::{Request, Response};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let check = |request: &Request, response: Response| {
+                    let presented = request
+                        .headers()
+                        .get(http::header::AUTHORIZATION)
+                        .and_then(|value| value.to_str().ok());
+                    if presented == Some(format!("Bearer {}", token).as_str()) {
+                        Ok(response)
+                    } else {
+                        let mut rejection = http::Response::<Option<String>>::new(None);
+                        *rejection.status_mut() = http::StatusCode::UNAUTHORIZED;
+                        Err(rejection)
+                    }
+                };
+                let _ = tokio_tungstenite::accept_hdr_async(stream, check).await;
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn an_authed_server_admits_only_clients_with_its_token() {
+        let server_url = authed_server("secret").await;
+        let client = |auth_token: Option<&str>| ConnectOptions {
+            server_url: server_url.clone(),
+            auth_token: auth_token.map(str::to_string),
+            ..Default::default()
+        };
+
+        probe_server(&client(Some("secret"))).await.unwrap();
+        assert!(matches!(
+            probe_server(&client(None)).await,
+            Err(AutodashError::AuthRequired { .. })
+        ));
+        assert!(matches!(
+            probe_server(&client(Some("guess"))).await,
+            Err(AutodashError::AuthRejected { .. })
+        ));
+    }
+}
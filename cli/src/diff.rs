@@ -0,0 +1,104 @@
+//! Field-by-field comparison of two document states.
+
+use crate::{truncate_preview, Doc, Field};
+
+/// Characters of notes/code shown on each side of a change
+const DIFF_PREVIEW_LEN: usize = 40;
+
+/// A top-level field whose value differs between two states
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldChange {
+    pub field: Field,
+    pub before: String,
+    pub after: String,
+}
+
+/// A short, single-line rendering of `field` for showing before/after
+fn summarize(field: Field, doc: &Doc) -> String {
+    let text_preview = |text: &str| {
+        format!(
+            "{:?} ({} chars)",
+            truncate_preview(&text.replace('\n', " "), DIFF_PREVIEW_LEN),
+            text.chars().count()
+        )
+    };
+    match field {
+        Field::Counter => doc.counter.to_string(),
+        Field::Temperature => format!("{}°C", doc.temperature),
+        Field::DarkMode => (if doc.darkMode { "on" } else { "off" }).to_string(),
+        Field::Notes => text_preview(doc.notes.as_str()),
+        Field::Sections => format!("[{}]", doc.section_names().join(", ")),
+        Field::Code => text_preview(doc.code.as_str()),
+        Field::Tags => format!("[{}]", doc.tags.join(", ")),
+        Field::Todos => {
+            let done = doc.todos.iter().filter(|t| t.completed).count();
+            format!("{} todo(s), {} done", doc.todos.len(), done)
+        }
+        Field::Settings => {
+            let mut entries: Vec<_> = doc.settings.iter().collect();
+            entries.sort();
+            let entries: Vec<String> = entries.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+            format!("{{{}}}", entries.join(", "))
+        }
+        Field::Attachments => format!("{} attachment(s)", doc.attachments.len()),
+        Field::Metadata => format!(
+            "title {:?}, lastModified {}",
+            doc.metadata.title.as_ref().map(|t| t.as_str()).unwrap_or(""),
+            doc.metadata
+                .lastModified
+                .map(|ms| ms.to_string())
+                .unwrap_or_else(|| "-".to_string())
+        ),
+    }
+}
+
+/// Every top-level field that changed from `before` to `after`, in schema order
+pub fn diff_docs(before: &Doc, after: &Doc) -> Vec<FieldChange> {
+    <Field as clap::ValueEnum>::value_variants()
+        .iter()
+        .filter(|field| field.snapshot(before) != field.snapshot(after))
+        .map(|&field| FieldChange {
+            field,
+            before: summarize(field, before),
+            after: summarize(field, after),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use automerge::Automerge;
+
+    #[test]
+    fn diffing_against_an_earlier_save_shows_the_mutated_fields() {
+        let mut live = Automerge::new();
+        crate::ensure_schema(&mut live).unwrap();
+        let saved = live.save();
+
+        let mut state: Doc = autosurgeon::hydrate(&live).unwrap();
+        state.temperature = 30;
+        state.tags.push("hot".to_string());
+        live.transact(|tx| autosurgeon::reconcile(tx, &state)).unwrap();
+
+        let earlier: Doc = autosurgeon::hydrate(&Automerge::load(&saved).unwrap()).unwrap();
+        let current: Doc = autosurgeon::hydrate(&live).unwrap();
+        let changes = diff_docs(&earlier, &current);
+        assert_eq!(
+            changes,
+            vec![
+                FieldChange {
+                    field: Field::Temperature,
+                    before: format!("{}°C", earlier.temperature),
+                    after: "30°C".to_string(),
+                },
+                FieldChange {
+                    field: Field::Tags,
+                    before: "[]".to_string(),
+                    after: "[hot]".to_string(),
+                },
+            ]
+        );
+        assert!(diff_docs(&current, &current).is_empty());
+    }
+}
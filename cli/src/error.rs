@@ -0,0 +1,69 @@
+//! Error type for the `automerge_cli` library, so embedders can match on failure kinds.
+
+/// Everything that can go wrong in the library's public API
+#[derive(Debug, thiserror::Error)]
+pub enum AutodashError {
+    #[error("Failed to hydrate document: {0}")]
+    Hydrate(#[from] autosurgeon::HydrateError),
+
+    #[error("Failed to reconcile document: {0}")]
+    Reconcile(#[from] autosurgeon::ReconcileError),
+
+    #[error("Automerge error: {0}")]
+    Automerge(#[from] automerge::AutomergeError),
+
+    #[error("Failed to connect to WebSocket server at {url}: {source}")]
+    Connect {
+        url: String,
+        #[source]
+        source: Box<tokio_tungstenite::tungstenite::Error>,
+    },
+
+    #[error("The sync server at {url} requires authentication; pass --auth-token or set $AUTODASH_AUTH_TOKEN")]
+    AuthRequired { url: String },
+
+    #[error("The sync server at {url} rejected the auth token")]
+    AuthRejected { url: String },
+
+    #[error("Document not found: {0}")]
+    DocumentNotFound(String),
+
+    #[error("Timed out waiting for the document to sync")]
+    SyncTimeout,
+
+    #[error("{path} expects {expected}, got '{value}'")]
+    InvalidValue {
+        path: String,
+        expected: &'static str,
+        value: String,
+    },
+
+    #[error("Unknown or unsettable field '{0}'. Settable fields: counter, temperature, darkMode, notes, code, metadata.title, metadata.createdAt, metadata.lastModified")]
+    UnknownField(String),
+
+    #[error("{path} contains control characters ({found}); remove them or pass --strict-utf8=strip")]
+    ControlCharacters { path: String, found: String },
+
+    #[error("Field '{0}' is listed more than once")]
+    DuplicateField(String),
+
+    #[error("Invalid config file {}: {message}", path.display())]
+    Config {
+        path: std::path::PathBuf,
+        message: String,
+    },
+
+    #[error("Schema check failed: {path} {problem}")]
+    Schema { path: String, problem: String },
+
+    #[error("Invalid change hash '{0}'")]
+    InvalidChangeHash(String),
+
+    #[error("No choice given for {0}")]
+    NoChoice(String),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+pub type Result<T, E = AutodashError> = std::result::Result<T, E>;
@@ -1,12 +1,116 @@
 #![allow(non_snake_case)]
 
 use autosurgeon::{Hydrate, Reconcile};
+use std::collections::HashMap;
+use unicode_width::UnicodeWidthStr;
 
-#[derive(Debug, Clone, Reconcile, Hydrate)]
+pub mod autodash;
+pub mod config;
+pub mod conflicts;
+pub mod connection;
+pub mod diff;
+pub mod error;
+pub mod migrate;
+pub mod overrides;
+pub mod sanitize;
+pub mod schema;
+pub mod search;
+pub mod storage;
+pub mod terminal;
+pub mod todos;
+
+pub use autodash::{
+    change_notifications, reconcile_diff, reconcile_doc, with_hydrated, with_hydrated_unstamped,
+    Autodash, Field, TextField,
+};
+pub use config::Config;
+pub use conflicts::{
+    find_conflicts, merge_with_strategy, resolve_conflicts, ConflictPolicy, MergeStrategy,
+};
+pub use connection::{
+    connect_repo, probe_server, ConnEvent, ConnectOptions, Connection, DEFAULT_STORAGE_DIR,
+    DEFAULT_SYNC_SERVER_URL, PEER_NAME_HEADER,
+};
+pub use diff::{diff_docs, FieldChange};
+pub use error::{AutodashError, Result};
+pub use migrate::{migrate, schema_version, Migration, CURRENT_SCHEMA_VERSION};
+pub use overrides::{hydrate_with_overrides, reconcile_with_overrides, StoredAs, TypeOverrides};
+pub use sanitize::{describe_chars, screen_text, ControlChars};
+pub use schema::{check_schema, ensure_schema, hydrate_strict, missing_fields};
+pub use search::{search_doc, Match, SearchOptions};
+pub use storage::{list_stored_documents, StoredDocument};
+pub use terminal::{restore_terminal, setup_terminal, Tui};
+pub use todos::{find_todo, new_id, parse_checklist, render_checklist};
+
+#[derive(Debug, Clone, Reconcile, Hydrate, serde::Serialize, serde::Deserialize)]
 pub struct TodoItem {
+    #[serde(
+        serialize_with = "serialize_text",
+        deserialize_with = "deserialize_text",
+        default = "new_text_id"
+    )]
     pub id: autosurgeon::Text,
+    #[serde(serialize_with = "serialize_text", deserialize_with = "deserialize_text")]
     pub text: autosurgeon::Text,
+    #[serde(default)]
     pub completed: bool,
+    /// Labels for this todo alone, separate from the document's `tags`
+    #[autosurgeon(hydrate = "hydrate_string_list")]
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+fn new_text_id() -> autosurgeon::Text {
+    autosurgeon::Text::with_value(new_id())
+}
+
+/// Serialize a Text field as its plain string, for `export-json` and `import-json`
+fn serialize_text<S: serde::Serializer>(
+    text: &autosurgeon::Text,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(text.as_str())
+}
+
+fn serialize_optional_text<S: serde::Serializer>(
+    text: &Option<autosurgeon::Text>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match text {
+        Some(text) => serializer.serialize_some(text.as_str()),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn serialize_text_map<S: serde::Serializer>(
+    map: &HashMap<String, autosurgeon::Text>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(map.iter().map(|(name, text)| (name, text.as_str())))
+}
+
+fn deserialize_text<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<autosurgeon::Text, D::Error> {
+    let value = <String as serde::Deserialize>::deserialize(deserializer)?;
+    Ok(autosurgeon::Text::with_value(value))
+}
+
+fn deserialize_text_map<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<HashMap<String, autosurgeon::Text>, D::Error> {
+    let map = <HashMap<String, String> as serde::Deserialize>::deserialize(deserializer)?;
+    Ok(map
+        .into_iter()
+        .map(|(name, text)| (name, autosurgeon::Text::with_value(text)))
+        .collect())
+}
+
+fn deserialize_optional_text<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<autosurgeon::Text>, D::Error> {
+    let value = <Option<String> as serde::Deserialize>::deserialize(deserializer)?;
+    Ok(value.map(autosurgeon::Text::with_value))
 }
 
 pub fn hydrate_optional_timestamp<D: autosurgeon::ReadDoc>(
@@ -27,91 +131,625 @@ pub fn hydrate_optional_timestamp<D: autosurgeon::ReadDoc>(
         _ => Ok(None),
     }
 }
+/// Hydrate the counter, accepting both Automerge Counters and the plain
+/// integers older documents stored, and treating a missing value as 0.
+pub fn hydrate_counter<D: autosurgeon::ReadDoc>(
+    doc: &D,
+    obj: &automerge::ObjId,
+    prop: autosurgeon::Prop,
+) -> Result<i64, autosurgeon::HydrateError> {
+    use automerge::{ScalarValue, Value};
+    match doc.get(obj, &prop)? {
+        Some((Value::Scalar(s), _)) => match &*s {
+            ScalarValue::Counter(_) | ScalarValue::Int(_) | ScalarValue::Uint(_) => {
+                Ok(s.to_i64().unwrap_or_default())
+            }
+            other => Err(autosurgeon::HydrateError::unexpected("a counter", other.to_string())),
+        },
+        Some((value, _)) => Err(autosurgeon::HydrateError::unexpected("a counter", value.to_string())),
+        None => Ok(0),
+    }
+}
+
+/// The counter is left alone by `reconcile`: putting the hydrated value back
+/// would replace the Counter and drop increments made concurrently elsewhere.
+/// Write it with [`write_counter`] instead.
+fn reconcile_counter<R: autosurgeon::Reconciler>(_value: &i64, _reconciler: R) -> Result<(), R::Error> {
+    Ok(())
+}
+
+/// Move the counter from `current` to `desired` as a CRDT operation.
+///
+/// An existing Counter is incremented by the difference, so concurrent changes
+/// from other peers add up. A missing counter, or a plain integer written by an
+/// older client, is replaced with a Counter holding `desired`.
+pub fn write_counter<T: automerge::transaction::Transactable>(
+    tx: &mut T,
+    current: i64,
+    desired: i64,
+) -> Result<(), automerge::AutomergeError> {
+    use automerge::{ScalarValue, Value};
+    match tx.get(automerge::ROOT, "counter")? {
+        Some((Value::Scalar(s), _)) if matches!(&*s, ScalarValue::Counter(_)) => {
+            if desired != current {
+                tx.increment(automerge::ROOT, "counter", desired - current)?;
+            }
+        }
+        _ => tx.put(automerge::ROOT, "counter", ScalarValue::counter(desired))?,
+    }
+    Ok(())
+}
+
+/// Hydrate a list of strings, accepting both scalar strings and Text objects
+/// and treating a missing or non-list value as empty.
+pub fn hydrate_string_list<D: autosurgeon::ReadDoc>(
+    doc: &D,
+    obj: &automerge::ObjId,
+    prop: autosurgeon::Prop,
+) -> Result<Vec<String>, autosurgeon::HydrateError> {
+    use automerge::{ObjType, Value};
+    match doc.get(obj, &prop)? {
+        Some((Value::Object(ObjType::List), list)) => {
+            let mut items = Vec::new();
+            for index in 0..doc.length(&list) {
+                match doc.get(&list, index)? {
+                    Some((Value::Scalar(s), _)) => {
+                        if let Some(s) = s.to_str() {
+                            items.push(s.to_string());
+                        }
+                    }
+                    Some((Value::Object(ObjType::Text), text)) => items.push(doc.text(&text)?),
+                    _ => {}
+                }
+            }
+            Ok(items)
+        }
+        _ => Ok(Vec::new()),
+    }
+}
 
+/// Hydrate a map of string values, accepting both scalar strings and Text
+/// objects and treating a missing or non-map value as empty.
+pub fn hydrate_string_map<D: autosurgeon::ReadDoc>(
+    doc: &D,
+    obj: &automerge::ObjId,
+    prop: autosurgeon::Prop,
+) -> Result<HashMap<String, String>, autosurgeon::HydrateError> {
+    use automerge::{ObjType, Value};
+    match doc.get(obj, &prop)? {
+        Some((Value::Object(ObjType::Map), map)) => {
+            let mut entries = HashMap::new();
+            for key in doc.keys(&map) {
+                let value = match doc.get(&map, key.as_str())? {
+                    Some((Value::Scalar(s), _)) => s.to_str().map(|s| s.to_string()),
+                    Some((Value::Object(ObjType::Text), text)) => Some(doc.text(&text)?),
+                    _ => None,
+                };
+                if let Some(value) = value {
+                    entries.insert(key, value);
+                }
+            }
+            Ok(entries)
+        }
+        _ => Ok(HashMap::new()),
+    }
+}
 
-#[derive(Debug, Clone, Reconcile, Hydrate)]
+/// Hydrate a map of Text objects, accepting scalar strings too and treating a
+/// missing or non-map value as empty.
+pub fn hydrate_text_map<D: autosurgeon::ReadDoc>(
+    doc: &D,
+    obj: &automerge::ObjId,
+    prop: autosurgeon::Prop,
+) -> Result<HashMap<String, autosurgeon::Text>, autosurgeon::HydrateError> {
+    use automerge::{ObjType, Value};
+    match doc.get(obj, &prop)? {
+        Some((Value::Object(ObjType::Map), map)) => {
+            let mut entries = HashMap::new();
+            for key in doc.keys(&map) {
+                match doc.get(&map, key.as_str())? {
+                    Some((Value::Object(ObjType::Text), _)) => {
+                        let text = autosurgeon::hydrate_prop(doc, &map, key.as_str())?;
+                        entries.insert(key, text);
+                    }
+                    Some((Value::Scalar(s), _)) => {
+                        if let Some(s) = s.to_str() {
+                            entries.insert(key, autosurgeon::Text::with_value(s));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Ok(entries)
+        }
+        _ => Ok(HashMap::new()),
+    }
+}
+
+/// A reference to a file stored outside the document
+#[derive(Debug, Clone, PartialEq, Reconcile, Hydrate, serde::Serialize, serde::Deserialize)]
+pub struct Attachment {
+    pub id: String,
+    pub mime: String,
+    pub url: String,
+    /// The todo this attachment belongs to
+    pub todoId: Option<String>,
+}
+
+/// Hydrate any defaultable value, treating a missing field as its default.
+pub fn hydrate_or_default<D: autosurgeon::ReadDoc, T: autosurgeon::Hydrate + Default>(
+    doc: &D,
+    obj: &automerge::ObjId,
+    prop: autosurgeon::Prop,
+) -> Result<T, autosurgeon::HydrateError> {
+    match doc.get(obj, &prop)? {
+        Some(_) => autosurgeon::hydrate_prop(doc, obj, prop),
+        None => Ok(T::default()),
+    }
+}
+
+#[derive(Debug, Clone, Default, Reconcile, Hydrate, serde::Serialize, serde::Deserialize)]
 pub struct Metadata {
     #[autosurgeon(hydrate = "hydrate_optional_timestamp")]
     pub createdAt: Option<i64>,
     #[autosurgeon(hydrate = "hydrate_optional_timestamp")]
     pub lastModified: Option<i64>,
+    #[serde(
+        serialize_with = "serialize_optional_text",
+        deserialize_with = "deserialize_optional_text",
+        default
+    )]
     pub title: Option<autosurgeon::Text>,
+    /// Idempotency keys of counter commands that have already been applied
+    #[autosurgeon(hydrate = "hydrate_string_list")]
+    pub appliedKeys: Vec<String>,
+    /// Advisory locks: field name -> name of whoever holds it
+    #[autosurgeon(hydrate = "hydrate_string_map")]
+    pub locks: HashMap<String, String>,
 }
 
-#[derive(Debug, Clone, Reconcile, Hydrate)]
+#[derive(Debug, Clone, Reconcile, Hydrate, serde::Serialize, serde::Deserialize)]
 pub struct Doc {
+    /// An Automerge Counter, so concurrent increments add up; see [`write_counter`]
+    #[autosurgeon(hydrate = "hydrate_counter", reconcile = "reconcile_counter")]
     pub counter: i64,
     pub temperature: i64,
     pub darkMode: bool,
+    #[serde(serialize_with = "serialize_text", deserialize_with = "deserialize_text")]
     pub notes: autosurgeon::Text,
+    /// Named notes sections, each its own Text so they're edited independently
+    #[autosurgeon(hydrate = "hydrate_text_map")]
+    #[serde(
+        serialize_with = "serialize_text_map",
+        deserialize_with = "deserialize_text_map",
+        default
+    )]
+    pub sections: HashMap<String, autosurgeon::Text>,
+    #[serde(serialize_with = "serialize_text", deserialize_with = "deserialize_text")]
     pub code: autosurgeon::Text,
     pub tags: Vec<String>,
     pub todos: Vec<TodoItem>,
     pub metadata: Metadata,
+    /// Open-ended key/value settings, stored as an Automerge map
+    #[autosurgeon(hydrate = "hydrate_string_map")]
+    pub settings: HashMap<String, String>,
+    /// Files attached to todos; the bytes live outside the document
+    #[autosurgeon(hydrate = "hydrate_or_default")]
+    pub attachments: Vec<Attachment>,
+    /// Which migrations have run; see [`migrate`]. Missing means version 1
+    pub schemaVersion: Option<i64>,
+}
+
+/// Which fields the `show` dashboard renders, and in what order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderOptions {
+    pub fields: Vec<Field>,
+    /// Longest notes/title preview, in characters, before it's cut with `...`
+    pub preview_len: usize,
+    /// Plain ASCII layout with text labels, for terminals without emoji or box drawing
+    pub ascii: bool,
+    /// Show timestamps as "3 minutes ago" instead of RFC 3339
+    pub relative_times: bool,
+    /// Show temperatures in °F; the document always stores °C
+    pub fahrenheit: bool,
+    /// Most todos listed before an "(… and N more)" footer; `None` lists them all
+    pub todo_limit: Option<usize>,
+    /// Todos skipped from the start of the list
+    pub todo_offset: usize,
+    /// Only list todos whose `completed` matches; `None` lists them all
+    pub todo_status: Option<bool>,
+    /// Total box width in columns, borders included. Previews are cut to fit;
+    /// `None` sizes the box from `preview_len` instead
+    pub width: Option<usize>,
+}
+
+pub const DEFAULT_PREVIEW_LEN: usize = 30;
+pub const DEFAULT_TODO_LIMIT: usize = 20;
+/// Narrowest box `width` is honoured down to; every row's label still fits
+pub const MIN_RENDER_WIDTH: usize = 32;
+/// Columns between the borders of the default-sized box
+const DEFAULT_INNER_WIDTH: usize = 41;
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            fields: vec![
+                Field::Counter,
+                Field::Temperature,
+                Field::DarkMode,
+                Field::Notes,
+                Field::Code,
+                Field::Todos,
+                Field::Tags,
+                Field::Metadata,
+            ],
+            preview_len: DEFAULT_PREVIEW_LEN,
+            ascii: !locale_supports_unicode(),
+            relative_times: false,
+            fahrenheit: false,
+            todo_limit: Some(DEFAULT_TODO_LIMIT),
+            todo_offset: 0,
+            todo_status: None,
+            width: None,
+        }
+    }
+}
+
+impl RenderOptions {
+    /// Columns between the dashboard box's borders
+    fn inner_width(&self) -> usize {
+        match self.width {
+            Some(width) => width.max(MIN_RENDER_WIDTH) - 2,
+            // Widen the box for previews longer than the default so it stays closed
+            None => DEFAULT_INNER_WIDTH + self.preview_len.saturating_sub(DEFAULT_PREVIEW_LEN),
+        }
+    }
+
+    /// The preview length to use where `room` columns are free
+    fn preview_within(&self, room: usize) -> usize {
+        match self.width {
+            Some(_) => self.preview_len.min(room),
+            None => self.preview_len,
+        }
+    }
+}
+
+/// Whether the terminal locale looks like it can show emoji and box drawing.
+///
+/// Follows the usual precedence (`LC_ALL`, then `LC_CTYPE`, then `LANG`); an
+/// unset locale is assumed to be fine, a `dumb` terminal never is.
+pub fn locale_supports_unicode() -> bool {
+    if std::env::var("TERM").is_ok_and(|term| term == "dumb") {
+        return false;
+    }
+    let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.is_empty());
+    match locale {
+        Some(locale) => {
+            let locale = locale.to_lowercase();
+            locale.contains("utf-8") || locale.contains("utf8")
+        }
+        None => true,
+    }
+}
+
+/// Shorten `text` to at most `max` characters, ending in `...` when cut.
+/// Counts characters rather than bytes so multi-byte text is never split.
+pub fn truncate_preview(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    let kept: String = text.chars().take(max.saturating_sub(3)).collect();
+    format!("{}...", kept)
+}
+
+/// Pad `text` with spaces until it fills `width` terminal columns.
+///
+/// Measures display width rather than chars, so emoji and wide characters
+/// keep the box's right border in line; text already wider is left as is.
+fn pad_to_width(text: &str, width: usize) -> String {
+    let fill = width.saturating_sub(UnicodeWidthStr::width(text));
+    format!("{}{}", text, " ".repeat(fill))
+}
+
+/// How long ago a millisecond timestamp was, e.g. `just now`, `3 minutes ago` or `2 days ago`
+pub fn humanize_since(millis: i64) -> String {
+    let elapsed = chrono::Utc::now().timestamp_millis() - millis;
+    if elapsed < 0 {
+        return "in the future".to_string();
+    }
+
+    let seconds = elapsed / 1000;
+    let (amount, unit) = match seconds {
+        0..=59 => return "just now".to_string(),
+        60..=3599 => (seconds / 60, "minute"),
+        3600..=86399 => (seconds / 3600, "hour"),
+        _ => (seconds / 86400, "day"),
+    };
+    format!("{} {}{} ago", amount, unit, if amount == 1 { "" } else { "s" })
+}
+
+/// Convert a stored Celsius temperature to whole degrees Fahrenheit
+pub fn celsius_to_fahrenheit(celsius: i64) -> i64 {
+    (celsius as f64 * 9.0 / 5.0 + 32.0).round() as i64
+}
+
+/// Convert a Fahrenheit reading to the whole degrees Celsius the document stores
+pub fn fahrenheit_to_celsius(fahrenheit: i64) -> i64 {
+    ((fahrenheit as f64 - 32.0) * 5.0 / 9.0).round() as i64
+}
+
+/// Parse a field order such as `counter,notes,todos`. Listing a field twice is an error.
+pub fn parse_field_order<'a>(names: impl IntoIterator<Item = &'a str>) -> Result<Vec<Field>> {
+    let mut fields = Vec::new();
+    for name in names {
+        let name = name.trim();
+        let field = <Field as clap::ValueEnum>::from_str(name, true).map_err(|_| {
+            AutodashError::InvalidValue {
+                path: "field order".to_string(),
+                expected: "a field name",
+                value: name.to_string(),
+            }
+        })?;
+        if fields.contains(&field) {
+            return Err(AutodashError::DuplicateField(name.to_string()));
+        }
+        fields.push(field);
+    }
+    Ok(fields)
 }
 
 impl Doc {
     pub fn display(&self) {
-        println!("\n📊 Autodash State:");
-        println!("╭─────────────────────────────────────────╮");
-        println!("│ 🔢 Counter: {:<28}│", self.counter);
-        println!("│ 🌡️  Temperature: {}°C{:<22}│", self.temperature, "");
-        println!(
-            "│ 🌙 Dark Mode: {:<26}│",
-            if self.darkMode { "ON" } else { "OFF" }
-        );
-        if self.notes.as_str().is_empty() {
-            println!("│ 📝 Notes: (empty){:<22}│", "");
-        } else {
-            let notes_str = self.notes.as_str();
-            let notes_preview = if notes_str.len() > 30 {
-                format!("{}...", &notes_str[..27])
-            } else {
-                notes_str.to_string()
-            };
-            println!("│ 📝 Notes: {:<28}│", notes_preview);
+        self.display_with(&RenderOptions::default());
+    }
+
+    pub fn display_with(&self, options: &RenderOptions) {
+        print!("{}", self.render(options));
+    }
+
+    /// Render the dashboard box followed by any list details, one field at a time
+    pub fn render(&self, options: &RenderOptions) -> String {
+        let inner = options.inner_width();
+        if options.ascii {
+            return self.render_ascii(options, inner);
         }
-        if self.code.as_str().is_empty() {
-            println!("│ 💻 Code: (empty){:<23}│", "");
-        } else {
-            let code_str = self.code.as_str();
-            let code_lines = code_str.lines().count();
-            let code_chars = code_str.chars().count();
-            println!("│ 💻 Code: {} lines, {} chars{:<11}│", code_lines, code_chars, "");
-        }
-        println!("│ ✓  Todos: {:<28}│", self.todos.len());
-        println!("│ 🏷️  Tags: {:<29}│", self.tags.len());
-        if let Some(title) = &self.metadata.title {
-            let title_str = title.as_str();
-            let title_preview = if title_str.len() > 30 {
-                format!("{}...", &title_str[..27])
-            } else {
-                title_str.to_string()
-            };
-            println!("│ 📄 Title: {:<28}│", title_preview);
+        let border = "─".repeat(inner);
+
+        let mut out = String::new();
+        match &self.metadata.title {
+            Some(title) if !title.as_str().is_empty() => {
+                out.push_str(&format!("\n📊 {}\n", title.as_str()))
+            }
+            _ => out.push_str("\n📊 Autodash State:\n"),
         }
-        println!("╰─────────────────────────────────────────╯");
+        out.push_str(&format!("╭{}╮\n", border));
+        for field in &options.fields {
+            if let Some(row) = self.render_row(*field, options, inner) {
+                out.push_str(&row);
+                out.push('\n');
+            }
+        }
+        out.push_str(&format!("╰{}╯\n", border));
+        for field in &options.fields {
+            if let Some(details) = self.render_details(*field, options) {
+                out.push_str(&details);
+            }
+        }
+        out
+    }
 
-        if !self.tags.is_empty() {
-            println!("\n🏷️  Tags: {}", self.tags.join(", "));
+    /// The field's line inside the dashboard box, if it has one
+    fn render_row(&self, field: Field, options: &RenderOptions, inner: usize) -> Option<String> {
+        // Leave room for the longest label before the preview
+        let preview_len = options.preview_within(inner.saturating_sub(13));
+        let content = match field {
+            Field::Counter => format!(" 🔢 Counter: {}", self.counter),
+            Field::Temperature => {
+                let (degrees, unit) = self.temperature_in(options);
+                format!(" 🌡️  Temperature: {}°{}", degrees, unit)
+            }
+            Field::DarkMode => {
+                format!(" 🌙 Dark Mode: {}", if self.darkMode { "ON" } else { "OFF" })
+            }
+            Field::Notes => {
+                if self.notes.as_str().is_empty() {
+                    " 📝 Notes: (empty)".to_string()
+                } else {
+                    let notes_preview = truncate_preview(self.notes.as_str(), preview_len);
+                    format!(" 📝 Notes: {}", notes_preview)
+                }
+            }
+            Field::Code => {
+                if self.code.as_str().is_empty() {
+                    " 💻 Code: (empty)".to_string()
+                } else {
+                    let code_str = self.code.as_str();
+                    let code_lines = code_str.lines().count();
+                    let code_chars = code_str.chars().count();
+                    format!(" 💻 Code: {} lines, {} chars", code_lines, code_chars)
+                }
+            }
+            Field::Todos => format!(" ✓  Todos: {}", self.todos.len()),
+            Field::Tags => format!(" 🏷️  Tags: {}", self.tags.len()),
+            Field::Sections => format!(" 📑 Sections: {}", self.sections.len()),
+            Field::Settings => format!(" ⚙️  Settings: {}", self.settings.len()),
+            Field::Attachments => format!(" 📎 Attachments: {}", self.attachments.len()),
+            Field::Metadata => {
+                let title = self.metadata.title.as_ref()?;
+                let title_preview = truncate_preview(title.as_str(), preview_len);
+                format!(" 📄 Title: {}", title_preview)
+            }
+        };
+        Some(format!("│{}│", pad_to_width(&content, inner)))
+    }
+
+    /// The `--ascii` layout: `+--+` borders, `|` sides and text labels
+    fn render_ascii(&self, options: &RenderOptions, inner: usize) -> String {
+        let border = format!("+{}+\n", "-".repeat(inner));
+        let mut out = match &self.metadata.title {
+            Some(title) if !title.as_str().is_empty() => format!("\n{}\n", title.as_str()),
+            _ => String::from("\nAutodash State:\n"),
+        };
+        out.push_str(&border);
+        for field in &options.fields {
+            if let Some((label, value)) = self.row_text(*field, options, inner - 2) {
+                let content = format!("{}: {}", label, value);
+                out.push_str(&format!("| {} |\n", pad_to_width(&content, inner - 2)));
+            }
         }
+        out.push_str(&border);
 
-        if !self.todos.is_empty() {
-            println!("\n✓ Todos:");
-            for todo in &self.todos {
-                let status = if todo.completed { "✓" } else { "○" };
-                println!("  {} [{}] {}", status, todo.id.as_str(), todo.text.as_str());
+        for field in &options.fields {
+            match field {
+                Field::Tags if !self.tags.is_empty() => {
+                    out.push_str(&format!("\nTags: {}\n", self.tags.join(", ")));
+                }
+                Field::Todos if !self.todos.is_empty() => {
+                    out.push_str("\nTodos:\n");
+                    let (page, more) = self.todo_page(options);
+                    for todo in page {
+                        let status = if todo.completed { "[x]" } else { "[ ]" };
+                        out.push_str(&format!(
+                            "  {} {} {}{}\n",
+                            status,
+                            todo.id.as_str(),
+                            todo.text.as_str(),
+                            todo.tag_suffix()
+                        ));
+                    }
+                    if more > 0 {
+                        out.push_str(&format!("  (... and {} more)\n", more));
+                    }
+                }
+                _ => {}
             }
         }
+        out
+    }
+
+    /// Label and value for a field's dashboard row, without decoration
+    fn row_text(
+        &self,
+        field: Field,
+        options: &RenderOptions,
+        room: usize,
+    ) -> Option<(&'static str, String)> {
+        let preview = |text: &str| {
+            if text.is_empty() {
+                "(empty)".to_string()
+            } else {
+                // Leave room for the longest label and its ": "
+                truncate_preview(text, options.preview_within(room.saturating_sub(13)))
+            }
+        };
+        Some(match field {
+            Field::Counter => ("Counter", self.counter.to_string()),
+            Field::Temperature => {
+                let (degrees, unit) = self.temperature_in(options);
+                ("Temperature", format!("{} {}", degrees, unit))
+            }
+            Field::DarkMode => ("Dark Mode", if self.darkMode { "ON" } else { "OFF" }.to_string()),
+            Field::Notes => ("Notes", preview(self.notes.as_str())),
+            Field::Code => {
+                let code_str = self.code.as_str();
+                let value = if code_str.is_empty() {
+                    "(empty)".to_string()
+                } else {
+                    format!("{} lines, {} chars", code_str.lines().count(), code_str.chars().count())
+                };
+                ("Code", value)
+            }
+            Field::Todos => ("Todos", self.todos.len().to_string()),
+            Field::Tags => ("Tags", self.tags.len().to_string()),
+            Field::Sections => ("Sections", self.sections.len().to_string()),
+            Field::Settings => ("Settings", self.settings.len().to_string()),
+            Field::Attachments => ("Attachments", self.attachments.len().to_string()),
+            Field::Metadata => ("Title", preview(self.metadata.title.as_ref()?.as_str())),
+        })
+    }
+
+    /// The temperature and its unit letter, in °F when the options ask for it
+    fn temperature_in(&self, options: &RenderOptions) -> (i64, &'static str) {
+        if options.fahrenheit {
+            (celsius_to_fahrenheit(self.temperature), "F")
+        } else {
+            (self.temperature, "C")
+        }
+    }
+
+    /// The todos selected by the options' status, offset and limit, and how many follow them
+    fn todo_page(&self, options: &RenderOptions) -> (Vec<&TodoItem>, usize) {
+        let matching: Vec<&TodoItem> = self
+            .todos
+            .iter()
+            .filter(|todo| !matches!(options.todo_status, Some(status) if todo.completed != status))
+            .collect();
+        let len = matching.len();
+        let start = options.todo_offset.min(len);
+        let end = match options.todo_limit {
+            Some(limit) => start.saturating_add(limit).min(len),
+            None => len,
+        };
+        (matching[start..end].to_vec(), len - end)
+    }
+
+    /// Extra lines printed below the box for list fields
+    fn render_details(&self, field: Field, options: &RenderOptions) -> Option<String> {
+        match field {
+            Field::Tags if !self.tags.is_empty() => {
+                Some(format!("\n🏷️  Tags: {}\n", self.tags.join(", ")))
+            }
+            Field::Todos if !self.todos.is_empty() => {
+                let mut out = String::from("\n✓ Todos:\n");
+                let (page, more) = self.todo_page(options);
+                for todo in page {
+                    let status = if todo.completed { "✓" } else { "○" };
+                    out.push_str(&format!(
+                        "  {} [{}] {}{}\n",
+                        status,
+                        todo.id.as_str(),
+                        todo.text.as_str(),
+                        todo.tag_suffix()
+                    ));
+                }
+                if more > 0 {
+                    out.push_str(&format!("  (… and {} more)\n", more));
+                }
+                Some(out)
+            }
+            _ => None,
+        }
     }
 
     pub fn display_field(&self, field: &str) {
+        self.display_field_with(field, &RenderOptions::default());
+    }
+
+    pub fn display_field_with(&self, field: &str, options: &RenderOptions) {
+        let format_time = |millis: i64| {
+            if options.relative_times {
+                humanize_since(millis)
+            } else {
+                format!(
+                    "{} ({})",
+                    chrono::DateTime::from_timestamp_millis(millis)
+                        .map(|dt| dt.to_rfc3339())
+                        .unwrap_or_else(|| "invalid".to_string()),
+                    millis
+                )
+            }
+        };
+
         match field.to_lowercase().as_str() {
             "counter" => {
                 println!("🔢 Counter: {}", self.counter);
             }
             "temperature" => {
-                println!("🌡️  Temperature: {}°C", self.temperature);
+                let (degrees, unit) = self.temperature_in(options);
+                println!("🌡️  Temperature: {}°{}", degrees, unit);
             }
             "darkmode" | "dark_mode" => {
                 println!("🌙 Dark Mode: {}", if self.darkMode { "ON" } else { "OFF" });
@@ -134,12 +772,22 @@ impl Doc {
             }
             "todos" => {
                 println!("✓ Todos ({}):", self.todos.len());
-                if self.todos.is_empty() {
+                let (page, more) = self.todo_page(options);
+                if page.is_empty() && more == 0 {
                     println!("  (none)");
                 } else {
-                    for todo in &self.todos {
+                    for todo in page {
                         let status = if todo.completed { "✓" } else { "○" };
-                        println!("  {} [{}] {}", status, todo.id.as_str(), todo.text.as_str());
+                        println!(
+                            "  {} [{}] {}{}",
+                            status,
+                            todo.id.as_str(),
+                            todo.text.as_str(),
+                            todo.tag_suffix()
+                        );
+                    }
+                    if more > 0 {
+                        println!("  (… and {} more; --all lists everything)", more);
                     }
                 }
             }
@@ -151,32 +799,653 @@ impl Doc {
                     println!("  {}", self.tags.join(", "));
                 }
             }
+            "attachments" => {
+                println!("📎 Attachments ({}):", self.attachments.len());
+                if self.attachments.is_empty() {
+                    println!("  (none)");
+                } else {
+                    for attachment in &self.attachments {
+                        println!(
+                            "  [{}] {} {} (todo {})",
+                            attachment.id,
+                            attachment.mime,
+                            attachment.url,
+                            attachment.todoId.as_deref().unwrap_or("-")
+                        );
+                    }
+                }
+            }
+            "sections" => {
+                println!("📑 Sections ({}):", self.sections.len());
+                if self.sections.is_empty() {
+                    println!("  (none)");
+                } else {
+                    for name in self.section_names() {
+                        println!("\n## {}", name);
+                        println!("{}", self.sections[name].as_str());
+                    }
+                }
+            }
+            "settings" => {
+                println!("⚙️  Settings ({}):", self.settings.len());
+                if self.settings.is_empty() {
+                    println!("  (none)");
+                } else {
+                    let mut keys: Vec<_> = self.settings.keys().collect();
+                    keys.sort();
+                    for key in keys {
+                        println!("  {} = {}", key, self.settings[key]);
+                    }
+                }
+            }
             "metadata" => {
                 println!("📄 Metadata:");
                 if let Some(title) = &self.metadata.title {
                     println!("  Title: {}", title.as_str());
                 }
                 if let Some(created) = self.metadata.createdAt {
-                    println!("  Created: {} ({})",
-                        chrono::DateTime::from_timestamp_millis(created)
-                            .map(|dt| dt.to_rfc3339())
-                            .unwrap_or_else(|| "invalid".to_string()),
-                        created
-                    );
+                    println!("  Created: {}", format_time(created));
                 }
                 if let Some(modified) = self.metadata.lastModified {
-                    println!("  Last Modified: {} ({})",
-                        chrono::DateTime::from_timestamp_millis(modified)
-                            .map(|dt| dt.to_rfc3339())
-                            .unwrap_or_else(|| "invalid".to_string()),
-                        modified
-                    );
+                    println!("  Last Modified: {}", format_time(modified));
+                }
+                let mut locks: Vec<_> = self.metadata.locks.iter().collect();
+                locks.sort();
+                for (field, owner) in locks {
+                    println!("  🔒 {} locked by {}", field, owner);
                 }
             }
             _ => {
                 println!("❌ Unknown field: {}", field);
-                println!("Available fields: counter, temperature, darkMode, notes, code, todos, tags, metadata");
+                println!("Available fields: counter, temperature, darkMode, notes, sections, code, todos, tags, settings, attachments, metadata");
             }
         }
     }
+
+    /// Overwrite the fields present in `json` (as written by `export-json`) in
+    /// place, leaving every other field as it is.
+    ///
+    /// Text fields are replaced with [`replace_text`], so they stay the same Text
+    /// objects. `metadata` is merged key by key, todos may be plain strings and
+    /// keep their Text when their id matches, and `schemaVersion` is never
+    /// imported since it describes this document's shape. Unknown keys are ignored.
+    pub fn merge_json(&mut self, json: serde_json::Value) -> serde_json::Result<()> {
+        use serde_json::Value;
+        fn invalid(message: String) -> serde_json::Error {
+            <serde_json::Error as serde::de::Error>::custom(message)
+        }
+        fn field<T: serde::de::DeserializeOwned>(key: &str, value: Value) -> serde_json::Result<T> {
+            serde_json::from_value(value).map_err(|e| invalid(format!("{}: {}", key, e)))
+        }
+
+        let Value::Object(json) = json else {
+            return Err(invalid("expected a JSON object of document fields".to_string()));
+        };
+        for (key, value) in json {
+            match key.as_str() {
+                "counter" => self.counter = field(&key, value)?,
+                "temperature" => self.temperature = field(&key, value)?,
+                "darkMode" => self.darkMode = field(&key, value)?,
+                "notes" => replace_text(&mut self.notes, &field::<String>(&key, value)?),
+                "code" => replace_text(&mut self.code, &field::<String>(&key, value)?),
+                "sections" => {
+                    let sections: HashMap<String, String> = field(&key, value)?;
+                    self.sections.retain(|name, _| sections.contains_key(name));
+                    for (name, text) in sections {
+                        match self.sections.get_mut(&name) {
+                            Some(section) => replace_text(section, &text),
+                            None => {
+                                self.sections.insert(name, autosurgeon::Text::with_value(text));
+                            }
+                        }
+                    }
+                }
+                "tags" => self.tags = field(&key, value)?,
+                "todos" => {
+                    let Value::Array(todos) = value else {
+                        return Err(invalid("todos: expected an array".to_string()));
+                    };
+                    let todos = todos
+                        .into_iter()
+                        .map(|todo| match todo {
+                            Value::String(text) => serde_json::json!({ "text": text }),
+                            todo => todo,
+                        })
+                        .map(|todo| field::<TodoItem>("todos", todo))
+                        .collect::<serde_json::Result<Vec<_>>>()?;
+                    self.todos = todos
+                        .into_iter()
+                        .map(|mut todo| {
+                            if let Some(existing) = self.todos.iter().find(|t| t.id == todo.id) {
+                                let text = std::mem::replace(&mut todo.text, existing.text.clone());
+                                replace_text(&mut todo.text, text.as_str());
+                                todo.id = existing.id.clone();
+                            }
+                            todo
+                        })
+                        .collect();
+                }
+                "metadata" => {
+                    let Value::Object(metadata) = value else {
+                        return Err(invalid("metadata: expected an object".to_string()));
+                    };
+                    for (key, value) in metadata {
+                        let meta = &mut self.metadata;
+                        match key.as_str() {
+                            "title" => match field::<Option<String>>(&key, value)? {
+                                Some(title) => match &mut meta.title {
+                                    Some(text) => replace_text(text, &title),
+                                    None => meta.title = Some(autosurgeon::Text::with_value(title)),
+                                },
+                                None => meta.title = None,
+                            },
+                            "createdAt" => meta.createdAt = field(&key, value)?,
+                            "lastModified" => meta.lastModified = field(&key, value)?,
+                            "appliedKeys" => meta.appliedKeys = field(&key, value)?,
+                            "locks" => meta.locks = field(&key, value)?,
+                            _ => {}
+                        }
+                    }
+                }
+                "settings" => self.settings = field(&key, value)?,
+                "attachments" => self.attachments = field(&key, value)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Section names in alphabetical order
+    pub fn section_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.sections.keys().map(String::as_str).collect();
+        names.sort();
+        names
+    }
+
+    /// Stamp `metadata.lastModified` with the current time
+    pub fn touch(&mut self) {
+        self.metadata.lastModified = Some(chrono::Utc::now().timestamp_millis());
+    }
+
+    /// Whether two states hold the same content, ignoring `metadata.lastModified`
+    pub fn same_content(&self, other: &Doc) -> bool {
+        fn todo_key(todo: &TodoItem) -> (&str, &str, bool, &[String]) {
+            (todo.id.as_str(), todo.text.as_str(), todo.completed, todo.tags.as_slice())
+        }
+
+        self.counter == other.counter
+            && self.temperature == other.temperature
+            && self.darkMode == other.darkMode
+            && self.notes.as_str() == other.notes.as_str()
+            && self.sections.len() == other.sections.len()
+            && self.sections.iter().all(|(name, text)| {
+                matches!(other.sections.get(name), Some(other) if other.as_str() == text.as_str())
+            })
+            && self.code.as_str() == other.code.as_str()
+            && self.tags == other.tags
+            && self.todos.iter().map(todo_key).eq(other.todos.iter().map(todo_key))
+            && self.settings == other.settings
+            && self.attachments == other.attachments
+            && self.schemaVersion == other.schemaVersion
+            && self.metadata.title.as_ref().map(|t| t.as_str())
+                == other.metadata.title.as_ref().map(|t| t.as_str())
+            && self.metadata.createdAt == other.metadata.createdAt
+            && self.metadata.appliedKeys == other.metadata.appliedKeys
+            && self.metadata.locks == other.metadata.locks
+    }
+
+    /// Set a field by (optionally dotted) path, e.g. `counter` or `metadata.title`,
+    /// coercing `value` to the field's type.
+    pub fn set_path(&mut self, path: &str, value: &str) -> Result<()> {
+        fn parse<T: std::str::FromStr>(path: &str, value: &str, expected: &'static str) -> Result<T> {
+            value.parse().map_err(|_| AutodashError::InvalidValue {
+                path: path.to_string(),
+                expected,
+                value: value.to_string(),
+            })
+        }
+
+        match path.to_lowercase().as_str() {
+            "counter" => self.counter = parse(path, value, "an integer")?,
+            "temperature" => self.temperature = parse::<i64>(path, value, "an integer")?.clamp(0, 40),
+            "darkmode" | "dark_mode" => self.darkMode = parse(path, value, "true or false")?,
+            "notes" => replace_text(&mut self.notes, value),
+            "code" => replace_text(&mut self.code, value),
+            "metadata.title" => match &mut self.metadata.title {
+                Some(title) => replace_text(title, value),
+                None => self.metadata.title = Some(autosurgeon::Text::with_value(value)),
+            },
+            "metadata.createdat" => {
+                self.metadata.createdAt = Some(parse(path, value, "a millisecond timestamp")?)
+            }
+            "metadata.lastmodified" => {
+                self.metadata.lastModified = Some(parse(path, value, "a millisecond timestamp")?)
+            }
+            _ => return Err(AutodashError::UnknownField(path.to_string())),
+        }
+        Ok(())
+    }
+}
+
+/// Replace the whole content of a Text field with a single splice.
+///
+/// The splice edits the local string, so it's counted in bytes; [`reconcile_doc`]
+/// writes the new value to the document by character.
+pub fn replace_text(text: &mut autosurgeon::Text, value: &str) {
+    let len = text.as_str().len();
+    text.splice(0, len as isize, value);
+}
+
+/// The single splice that turns one string into another: delete `delete`
+/// characters at `start`, then insert `insert` there
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplicePlan {
+    pub start: usize,
+    pub delete: usize,
+    pub insert: String,
+}
+
+impl SplicePlan {
+    /// The span of `old` this plan deletes, in bytes, for splicing a local string
+    pub fn byte_range(&self, old: &str) -> std::ops::Range<usize> {
+        let byte_offset = |chars: usize| old.char_indices().nth(chars).map_or(old.len(), |(i, _)| i);
+        byte_offset(self.start)..byte_offset(self.start + self.delete)
+    }
+}
+
+/// Diff `old` against `new` by their common prefix and suffix, so only the
+/// changed span is rewritten. Positions are in characters.
+pub fn splice_plan(old: &str, new: &str) -> SplicePlan {
+    let old_chars: Vec<char> = old.chars().collect();
+    let new_chars: Vec<char> = new.chars().collect();
+
+    let prefix = old_chars
+        .iter()
+        .zip(&new_chars)
+        .take_while(|(a, b)| a == b)
+        .count();
+    // The suffix can't overlap the prefix in either string
+    let suffix = old_chars[prefix..]
+        .iter()
+        .rev()
+        .zip(new_chars[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    SplicePlan {
+        start: prefix,
+        delete: old_chars.len() - prefix - suffix,
+        insert: new_chars[prefix..new_chars.len() - suffix].iter().collect(),
+    }
+}
+
+/// Split `s` into chunks of at most `max_bytes`, never cutting through a character.
+pub fn chunk_str(s: &str, max_bytes: usize) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < s.len() {
+        let mut end = (start + max_bytes).min(s.len());
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        if end == start {
+            // A single character wider than the limit gets a chunk of its own
+            end = start + s[start..].chars().next().map_or(0, char::len_utf8);
+        }
+        chunks.push(&s[start..end]);
+        start = end;
+    }
+    chunks
+}
+
+/// Remove duplicates from `items`, keeping the first occurrence in place.
+/// Entries are compared after optional trimming and case folding; the kept
+/// entry is left as written. Returns how many entries were removed.
+pub fn dedupe_strings(items: &mut Vec<String>, ignore_case: bool, trim: bool) -> usize {
+    let normalize = |item: &str| {
+        let item = if trim { item.trim() } else { item };
+        if ignore_case {
+            item.to_lowercase()
+        } else {
+            item.to_string()
+        }
+    };
+
+    let before = items.len();
+    let mut seen = std::collections::HashSet::new();
+    items.retain(|item| seen.insert(normalize(item)));
+    before - items.len()
+}
+
+/// Parse a comma-separated list of hex change hashes (as printed by `heads`).
+pub fn parse_heads(s: &str) -> Result<Vec<automerge::ChangeHash>> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|h| !h.is_empty())
+        .map(|h| {
+            h.parse()
+                .map_err(|_| AutodashError::InvalidChangeHash(h.to_string()))
+        })
+        .collect()
+}
+
+/// Format heads as a comma-separated list of hex change hashes.
+pub fn format_heads(heads: &[automerge::ChangeHash]) -> String {
+    heads
+        .iter()
+        .map(|h| h.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Serialize `value` as JSON. `pretty` forces the layout; when unset, output is
+/// pretty-printed for a terminal and compact when piped.
+pub fn to_json_string<T: serde::Serialize + ?Sized>(
+    value: &T,
+    pretty: Option<bool>,
+) -> serde_json::Result<String> {
+    use std::io::IsTerminal;
+    if pretty.unwrap_or_else(|| std::io::stdout().is_terminal()) {
+        serde_json::to_string_pretty(value)
+    } else {
+        serde_json::to_string(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use automerge::{Automerge, ReadDoc};
+
+    fn fresh_doc() -> Automerge {
+        let mut doc = Automerge::new();
+        ensure_schema(&mut doc).unwrap();
+        doc
+    }
+
+    fn save(doc: &mut Automerge, state: &Doc) {
+        doc.transact(|tx| reconcile_doc(tx, state, None)).unwrap();
+    }
+
+    fn empty_state() -> Doc {
+        autosurgeon::hydrate(&fresh_doc()).unwrap()
+    }
+
+    /// The text inside each `| ... |` row of an ASCII render
+    fn ascii_rows(rendered: &str) -> Vec<&str> {
+        rendered
+            .lines()
+            .filter_map(|line| line.strip_prefix("| ")?.strip_suffix(" |"))
+            .map(str::trim_end)
+            .collect()
+    }
+
+    fn root_text(doc: &Automerge, key: &str) -> String {
+        let (_, obj) = doc.get(automerge::ROOT, key).unwrap().unwrap();
+        doc.text(&obj).unwrap()
+    }
+
+    #[test]
+    fn counter_increments_on_two_forks_add_up() {
+        let mut doc = fresh_doc();
+        let start: Doc = autosurgeon::hydrate(&doc).unwrap();
+        let mut fork = doc.fork();
+        for side in [&mut doc, &mut fork] {
+            let state: Doc = autosurgeon::hydrate(&*side).unwrap();
+            side.transact(|tx| write_counter(tx, state.counter, state.counter + 1))
+                .unwrap();
+        }
+        doc.merge(&mut fork).unwrap();
+        let merged: Doc = autosurgeon::hydrate(&doc).unwrap();
+        assert_eq!(merged.counter, start.counter + 2);
+    }
+
+    #[test]
+    fn write_counter_replaces_a_plain_integer() {
+        use automerge::transaction::Transactable;
+        let mut doc = Automerge::new();
+        doc.transact(|tx| tx.put(automerge::ROOT, "counter", 5)).unwrap();
+        doc.transact(|tx| write_counter(tx, 5, 7)).unwrap();
+        let (value, _) = doc.get(automerge::ROOT, "counter").unwrap().unwrap();
+        assert_eq!(value, automerge::Value::counter(7));
+    }
+
+    #[test]
+    fn set_path_replaces_text_and_parses_timestamps() {
+        let mut doc = fresh_doc();
+        for notes in ["first draft", "second"] {
+            let mut state: Doc = autosurgeon::hydrate(&doc).unwrap();
+            state.set_path("notes", notes).unwrap();
+            state.set_path("metadata.title", notes).unwrap();
+            state.set_path("metadata.createdAt", "1700000000000").unwrap();
+            save(&mut doc, &state);
+        }
+        assert_eq!(root_text(&doc, "notes"), "second");
+        let state: Doc = autosurgeon::hydrate(&doc).unwrap();
+        assert_eq!(state.metadata.title.as_ref().map(|t| t.as_str()), Some("second"));
+        assert_eq!(state.metadata.createdAt, Some(1_700_000_000_000));
+    }
+
+    #[test]
+    fn set_path_rejects_bad_values_and_unknown_fields() {
+        let mut state: Doc = autosurgeon::hydrate(&fresh_doc()).unwrap();
+        assert!(matches!(
+            state.set_path("metadata.createdAt", "yesterday"),
+            Err(AutodashError::InvalidValue { .. })
+        ));
+        assert!(matches!(state.set_path("nope", "1"), Err(AutodashError::UnknownField(_))));
+    }
+
+    #[test]
+    fn compact_json_is_a_single_line() {
+        let value = serde_json::json!({ "todos": [{ "text": "a" }, { "text": "b" }] });
+        let compact = to_json_string(&value, Some(false)).unwrap();
+        assert!(!compact.contains('\n'));
+        let pretty = to_json_string(&value, Some(true)).unwrap();
+        assert!(pretty.lines().count() > 1);
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&compact).unwrap(), value);
+    }
+
+    #[test]
+    fn field_order_renders_only_the_listed_fields_in_order() {
+        let mut state = empty_state();
+        state.counter = 4;
+        state.tags = vec!["a".to_string(), "b".to_string()];
+        let options = RenderOptions {
+            fields: parse_field_order("counter,tags".split(',')).unwrap(),
+            ascii: true,
+            ..Default::default()
+        };
+        assert_eq!(ascii_rows(&state.render(&options)), vec!["Counter: 4", "Tags: 2"]);
+        assert!(matches!(
+            parse_field_order(["counter", "Counter"]),
+            Err(AutodashError::DuplicateField(_))
+        ));
+    }
+
+    #[test]
+    fn dedupe_collapses_case_and_whitespace_variants() {
+        let mut tags = vec!["a".to_string(), "A".to_string(), "a ".to_string()];
+        assert_eq!(dedupe_strings(&mut tags, true, true), 2);
+        assert_eq!(tags, vec!["a"]);
+
+        let mut tags = vec!["a".to_string(), "A".to_string(), "a ".to_string()];
+        assert_eq!(dedupe_strings(&mut tags, false, false), 0);
+        assert_eq!(tags.len(), 3);
+    }
+
+    #[test]
+    fn preview_len_cuts_the_notes_preview() {
+        let mut state = empty_state();
+        state.notes = autosurgeon::Text::with_value("a note well past ten characters");
+        let options = RenderOptions {
+            preview_len: 10,
+            ascii: true,
+            ..Default::default()
+        };
+        let rendered = state.render(&options);
+        assert!(ascii_rows(&rendered).contains(&"Notes: a note ..."));
+        assert_eq!(truncate_preview(state.notes.as_str(), 10).chars().count(), 10);
+        // The box stays closed
+        let widths: Vec<usize> = rendered
+            .lines()
+            .filter(|line| line.starts_with('+') || line.starts_with('|'))
+            .map(|line| line.chars().count())
+            .collect();
+        assert!(widths.windows(2).all(|pair| pair[0] == pair[1]));
+    }
+
+    #[test]
+    fn ascii_render_snapshot() {
+        let mut state = empty_state();
+        state.counter = 3;
+        state.temperature = 21;
+        state.darkMode = true;
+        state.notes = autosurgeon::Text::with_value("hello");
+        state.tags = vec!["ops".to_string()];
+        state.metadata.title = Some(autosurgeon::Text::with_value("Demo"));
+        state.todos.push(TodoItem {
+            id: autosurgeon::Text::with_value("t1"),
+            text: autosurgeon::Text::with_value("write tests"),
+            completed: true,
+            tags: vec!["cli".to_string()],
+        });
+        let options = RenderOptions {
+            ascii: true,
+            ..Default::default()
+        };
+        let expected = concat!(
+            "\n",
+            "Demo\n",
+            "+-----------------------------------------+\n",
+            "| Counter: 3                              |\n",
+            "| Temperature: 21 C                       |\n",
+            "| Dark Mode: ON                           |\n",
+            "| Notes: hello                            |\n",
+            "| Code: (empty)                           |\n",
+            "| Todos: 1                                |\n",
+            "| Tags: 1                                 |\n",
+            "| Title: Demo                             |\n",
+            "+-----------------------------------------+\n",
+            "\n",
+            "Todos:\n",
+            "  [x] t1 write tests #cli\n",
+            "\n",
+            "Tags: ops\n",
+        );
+        let rendered = state.render(&options);
+        assert_eq!(rendered, expected);
+        assert!(rendered.is_ascii());
+    }
+
+    #[test]
+    fn unicode_render_has_box_drawing_and_no_mojibake() {
+        let mut state = empty_state();
+        state.notes = autosurgeon::Text::with_value("naïve café ✓");
+        let options = RenderOptions {
+            ascii: false,
+            ..Default::default()
+        };
+        let rendered = state.render(&options);
+        for c in ['╭', '╮', '╰', '╯', '│', '─'] {
+            assert!(rendered.contains(c), "missing {:?}", c);
+        }
+        assert!(rendered.contains("naïve café ✓"));
+        assert!(!rendered.contains('\u{FFFD}'));
+        // UTF-8 box drawing read back as Latin-1 shows up as "â”"
+        assert!(!rendered.contains("â"));
+    }
+
+    #[test]
+    fn humanize_since_boundaries() {
+        let ago = |millis: i64| humanize_since(chrono::Utc::now().timestamp_millis() - millis);
+        assert_eq!(ago(0), "just now");
+        assert_eq!(ago(59_000), "just now");
+        assert_eq!(ago(60_000), "1 minute ago");
+        assert_eq!(ago(3_599_000), "59 minutes ago");
+        assert_eq!(ago(3_600_000), "1 hour ago");
+        assert_eq!(ago(86_399_000), "23 hours ago");
+        assert_eq!(ago(86_400_000), "1 day ago");
+        assert_eq!(ago(3 * 86_400_000), "3 days ago");
+        assert_eq!(ago(-60_000), "in the future");
+    }
+
+    #[test]
+    fn todo_limit_lists_a_page_and_a_footer() {
+        let mut state = empty_state();
+        for i in 0..100 {
+            state.todos.push(TodoItem::new(&format!("todo {}", i), false));
+        }
+        let options = RenderOptions {
+            ascii: true,
+            todo_limit: Some(5),
+            ..Default::default()
+        };
+        let rendered = state.render(&options);
+        let listed: Vec<&str> = rendered.lines().filter(|line| line.starts_with("  [ ]")).collect();
+        assert_eq!(listed.len(), 5);
+        assert!(listed[4].ends_with("todo 4"));
+        assert!(rendered.contains("  (... and 95 more)"));
+        assert!(ascii_rows(&rendered).contains(&"Todos: 100"));
+    }
+
+    #[test]
+    fn render_width_80_keeps_borders_aligned() {
+        let mut state = empty_state();
+        state.notes = autosurgeon::Text::with_value("x".repeat(200));
+        // Wide characters take two columns each, so they only line up when padded by width
+        state.metadata.title = Some(autosurgeon::Text::with_value("日本語 ☕ title"));
+        let options = RenderOptions {
+            ascii: true,
+            width: Some(80),
+            ..Default::default()
+        };
+        let rendered = state.render(&options);
+        let boxed: Vec<&str> = rendered
+            .lines()
+            .filter(|line| line.starts_with('+') || line.starts_with('|'))
+            .collect();
+        assert_eq!(boxed.len(), 2 + options.fields.len());
+        for line in boxed {
+            assert_eq!(UnicodeWidthStr::width(line), 80, "{:?}", line);
+        }
+
+        let options = RenderOptions {
+            ascii: false,
+            ..options
+        };
+        let rendered = state.render(&options);
+        let boxed: Vec<&str> = rendered
+            .lines()
+            .filter(|line| line.starts_with('╭') || line.starts_with('│') || line.starts_with('╰'))
+            .collect();
+        assert_eq!(boxed.len(), 2 + options.fields.len());
+        for line in boxed {
+            assert_eq!(UnicodeWidthStr::width(line), 80, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn replacing_multi_byte_text_writes_whole_characters() {
+        let mut doc = fresh_doc();
+        for value in ["naïve café ✓", "crème brûlée", "plain"] {
+            let mut state: Doc = autosurgeon::hydrate(&doc).unwrap();
+            for path in ["notes", "code", "metadata.title"] {
+                state.set_path(path, value).unwrap();
+            }
+            save(&mut doc, &state);
+            assert_eq!(root_text(&doc, "notes"), value);
+            assert_eq!(root_text(&doc, "code"), value);
+            let state: Doc = autosurgeon::hydrate(&doc).unwrap();
+            assert_eq!(state.metadata.title.as_ref().map(|t| t.as_str()), Some(value));
+        }
+    }
+
+    #[test]
+    fn splice_plan_byte_range_skips_multi_byte_characters() {
+        let plan = splice_plan("crème brûlée", "crème caramel");
+        assert_eq!((plan.start, plan.delete), (6, 6));
+        let range = plan.byte_range("crème brûlée");
+        assert_eq!(&"crème brûlée"[range], "brûlée");
+    }
 }
@@ -9,17 +9,13 @@
 
 use anyhow::{Context, Result};
 use automerge_cli::*;
-use autosurgeon::{hydrate, reconcile};
+use autosurgeon::hydrate;
 use clap::{Parser, Subcommand};
 use futures_util::StreamExt;
-use std::convert::Infallible;
-use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
 use tokio::time::sleep;
-use tokio_tungstenite::{connect_async, tungstenite::Message};
 
-// Global counter for unique todo IDs
-static TODO_ID_COUNTER: AtomicU64 = AtomicU64::new(0);
+const DEFAULT_ATTACHMENTS_DIR: &str = "./autodash-attachments/";
 
 #[derive(Parser)]
 #[command(name = "automerge-cli")]
@@ -30,12 +26,127 @@ struct Cli {
     ///   automerge:4VgLSsiuVNfWeZk17m85GgA18VVp
     ///   http://localhost:5173/#automerge:4VgLSsiuVNfWeZk17m85GgA18VVp
     #[arg(value_name = "URL")]
-    doc_url: String,
+    doc_url: Option<String>,
+
+    /// Open the document with this label (see the `label` command) instead of a URL
+    #[arg(long = "doc", value_name = "LABEL", conflicts_with = "doc_url")]
+    doc_label: Option<String>,
 
     /// Enable verbose debug logging
     #[arg(short, long)]
     verbose: bool,
 
+    /// Don't reconnect if the sync server closes the connection
+    #[arg(long)]
+    no_reconnect_on_eof: bool,
+
+    /// Token for sync servers that require authentication
+    /// (defaults to $AUTODASH_AUTH_TOKEN, then `auth_token` in the config file)
+    #[arg(long, value_name = "TOKEN")]
+    auth_token: Option<String>,
+
+    /// A friendly name for this client, sent to the sync server when connecting
+    #[arg(long, value_name = "NAME")]
+    peer_name: Option<String>,
+
+    /// Skip writing a change when the command leaves the document unchanged
+    #[arg(long)]
+    only_if_changed: bool,
+
+    /// Only write the top-level fields the command changed, instead of reconciling them all
+    #[arg(long)]
+    dedupe_changes: bool,
+
+    /// Write the change produced by a mutating command to this file
+    /// as an Automerge incremental save
+    #[arg(long, value_name = "PATH")]
+    as_patch: Option<std::path::PathBuf>,
+
+    /// Who is making the change, for advisory locks
+    /// (defaults to $AUTODASH_USER, then $USER)
+    #[arg(long, value_name = "NAME")]
+    user: Option<String>,
+
+    /// Modify fields even when someone else holds their lock
+    #[arg(long)]
+    force: bool,
+
+    /// Leave metadata.lastModified alone instead of stamping it on change
+    #[arg(long)]
+    no_stamp: bool,
+
+    /// For tail-history and watch: poll for document changes every MS milliseconds
+    /// instead of waiting for change events (0 = events). Polling costs a little
+    /// CPU per tick but works where change events aren't delivered
+    #[arg(long, value_name = "MS", default_value_t = 0)]
+    poll_changes: u64,
+
+    /// Keep documents in memory only, writing nothing to local storage
+    #[arg(long)]
+    no_persist: bool,
+
+    /// Fail if the document is missing a required field or has one of the
+    /// wrong type, instead of filling in defaults (useful in CI)
+    #[arg(long, conflicts_with = "type_overrides")]
+    strict_hydrate: bool,
+
+    /// Check text arguments for control characters (anything but newline and tab)
+    /// before writing them: reject the command (the default) or, with =strip, remove them
+    #[arg(
+        long,
+        value_enum,
+        value_name = "MODE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "reject"
+    )]
+    strict_utf8: Option<ControlChars>,
+
+    /// How this document stores fields that clients disagree on, e.g.
+    /// `notes=str,counter=counter,darkMode=int` (types: text, str, int, counter, bool)
+    #[arg(long = "as", value_delimiter = ',', value_name = "FIELD=TYPE")]
+    type_overrides: Vec<String>,
+
+    /// Fields to show on the dashboard, in order (e.g. counter,notes,todos);
+    /// unlisted fields are hidden. Overrides `field_order` in the config file
+    #[arg(long, value_delimiter = ',', value_name = "FIELDS")]
+    field_order: Option<Vec<String>>,
+
+    /// Characters of notes/title to preview on the dashboard (default 30).
+    /// Overrides `preview_len` in the config file
+    #[arg(long, value_name = "N")]
+    preview_len: Option<usize>,
+
+    /// Plain ASCII dashboard without emoji or box drawing
+    /// (the default when the locale isn't UTF-8)
+    #[arg(long)]
+    ascii: bool,
+
+    /// Dashboard width in columns, previews cut to fit (default: the terminal's
+    /// width when printing to one)
+    #[arg(long, value_name = "COLS", value_parser = clap::value_parser!(u16).range(MIN_RENDER_WIDTH as i64..))]
+    render_width: Option<u16>,
+
+    /// Show metadata timestamps relative to now ("3 minutes ago")
+    #[arg(long)]
+    relative: bool,
+
+    /// Show temperatures in °F (the document still stores °C)
+    #[arg(long)]
+    fahrenheit: bool,
+
+    /// Pretty-print JSON output (default when writing to a terminal)
+    #[arg(long, global = true, conflicts_with = "compact")]
+    pretty: bool,
+
+    /// Emit compact single-line JSON (default when piped)
+    #[arg(long, global = true)]
+    compact: bool,
+
+    /// Don't draw progress bars (they're only drawn on a terminal anyway)
+    #[arg(long, short, global = true)]
+    quiet: bool,
+
     #[command(subcommand)]
     command: Option<Command>,
 }
@@ -43,25 +154,104 @@ struct Cli {
 #[derive(Subcommand)]
 enum Command {
     /// Increment the counter by 1
-    Increment,
+    Increment {
+        /// Apply at most once per key, so retried scripts don't double-count
+        #[arg(long)]
+        idempotency_key: Option<String>,
+    },
     /// Decrement the counter by 1
-    Decrement,
+    Decrement {
+        /// Apply at most once per key, so retried scripts don't double-count
+        #[arg(long)]
+        idempotency_key: Option<String>,
+    },
     /// Set counter to a specific value
-    SetCounter { value: i64 },
-    /// Set temperature value (0-40)
-    SetTemp { value: i64 },
+    SetCounter {
+        value: i64,
+        /// Apply at most once per key, so retried scripts don't double-count
+        #[arg(long)]
+        idempotency_key: Option<String>,
+    },
+    /// Set temperature value (0-40°C)
+    SetTemp {
+        value: i64,
+        /// The value is in °F; it's converted to °C before clamping
+        #[arg(long)]
+        fahrenheit: bool,
+    },
     /// Steadily increase temperature (1°C per 0.2s)
-    Heat,
+    Heat {
+        /// Only write every Nth tick to the document (every tick is still printed)
+        #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+        commit_every: u64,
+    },
+    /// Ease the temperature down from its current value to 0°C
+    Cool {
+        /// Only write every Nth tick to the document (every tick is still printed)
+        #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+        commit_every: u64,
+    },
+    /// Animate the temperature from one value to another
+    AnimateTemp {
+        /// Starting temperature (°C)
+        #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(i64).range(0..=40))]
+        from: i64,
+        /// Target temperature (°C)
+        #[arg(long, value_parser = clap::value_parser!(i64).range(0..=40))]
+        to: i64,
+        /// How long the sweep takes, in seconds
+        #[arg(long, value_name = "SECS", default_value_t = 8.0)]
+        duration: f64,
+        /// Easing curve
+        #[arg(long, value_enum, default_value_t = Ease::Linear)]
+        ease: Ease,
+        /// Only write every Nth tick to the document (every tick is still printed)
+        #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+        commit_every: u64,
+    },
     /// Toggle dark mode
     ToggleDark,
     /// Set dark mode on/off
     SetDark { enabled: bool },
     /// Add text to notes
-    AddNote { text: String },
+    AddNote {
+        text: String,
+        /// Split inserts larger than this many bytes into multiple splice ops
+        #[arg(long, value_name = "BYTES")]
+        max_change_size: Option<usize>,
+        /// Refuse inserts larger than --max-change-size instead of splitting them
+        #[arg(long, requires = "max_change_size")]
+        reject_oversized: bool,
+    },
     /// Clear notes field
     ClearNotes,
     /// Replace notes content
-    SetNotes { text: String },
+    SetNotes {
+        text: String,
+        /// Split inserts larger than this many bytes into multiple splice ops
+        #[arg(long, value_name = "BYTES")]
+        max_change_size: Option<usize>,
+        /// Refuse inserts larger than --max-change-size instead of splitting them
+        #[arg(long, requires = "max_change_size")]
+        reject_oversized: bool,
+    },
+    /// Replace the code field's content
+    SetCode { text: String },
+    /// Append a line to the code field
+    AppendCode { text: String },
+    /// Replace notes with a file's contents, syncing only the part that differs
+    SetNotesFromFile {
+        path: std::path::PathBuf,
+        /// The file's text encoding, e.g. utf-8, latin1, windows-1252, shift_jis
+        #[arg(long, value_name = "LABEL", default_value = "utf-8", value_parser = parse_encoding)]
+        encoding: &'static encoding_rs::Encoding,
+        /// Split inserts larger than this many bytes into multiple splice ops
+        #[arg(long, value_name = "BYTES")]
+        max_change_size: Option<usize>,
+        /// Refuse inserts larger than --max-change-size instead of splitting them
+        #[arg(long, requires = "max_change_size")]
+        reject_oversized: bool,
+    },
     /// Insert text at position in notes
     InsertNotes { position: usize, text: String },
     /// Delete characters from notes
@@ -70,370 +260,2021 @@ enum Command {
     AddTodo { text: String },
     /// Toggle todo completion
     ToggleTodo { id: String },
+    /// Replace a todo's text
+    EditTodo { id: String, text: String },
     /// Delete a todo
     DeleteTodo { id: String },
+    /// Delete every completed todo
+    ClearCompleted,
+    /// Add a tag to every todo whose text contains --match
+    BulkTag {
+        tag: String,
+        /// Text the todo must contain (case-sensitive)
+        #[arg(long = "match", value_name = "TEXT")]
+        pattern: String,
+        /// Only tag completed todos
+        #[arg(long, conflicts_with = "open")]
+        completed: bool,
+        /// Only tag todos that are still open
+        #[arg(long)]
+        open: bool,
+    },
+    /// Add todos from a markdown checklist (`- [ ] task`, `- [x] done`)
+    ImportTodos { path: std::path::PathBuf },
+    /// Print todos as a markdown checklist, or write them to a file
+    ExportTodos {
+        #[arg(long, short, value_name = "PATH")]
+        output: Option<std::path::PathBuf>,
+    },
+    /// Print notes, or write them to a file
+    ExportNotes {
+        #[arg(long, short, value_name = "PATH")]
+        output: Option<std::path::PathBuf>,
+        /// Text encoding to write, e.g. utf-8, latin1, windows-1252, shift_jis
+        #[arg(long, value_name = "LABEL", default_value = "utf-8", value_parser = parse_encoding)]
+        encoding: &'static encoding_rs::Encoding,
+    },
+    /// Print the whole document as JSON, or write it to a file
+    ExportJson { path: Option<std::path::PathBuf> },
+    /// Overwrite the fields present in a JSON file (e.g. from export-json), keeping the rest
+    ImportJson { path: std::path::PathBuf },
+    /// Attach a file to a todo (the file is copied to --attachments-dir)
+    Attach {
+        path: std::path::PathBuf,
+        /// Id (or id prefix) of the todo to attach to
+        #[arg(long, value_name = "ID")]
+        to_todo: String,
+        /// Where attachment files are stored
+        #[arg(long, value_name = "DIR", default_value = DEFAULT_ATTACHMENTS_DIR)]
+        attachments_dir: std::path::PathBuf,
+    },
     /// Add a tag
     AddTag { tag: String },
     /// Remove a tag
     RemoveTag { tag: String },
+    /// Collapse duplicate tags, keeping the first of each
+    Dedupe {
+        /// Treat `urgent` and `Urgent` as the same tag
+        #[arg(long)]
+        ignore_case: bool,
+        /// Ignore leading and trailing whitespace when comparing
+        #[arg(long)]
+        trim: bool,
+    },
     /// Set document title
     SetTitle { title: String },
+    /// Set the title and remember it locally as a label for this document,
+    /// so `--doc <label>` can open it
+    Label { label: String },
+    /// Add an empty named notes section
+    AddSection { name: String },
+    /// Replace a notes section's text, leaving the other sections untouched
+    EditSection { name: String, text: String },
+    /// List notes sections with their sizes
+    ListSections,
+    /// Set a key in the settings map
+    SetSetting { key: String, value: String },
+    /// Print a key from the settings map
+    GetSetting { key: String },
+    /// Remove a key from the settings map
+    UnsetSetting { key: String },
+    /// Set any field by path, e.g. `set counter 5` or `set metadata.title "Hello"`
+    Set { path: String, value: String },
+    /// Take an advisory lock on a field so others' commands refuse to change it
+    Lock {
+        #[arg(value_enum)]
+        field: Field,
+        /// Name to hold the lock under (defaults to --user)
+        #[arg(long, value_name = "NAME")]
+        by: Option<String>,
+    },
+    /// Release an advisory lock
+    Unlock {
+        #[arg(value_enum)]
+        field: Field,
+    },
     /// Display current document state (default)
     /// Optional field name to show specific field: code, notes, counter, temperature, darkMode, todos, tags, metadata
-    Show { field: Option<String> },
+    Show {
+        field: Option<String>,
+        /// Most todos to list [default: 20]
+        #[arg(long, value_name = "N")]
+        limit: Option<usize>,
+        /// Todos to skip from the start of the list
+        #[arg(long, value_name = "N", default_value_t = 0)]
+        offset: usize,
+        /// List every todo
+        #[arg(long, conflicts_with = "limit")]
+        all: bool,
+    },
+    /// List todos, optionally only completed (`--completed true`) or pending (`--completed false`) ones
+    ListTodos {
+        #[arg(long, value_name = "BOOL")]
+        completed: Option<bool>,
+    },
+    /// Print the document's current heads (comma-separated change hashes)
+    Heads,
+    /// Fork the document at historical heads into a new, editable document
+    Rewind {
+        /// Comma-separated change hashes to fork at (as printed by `heads`)
+        #[arg(long)]
+        to: String,
+    },
+    /// Save the document (or only the changes since some heads) to a file
+    Export {
+        path: std::path::PathBuf,
+        /// Only write changes made after these comma-separated heads
+        #[arg(long, value_name = "HEADS", conflicts_with = "full")]
+        since_heads: Option<String>,
+        /// Write the complete document (the default)
+        #[arg(long)]
+        full: bool,
+        /// Also write a SHA-256 checksum to `<path>.sha256`, verified when the file is read back
+        #[arg(long)]
+        checksum: bool,
+    },
+    /// Apply incremental change files (e.g. from --as-patch) in order
+    Replay {
+        #[arg(required = true, value_name = "PATCH")]
+        patches: Vec<std::path::PathBuf>,
+    },
+    /// Merge a saved document into this one, settling conflicting values by a strategy.
+    /// Works for documents that share no history, where every field would conflict
+    MergeFile {
+        path: std::path::PathBuf,
+        /// Which side wins where both documents set the same field
+        #[arg(long, value_enum, default_value_t = MergeStrategy::Newest)]
+        merge_strategy: MergeStrategy,
+    },
+    /// Upgrade the document to the current schema version
+    Migrate,
+    /// Collapse concurrent scalar writes by writing a chosen winner
+    ResolveConflicts {
+        /// How to pick the value to keep
+        #[arg(long, value_enum, default_value_t = ConflictPolicy::LastWriteByTimestamp)]
+        policy: ConflictPolicy,
+    },
+    /// Diagnose common setup problems (no document URL required)
+    Doctor,
+    /// Print this session's actor id, storage directory and sync server
+    Whoami,
+    /// List documents in local storage with their heads and size (no URL or server needed)
+    DumpStorage {
+        #[arg(long, value_name = "DIR", default_value = DEFAULT_STORAGE_DIR)]
+        storage_dir: std::path::PathBuf,
+    },
+    /// Delete locally stored documents that haven't changed recently
+    GcStorage {
+        /// Only remove documents untouched for this long, e.g. 30d, 12h, 45m
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        older_than: Option<Duration>,
+        /// Comma-separated document ids to keep regardless of age
+        #[arg(long, value_delimiter = ',', value_name = "IDS")]
+        keep: Vec<String>,
+        #[arg(long, value_name = "DIR", default_value = DEFAULT_STORAGE_DIR)]
+        storage_dir: std::path::PathBuf,
+        /// Actually delete (required unless --dry-run)
+        #[arg(long, conflicts_with = "dry_run")]
+        yes: bool,
+        /// Report what would be removed without deleting anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Search notes, code, todos, tags and the title (case-insensitive by default)
+    Search {
+        term: String,
+        /// Match case exactly
+        #[arg(long)]
+        case_sensitive: bool,
+        /// Treat the term as a regular expression
+        #[arg(long)]
+        regex: bool,
+    },
+    /// Show which fields changed since a snapshot saved with `export --full`
+    DiffAgainst { path: std::path::PathBuf },
+    /// Show which fields the latest change (or everything since some heads) altered
+    Diff {
+        /// Comma-separated change hashes to compare against (as printed by `heads`);
+        /// defaults to the heads before the latest change
+        #[arg(long, value_name = "HEADS")]
+        since: Option<String>,
+    },
+    /// List the document's actual top-level keys and their Automerge types, without hydrating
+    ListFields {
+        /// Also list the entries of top-level maps and lists
+        #[arg(long)]
+        recursive: bool,
+    },
+    /// Print the whole document as an indented tree of keys and typed values
+    Tree {
+        /// Stop descending below this many levels
+        #[arg(long)]
+        max_depth: Option<usize>,
+        /// Emit the tree as nested JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// List the document's change history
+    History {
+        /// Output format
+        #[arg(long, value_enum, default_value_t = HistoryFormat::Table)]
+        format: HistoryFormat,
+        /// Only list the N most recent changes
+        #[arg(long, value_name = "N")]
+        limit: Option<usize>,
+    },
+    /// Print the change history, then follow new changes as they sync in
+    TailHistory {
+        /// Output format (json is one object per line)
+        #[arg(long, value_enum, default_value_t = HistoryFormat::Table)]
+        format: HistoryFormat,
+    },
+    /// Redraw the dashboard whenever the document changes
+    Watch {
+        /// How to draw it: plain reprints, tui keeps a full-screen view
+        #[arg(long, value_enum, default_value_t = WatchRender::Plain)]
+        render: WatchRender,
+    },
 }
 
+impl Command {
+    fn idempotency_key(&self) -> Option<&str> {
+        match self {
+            Command::Increment { idempotency_key }
+            | Command::Decrement { idempotency_key }
+            | Command::SetCounter { idempotency_key, .. } => idempotency_key.as_deref(),
+            _ => None,
+        }
+    }
 
-async fn heat_command(doc_handle: &samod::DocHandle) -> Result<()> {
-    println!("\n🔥 Heating with smooth ease-in... (press Ctrl+C to stop)");
-    println!("Starting from 0°C, easing to 40°C\n");
-
-    // Set temperature to 0
-    doc_handle.with_document(|doc| -> Result<()> {
-        let mut state: Doc = hydrate(doc)?;
-        state.temperature = 0;
-        state.metadata.lastModified = Some(chrono::Utc::now().timestamp_millis());
-        doc.transact(|tx| {
-            reconcile(tx, &state)
-        })
-        .map_err(|e| anyhow::anyhow!("Failed to reconcile document: {:?}", e))?;
-        Ok(())
-    })?;
-
-    println!("🌡️  Temperature: 0°C");
-    sleep(Duration::from_millis(200)).await;
-
-    // Ease-in animation: slow at start, fast at end
-    let target_temp = 40.0;
-    let duration_ms = 8000.0; // 8 seconds total
-    let start_time = std::time::Instant::now();
+    /// The largest single text insert this command may reconcile (`--max-change-size`)
+    fn max_change_size(&self) -> Option<usize> {
+        match self {
+            Command::AddNote { max_change_size, .. }
+            | Command::SetNotes { max_change_size, .. }
+            | Command::SetNotesFromFile { max_change_size, .. } => *max_change_size,
+            _ => None,
+        }
+    }
 
-    loop {
-        let elapsed_ms = start_time.elapsed().as_millis() as f64;
-        let progress = (elapsed_ms / duration_ms).min(1.0);
+    /// The top-level fields a mutating command writes, for advisory lock checks
+    fn touched_fields(&self) -> Vec<Field> {
+        match self {
+            Command::Increment { .. } | Command::Decrement { .. } | Command::SetCounter { .. } => {
+                vec![Field::Counter]
+            }
+            Command::SetTemp { .. }
+            | Command::Heat { .. }
+            | Command::Cool { .. }
+            | Command::AnimateTemp { .. } => vec![Field::Temperature],
+            Command::ToggleDark | Command::SetDark { .. } => vec![Field::DarkMode],
+            Command::AddNote { .. }
+            | Command::ClearNotes
+            | Command::SetNotes { .. }
+            | Command::SetNotesFromFile { .. }
+            | Command::InsertNotes { .. }
+            | Command::DeleteNotes { .. } => vec![Field::Notes],
+            Command::SetCode { .. } | Command::AppendCode { .. } => vec![Field::Code],
+            Command::AddTodo { .. }
+            | Command::ImportTodos { .. }
+            | Command::ToggleTodo { .. }
+            | Command::EditTodo { .. }
+            | Command::DeleteTodo { .. }
+            | Command::ClearCompleted
+            | Command::BulkTag { .. } => vec![Field::Todos],
+            Command::Attach { .. } => vec![Field::Attachments],
+            Command::AddTag { .. } | Command::RemoveTag { .. } | Command::Dedupe { .. } => {
+                vec![Field::Tags]
+            }
+            Command::SetTitle { .. } | Command::Label { .. } => vec![Field::Metadata],
+            Command::AddSection { .. } | Command::EditSection { .. } => vec![Field::Sections],
+            Command::SetSetting { .. } | Command::UnsetSetting { .. } => vec![Field::Settings],
+            Command::Set { path, .. } => {
+                let top = path.split('.').next().unwrap_or(path);
+                <Field as clap::ValueEnum>::from_str(top, true)
+                    .into_iter()
+                    .collect()
+            }
+            // Any field may be in the file
+            Command::ImportJson { .. } => <Field as clap::ValueEnum>::value_variants().to_vec(),
+            _ => Vec::new(),
+        }
+    }
+}
 
-        // Ease-in quadratic: slow start, fast end
-        let eased = progress.powf(2.0);
-        let new_temp = (eased * target_temp).round() as i64;
-
-        if new_temp >= 40 || progress >= 1.0 {
-            // Final update to exactly 40
-            doc_handle.with_document(|doc| -> Result<()> {
-                let mut state: Doc = hydrate(doc)?;
-                state.temperature = 40;
-                state.metadata.lastModified = Some(chrono::Utc::now().timestamp_millis());
-                doc.transact(|tx| {
-                    reconcile(tx, &state)
-                })
-                .map_err(|e| anyhow::anyhow!("Failed to reconcile document: {:?}", e))?;
-                Ok(())
-            })?;
-            println!("🌡️  Temperature: 40°C");
-            println!("🔥 Maximum temperature reached!");
-            break;
+/// Apply `--strict-utf8` to the text a command is about to write, reporting anything stripped
+fn screen_command(command: &mut Command, policy: ControlChars) -> Result<()> {
+    let inputs: Vec<(&str, &mut String)> = match command {
+        Command::AddNote { text, .. }
+        | Command::SetNotes { text, .. }
+        | Command::InsertNotes { text, .. } => vec![("notes", text)],
+        Command::SetCode { text } | Command::AppendCode { text } => vec![("code", text)],
+        Command::AddTodo { text } | Command::EditTodo { text, .. } => vec![("todo", text)],
+        Command::AddTag { tag } | Command::BulkTag { tag, .. } => vec![("tag", tag)],
+        Command::SetTitle { title } => vec![("metadata.title", title)],
+        Command::Label { label } => vec![("metadata.title", label)],
+        Command::AddSection { name } => vec![("section name", name)],
+        Command::EditSection { name, text } => vec![("section name", name), ("section", text)],
+        Command::SetSetting { key, value } => vec![("setting key", key), ("setting value", value)],
+        Command::Set { path, value } => vec![(path.as_str(), value)],
+        _ => Vec::new(),
+    };
+    for (path, text) in inputs {
+        let (screened, stripped) = screen_text(path, text, policy)?;
+        if !stripped.is_empty() {
+            eprintln!(
+                "⚠️  Stripped {} control character(s) from {}: {}",
+                stripped.len(),
+                path,
+                describe_chars(&stripped)
+            );
         }
+        *text = screened;
+    }
+    Ok(())
+}
 
-        // Update temperature
-        doc_handle.with_document(|doc| -> Result<()> {
-            let mut state: Doc = hydrate(doc)?;
-            if state.temperature != new_temp {
-                state.temperature = new_temp;
-                state.metadata.lastModified = Some(chrono::Utc::now().timestamp_millis());
-                doc.transact(|tx| {
-                    reconcile(tx, &state)
-                })
-                .map_err(|e| anyhow::anyhow!("Failed to reconcile document: {:?}", e))?;
+/// Refuse commands that touch a field someone other than `user` has locked
+fn check_locks(state: &Doc, command: &Command, user: &str) -> Result<()> {
+    for field in command.touched_fields() {
+        if let Some(owner) = state.metadata.locks.get(field.name()) {
+            if owner != user {
+                anyhow::bail!(
+                    "{} is locked by {} (pass --force to override)",
+                    field.name(),
+                    owner
+                );
             }
-            Ok(())
-        })?;
-
-        let current_temp: i64 = doc_handle.with_document(|doc| -> Result<i64> {
-            let data: Doc = hydrate(doc)?;
-            Ok(data.temperature)
-        })?;
+        }
+    }
+    Ok(())
+}
 
-        println!("🌡️  Temperature: {}°C", current_temp);
+/// Look up a text encoding by its WHATWG label (`utf-8`, `latin1`, `shift_jis`, ...)
+fn parse_encoding(label: &str) -> Result<&'static encoding_rs::Encoding, String> {
+    encoding_rs::Encoding::for_label(label.trim().as_bytes())
+        .ok_or_else(|| format!("unknown encoding '{}'", label))
+}
 
-        sleep(Duration::from_millis(100)).await;
+/// Read a text file in `encoding`, failing on bytes that aren't valid in it
+fn read_encoded(path: &std::path::Path, encoding: &'static encoding_rs::Encoding) -> Result<String> {
+    let bytes = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let (text, _, had_errors) = encoding.decode(&bytes);
+    if had_errors {
+        anyhow::bail!("{} is not valid {} (try --encoding)", path.display(), encoding.name());
     }
+    Ok(text.into_owned())
+}
 
-    println!("\n📄 Final state:");
-    let doc_data: Doc = doc_handle.with_document(|doc| {
-        hydrate(doc).context("Failed to hydrate document")
-    })?;
-    doc_data.display();
-
-    Ok(())
+/// Encode `text` for writing, failing on characters `encoding` can't represent
+fn encode_text(text: &str, encoding: &'static encoding_rs::Encoding) -> Result<Vec<u8>> {
+    let (bytes, _, unmappable) = encoding.encode(text);
+    if unmappable {
+        anyhow::bail!("Notes contain characters that can't be written as {}", encoding.name());
+    }
+    Ok(bytes.into_owned())
 }
 
-async fn execute_command(doc_handle: &samod::DocHandle, command: &Command) -> Result<()> {
-    doc_handle.with_document(|doc| -> Result<()> {
-        // Hydrate current state from document
-        let mut state: Doc = hydrate(doc).context("Failed to hydrate document state")?;
+/// Best-effort MIME type from a file's extension
+fn guess_mime(path: &std::path::Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("webp") => "image/webp",
+        Some("pdf") => "application/pdf",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain",
+        Some("md") => "text/markdown",
+        Some("csv") => "text/csv",
+        _ => "application/octet-stream",
+    }
+}
 
-        // Apply command to local state
-        match command {
-            Command::Increment => {
-                state.counter += 1;
-                state.metadata.lastModified = Some(chrono::Utc::now().timestamp_millis());
-                tracing::debug!("Incremented counter to {}", state.counter);
-            }
-            Command::Decrement => {
-                state.counter -= 1;
-                state.metadata.lastModified = Some(chrono::Utc::now().timestamp_millis());
-                tracing::debug!("Decremented counter to {}", state.counter);
-            }
-            Command::SetCounter { value } => {
-                state.counter = *value;
-                state.metadata.lastModified = Some(chrono::Utc::now().timestamp_millis());
-                tracing::debug!("Set counter to {}", value);
-            }
-            Command::SetTemp { value } => {
-                let temp = (*value).clamp(0, 40);
-                state.temperature = temp;
-                state.metadata.lastModified = Some(chrono::Utc::now().timestamp_millis());
-                tracing::debug!("Set temperature to {}°C", temp);
-            }
-            Command::Heat => {
-                // Handled specially in heat_command() function
-                tracing::debug!("Heat command - handled separately");
-            }
-            Command::ToggleDark => {
-                state.darkMode = !state.darkMode;
-                state.metadata.lastModified = Some(chrono::Utc::now().timestamp_millis());
-                tracing::debug!("Toggled dark mode to {}", state.darkMode);
-            }
-            Command::SetDark { enabled } => {
-                state.darkMode = *enabled;
-                state.metadata.lastModified = Some(chrono::Utc::now().timestamp_millis());
-                tracing::debug!("Set dark mode to {}", enabled);
-            }
-            Command::AddNote { text } => {
-                if state.notes.as_str().is_empty() {
-                    state.notes.splice(0, 0, text);
-                } else {
-                    let len = state.notes.as_str().len();
-                    state.notes.splice(len, 0, &format!("\n{}", text));
-                }
-                state.metadata.lastModified = Some(chrono::Utc::now().timestamp_millis());
-                tracing::debug!("Added note");
-            }
-            Command::ClearNotes => {
-                let len = state.notes.as_str().len();
-                state.notes.splice(0, len as isize, "");
-                state.metadata.lastModified = Some(chrono::Utc::now().timestamp_millis());
-                tracing::debug!("Cleared notes");
-            }
-            Command::SetNotes { text } => {
-                let len = state.notes.as_str().len();
-                state.notes.splice(0, len as isize, text);
-                state.metadata.lastModified = Some(chrono::Utc::now().timestamp_millis());
-                tracing::debug!("Set notes to: {}", text);
-            }
-            Command::InsertNotes { position, text } => {
-                let char_count = state.notes.as_str().chars().count();
-                let insert_pos = (*position).min(char_count);
-                state.notes.splice(insert_pos, 0, text);
-                state.metadata.lastModified = Some(chrono::Utc::now().timestamp_millis());
-                tracing::debug!("Inserted '{}' at character position {}", text, insert_pos);
-            }
-            Command::DeleteNotes { start, length } => {
-                let char_count = state.notes.as_str().chars().count();
-                let start_char = (*start).min(char_count);
-                let delete_length = (*length).min(char_count - start_char);
+/// Copy `path` into `dir` under a fresh id, returning the stored attachment
+/// and where the copy is
+fn store_attachment(
+    path: &std::path::Path,
+    dir: &std::path::Path,
+    todo_id: &str,
+) -> Result<(Attachment, std::path::PathBuf)> {
+    let id = new_id();
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("{} is not a file", path.display()))?;
 
-                if delete_length > 0 {
-                    state.notes.splice(start_char, delete_length as isize, "");
-                    state.metadata.lastModified = Some(chrono::Utc::now().timestamp_millis());
-                    tracing::debug!("Deleted {} characters from position {}", delete_length, start_char);
-                }
-            }
-            Command::AddTodo { text } => {
-                let counter = TODO_ID_COUNTER.fetch_add(1, Ordering::SeqCst);
-                let id_str = format!("{}-{}", chrono::Utc::now().timestamp_millis(), counter);
-                let todo = TodoItem {
-                    id: autosurgeon::Text::with_value(&id_str),
-                    text: autosurgeon::Text::with_value(text),
-                    completed: false,
-                };
-                state.todos.push(todo);
-                state.metadata.lastModified = Some(chrono::Utc::now().timestamp_millis());
-                tracing::debug!("Added todo: {}", text);
-            }
-            Command::ToggleTodo { id } => {
-                if let Some(todo) = state.todos.iter_mut().find(|t| t.id.as_str().starts_with(id)) {
-                    todo.completed = !todo.completed;
-                    state.metadata.lastModified = Some(chrono::Utc::now().timestamp_millis());
-                    tracing::debug!("Toggled todo {}", id);
-                } else {
-                    tracing::warn!("Todo {} not found", id);
-                }
-            }
-            Command::DeleteTodo { id } => {
-                if let Some(pos) = state.todos.iter().position(|t| t.id.as_str().starts_with(id)) {
-                    state.todos.remove(pos);
-                    state.metadata.lastModified = Some(chrono::Utc::now().timestamp_millis());
-                    tracing::debug!("Deleted todo {}", id);
-                } else {
-                    tracing::warn!("Todo {} not found", id);
-                }
-            }
-            Command::AddTag { tag } => {
-                if !state.tags.iter().any(|t| t == tag) {
-                    state.tags.push(tag.clone());
-                    state.metadata.lastModified = Some(chrono::Utc::now().timestamp_millis());
-                    tracing::debug!("Added tag: {}", tag);
-                } else {
-                    tracing::debug!("Tag '{}' already exists", tag);
-                }
-            }
-            Command::RemoveTag { tag } => {
-                if let Some(pos) = state.tags.iter().position(|t| t == tag) {
-                    state.tags.remove(pos);
-                    state.metadata.lastModified = Some(chrono::Utc::now().timestamp_millis());
-                    tracing::debug!("Removed tag: {}", tag);
-                } else {
-                    tracing::warn!("Tag '{}' not found", tag);
-                }
-            }
-            Command::SetTitle { title } => {
-                state.metadata.title = Some(autosurgeon::Text::with_value(title));
-                state.metadata.lastModified = Some(chrono::Utc::now().timestamp_millis());
-                tracing::debug!("Set title to: {}", title);
-            }
-            Command::Show { .. } => {
-                // No changes needed
-            }
-        }
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+    let stored = dir.join(format!("{}-{}", id, file_name.to_string_lossy()));
+    std::fs::copy(path, &stored)
+        .with_context(|| format!("Failed to copy {} to {}", path.display(), stored.display()))?;
+    let stored = std::fs::canonicalize(&stored)?;
 
-        // Reconcile changes back to document
-        doc.transact(|tx| {
-            reconcile(tx, &state)
-        })
-        .map_err(|e| anyhow::anyhow!("Failed to reconcile document: {:?}", e))?;
+    let attachment = Attachment {
+        id,
+        mime: guess_mime(path).to_string(),
+        url: format!("file://{}", stored.display()),
+        todoId: Some(todo_id.to_string()),
+    };
+    Ok((attachment, stored))
+}
 
-        Ok(())
-    })?;
+/// Resolve who is running the command: --user, then $AUTODASH_USER, then $USER
+fn current_user(user: Option<&str>) -> String {
+    user.map(str::to_string)
+        .or_else(|| std::env::var("AUTODASH_USER").ok())
+        .or_else(|| std::env::var("USER").ok())
+        .unwrap_or_else(|| "anonymous".to_string())
+}
 
-    Ok(())
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum WatchRender {
+    Plain,
+    Tui,
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let cli = Cli::parse();
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum HistoryFormat {
+    Table,
+    Json,
+    Csv,
+}
 
-    // Initialize logging based on verbose flag
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            if cli.verbose {
-                tracing_subscriber::EnvFilter::new("debug")
-            } else {
-                tracing_subscriber::EnvFilter::try_from_default_env()
-                    .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("warn"))
-            },
-        )
-        .init();
+#[derive(Debug, serde::Serialize)]
+struct HistoryEntry {
+    actor: String,
+    timestamp: i64,
+    message: String,
+    op_count: usize,
+    hash: String,
+}
 
-    let doc_url = &cli.doc_url;
-    let command = cli.command.unwrap_or(Command::Show { field: None });
+impl HistoryEntry {
+    fn from_change(change: &automerge::Change) -> Self {
+        Self {
+            actor: change.actor_id().to_string(),
+            timestamp: change.timestamp(),
+            message: change.message().cloned().unwrap_or_default(),
+            op_count: change.len(),
+            hash: change.hash().to_string(),
+        }
+    }
+}
 
-    // Parse the automerge URL - accept both plain URLs and browser URLs
-    let doc_id_str = if let Some(hash_pos) = doc_url.find("#automerge:") {
-        // Extract from browser URL: http://localhost:5173/#automerge:DOCID
-        &doc_url[hash_pos + 11..] // Skip "#automerge:"
-    } else if doc_url.starts_with("automerge:") {
-        // Plain automerge URL: automerge:DOCID
-        doc_url.strip_prefix("automerge:").unwrap()
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
     } else {
-        anyhow::bail!(
-            "URL must contain 'automerge:' or '#automerge:' - got: {}",
-            doc_url
-        );
-    };
+        value.to_string()
+    }
+}
 
-    tracing::debug!("Initializing automerge-repo");
+/// Column header for line-oriented history formats
+fn history_header(format: HistoryFormat) -> Option<String> {
+    match format {
+        HistoryFormat::Table => Some(format!(
+            "{:<10} {:<10} {:<20} {:>5}  {}\n",
+            "HASH", "ACTOR", "TIME", "OPS", "MESSAGE"
+        )),
+        HistoryFormat::Csv => Some("actor,timestamp,message,op_count,hash\n".to_string()),
+        HistoryFormat::Json => None,
+    }
+}
 
-    // Create a repo with in-memory storage
-    let repo = samod::Repo::build_tokio()
-        .with_storage(
-            samod::storage::TokioFilesystemStorage::new("./autodash-data/")
+/// A single history line; JSON rows are compact so they can be streamed
+fn history_row(entry: &HistoryEntry, format: HistoryFormat) -> Result<String> {
+    Ok(match format {
+        HistoryFormat::Table => {
+            // Change timestamps are seconds since the epoch; 0 means unset
+            let time = chrono::DateTime::from_timestamp(entry.timestamp, 0)
+                .filter(|_| entry.timestamp != 0)
+                .map(|dt| dt.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_else(|| "-".to_string());
+            format!(
+                "{:<10} {:<10} {:<20} {:>5}  {}\n",
+                &entry.hash[..8.min(entry.hash.len())],
+                &entry.actor[..8.min(entry.actor.len())],
+                time,
+                entry.op_count,
+                entry.message
             )
-        .load()
-        .await;
-
-    tracing::debug!("Connecting to sync server");
-
-    // Connect to WebSocket server using tokio-tungstenite
-    let (ws_stream, _) = connect_async("ws://localhost:3030")
-        .await
-        .context("Failed to connect to WebSocket server")?;
+        }
+        HistoryFormat::Json => format!("{}\n", to_json_string(entry, Some(false))?),
+        HistoryFormat::Csv => format!(
+            "{},{},{},{},{}\n",
+            csv_field(&entry.actor),
+            entry.timestamp,
+            csv_field(&entry.message),
+            entry.op_count,
+            entry.hash
+        ),
+    })
+}
 
-    tracing::debug!("WebSocket connected");
+fn render_history(
+    entries: &[HistoryEntry],
+    format: HistoryFormat,
+    json_pretty: Option<bool>,
+) -> Result<String> {
+    if let HistoryFormat::Json = format {
+        return Ok(format!("{}\n", to_json_string(entries, json_pretty)?));
+    }
+    let mut out = history_header(format).unwrap_or_default();
+    for entry in entries {
+        out.push_str(&history_row(entry, format)?);
+    }
+    Ok(out)
+}
 
-    let (ws_sink, ws_stream) = ws_stream.split();
+/// Automerge's name for a value's type, e.g. `Text` or `Int`
+fn type_name(value: &automerge::Value) -> &'static str {
+    use automerge::{ObjType, ScalarValue, Value};
+    match value {
+        Value::Object(ObjType::Map) => "Map",
+        Value::Object(ObjType::Table) => "Table",
+        Value::Object(ObjType::List) => "List",
+        Value::Object(ObjType::Text) => "Text",
+        Value::Scalar(s) => match &**s {
+            ScalarValue::Bytes(_) => "Bytes",
+            ScalarValue::Str(_) => "Str",
+            ScalarValue::Int(_) => "Int",
+            ScalarValue::Uint(_) => "Uint",
+            ScalarValue::F64(_) => "F64",
+            ScalarValue::Counter(_) => "Counter",
+            ScalarValue::Timestamp(_) => "Timestamp",
+            ScalarValue::Boolean(_) => "Boolean",
+            ScalarValue::Unknown { .. } => "Unknown",
+            ScalarValue::Null => "Null",
+        },
+    }
+}
 
-    // Create channels to bridge WebSocket and samod
-    let (to_samod_tx, to_samod_rx) = futures::channel::mpsc::unbounded::<Vec<u8>>();
-    let (from_samod_tx, from_samod_rx) = futures::channel::mpsc::unbounded::<Vec<u8>>();
+/// Automerge's name for a value's type, with its size or value, e.g. `Text (12)` or `Int 5`
+fn describe_value<D: automerge::ReadDoc>(doc: &D, value: &automerge::Value, obj: &automerge::ObjId) -> String {
+    match value {
+        automerge::Value::Object(_) => format!("{} ({})", type_name(value), doc.length(obj)),
+        automerge::Value::Scalar(s) => format!("{} {}", type_name(value), s),
+    }
+}
 
-    // Forward WebSocket messages to samod
-    let ws_to_samod = async move {
-        let mut stream = ws_stream;
-        while let Some(msg) = stream.next().await {
-            match msg {
-                Ok(Message::Binary(data)) => {
-                    if to_samod_tx.unbounded_send(data).is_err() {
-                        break;
-                    }
-                }
-                Ok(Message::Close(_)) => break,
-                Ok(_) => {} // Ignore text/ping/pong
-                Err(e) => {
-                    tracing::warn!("WebSocket error: {}", e);
-                    break;
+/// The entries of a map (by key) or list (as `[i]`); other objects have none
+fn tree_children<'a, D: automerge::ReadDoc>(
+    doc: &'a D,
+    obj: &automerge::ObjId,
+) -> Result<Vec<(String, automerge::Value<'a>, automerge::ObjId)>> {
+    use automerge::ObjType;
+    let mut children = Vec::new();
+    match doc.object_type(obj) {
+        Ok(ObjType::Map | ObjType::Table) => {
+            for key in doc.keys(obj) {
+                if let Some((value, child)) = doc.get(obj, key.as_str())? {
+                    children.push((key, value, child));
                 }
             }
         }
-    };
-
-    // Forward samod messages to WebSocket
-    let samod_to_ws = async move {
-        use futures_util::SinkExt;
-        let mut rx = from_samod_rx;
-        let mut sink = ws_sink;
-        while let Some(bytes) = rx.next().await {
-            if sink.send(Message::Binary(bytes)).await.is_err() {
-                break;
+        Ok(ObjType::List) => {
+            for index in 0..doc.length(obj) {
+                if let Some((value, child)) = doc.get(obj, index)? {
+                    children.push((format!("[{}]", index), value, child));
+                }
             }
         }
-    };
+        _ => {}
+    }
+    Ok(children)
+}
 
-    // Spawn the connection handling tasks
-    let ws_to_samod_handle = tokio::spawn(ws_to_samod);
-    let samod_to_ws_handle = tokio::spawn(samod_to_ws);
-
-    // Connect the repo to the sync server
-    let repo_clone = repo.clone();
-    tokio::spawn(async move {
-        let result = repo_clone
-            .connect(
-                to_samod_rx.map(Ok::<_, Infallible>),
-                from_samod_tx,
-                samod::ConnDirection::Outgoing,
-            )
-            .await;
+/// Whether a node at `depth` (top-level keys are depth 1) may show its children
+fn within_depth(depth: usize, max_depth: Option<usize>) -> bool {
+    !matches!(max_depth, Some(max) if depth >= max)
+}
 
-        tracing::debug!("Sync connection finished: {:?}", result);
-    });
+/// The whole document as an indented tree of keys, types and values, ignoring the schema
+fn render_tree<D: automerge::ReadDoc>(doc: &D, max_depth: Option<usize>) -> Result<String> {
+    fn walk<D: automerge::ReadDoc>(
+        doc: &D,
+        obj: &automerge::ObjId,
+        depth: usize,
+        max_depth: Option<usize>,
+        out: &mut String,
+    ) -> Result<()> {
+        use automerge::{ObjType, Value};
+        for (label, value, child) in tree_children(doc, obj)? {
+            let indent = "  ".repeat(depth - 1);
+            out.push_str(&format!("{}{}: {}", indent, label, describe_value(doc, &value, &child)));
+            if let Value::Object(ObjType::Text) = value {
+                out.push_str(&format!(" {:?}", doc.text(&child)?));
+            }
+            out.push('\n');
+            if matches!(value, Value::Object(_)) && within_depth(depth, max_depth) {
+                walk(doc, &child, depth + 1, max_depth, out)?;
+            }
+        }
+        Ok(())
+    }
 
-    tracing::debug!("Loading document: automerge:{}", doc_id_str);
+    let mut out = String::new();
+    walk(doc, &automerge::ROOT, 1, max_depth, &mut out)?;
+    if out.is_empty() {
+        out.push_str("(empty document)\n");
+    }
+    Ok(out)
+}
 
-    // Create DocumentId from string
-    let doc_id: samod::DocumentId = doc_id_str.parse()?;
+/// The tree as nested JSON: every node is `{"type": ..., "value": ...}`, with maps as
+/// objects and lists as arrays of nodes. Objects past `--max-depth` carry only their length.
+fn tree_json<D: automerge::ReadDoc>(
+    doc: &D,
+    value: &automerge::Value,
+    obj: &automerge::ObjId,
+    depth: usize,
+    max_depth: Option<usize>,
+) -> Result<serde_json::Value> {
+    use automerge::{ObjType, ScalarValue, Value};
+    use serde_json::json;
+    let inner = match value {
+        Value::Scalar(s) => match &**s {
+            ScalarValue::Str(s) => json!(s.as_str()),
+            ScalarValue::Int(_) | ScalarValue::Counter(_) | ScalarValue::Timestamp(_) => json!(s.to_i64()),
+            ScalarValue::Uint(n) => json!(n),
+            ScalarValue::F64(f) => json!(f),
+            ScalarValue::Boolean(b) => json!(b),
+            ScalarValue::Null => serde_json::Value::Null,
+            ScalarValue::Bytes(_) | ScalarValue::Unknown { .. } => json!(s.to_string()),
+        },
+        Value::Object(ObjType::Text) => json!(doc.text(obj)?),
+        Value::Object(_) if !within_depth(depth, max_depth) => {
+            return Ok(json!({ "type": type_name(value), "length": doc.length(obj) }));
+        }
+        Value::Object(ObjType::List) => {
+            let mut items = Vec::new();
+            for (_, child_value, child) in tree_children(doc, obj)? {
+                items.push(tree_json(doc, &child_value, &child, depth + 1, max_depth)?);
+            }
+            serde_json::Value::Array(items)
+        }
+        Value::Object(ObjType::Map | ObjType::Table) => {
+            let mut entries = serde_json::Map::new();
+            for (key, child_value, child) in tree_children(doc, obj)? {
+                entries.insert(key, tree_json(doc, &child_value, &child, depth + 1, max_depth)?);
+            }
+            serde_json::Value::Object(entries)
+        }
+    };
+    Ok(json!({ "type": type_name(value), "value": inner }))
+}
 
-    // Try to find the document (may return None if not synced yet)
-    tracing::debug!("Looking for document...");
-    let mut doc_handle = repo.find(doc_id.clone()).await?;
+/// Each top-level key with its type, optionally followed by the entries of maps and lists
+fn list_fields<D: automerge::ReadDoc>(doc: &D, recursive: bool) -> Result<String> {
+    use automerge::{ObjType, Value};
+    let mut out = String::new();
+    for key in doc.keys(automerge::ROOT) {
+        let Some((value, obj)) = doc.get(automerge::ROOT, key.as_str())? else {
+            continue;
+        };
+        out.push_str(&format!("{:<16} {}\n", key, describe_value(doc, &value, &obj)));
+        if !recursive {
+            continue;
+        }
+        match value {
+            Value::Object(ObjType::Map | ObjType::Table) => {
+                for child in doc.keys(&obj) {
+                    if let Some((v, o)) = doc.get(&obj, child.as_str())? {
+                        out.push_str(&format!("  {:<14} {}\n", child, describe_value(doc, &v, &o)));
+                    }
+                }
+            }
+            Value::Object(ObjType::List) => {
+                for index in 0..doc.length(&obj) {
+                    if let Some((v, o)) = doc.get(&obj, index)? {
+                        let label = format!("[{}]", index);
+                        out.push_str(&format!("  {:<14} {}\n", label, describe_value(doc, &v, &o)));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if out.is_empty() {
+        out.push_str("(empty document)\n");
+    }
+    Ok(out)
+}
+
+/// Like `tail -f` for the change log: print the existing history, then each
+/// change as it arrives. Runs until the document's change stream ends.
+async fn tail_history_command(
+    doc_handle: &samod::DocHandle,
+    format: HistoryFormat,
+    poll_interval: Duration,
+) -> Result<()> {
+    let mut changes = Box::pin(change_notifications(doc_handle, poll_interval));
+
+    // Backfill everything we already have
+    let (entries, mut last_heads) = doc_handle.with_document(|doc| {
+        let entries: Vec<HistoryEntry> = doc
+            .get_changes(&[])
+            .iter()
+            .map(|change| HistoryEntry::from_change(change))
+            .collect();
+        (entries, doc.get_heads())
+    });
+    if let Some(header) = history_header(format) {
+        print!("{}", header);
+    }
+    for entry in &entries {
+        print!("{}", history_row(entry, format)?);
+    }
+
+    // Then follow: each notification, print whatever is new since the last heads we saw
+    while changes.next().await.is_some() {
+        let (entries, heads) = doc_handle.with_document(|doc| {
+            let entries: Vec<HistoryEntry> = doc
+                .get_changes(&last_heads)
+                .iter()
+                .map(|change| HistoryEntry::from_change(change))
+                .collect();
+            (entries, doc.get_heads())
+        });
+        last_heads = heads;
+        for entry in &entries {
+            print!("{}", history_row(entry, format)?);
+        }
+    }
+
+    Ok(())
+}
+
+/// Print the dashboard now and again after every change that alters what it shows
+async fn watch_plain(
+    doc_handle: &samod::DocHandle,
+    options: &RenderOptions,
+    poll_interval: Duration,
+) -> Result<()> {
+    let mut changes = Box::pin(change_notifications(doc_handle, poll_interval));
+    let mut last: Option<Doc> = None;
+    loop {
+        let state: Doc =
+            doc_handle.with_document(|doc| hydrate(doc).context("Failed to hydrate document"))?;
+        if !last.as_ref().is_some_and(|last| state.same_content(last)) {
+            state.display_with(options);
+            last = Some(state);
+        }
+        if changes.next().await.is_none() {
+            return Ok(());
+        }
+    }
+}
+
+/// Keep the dashboard on a full-screen, read-only view, redrawn live until q, Esc or Ctrl+C
+async fn watch_tui(
+    doc_handle: &samod::DocHandle,
+    options: &RenderOptions,
+    poll_interval: Duration,
+) -> Result<()> {
+    let mut terminal = setup_terminal(false)?;
+    let result = watch_tui_loop(&mut terminal, doc_handle, options, poll_interval).await;
+    restore_terminal(&mut terminal, false)?;
+    result
+}
+
+async fn watch_tui_loop(
+    terminal: &mut Tui,
+    doc_handle: &samod::DocHandle,
+    options: &RenderOptions,
+    poll_interval: Duration,
+) -> Result<()> {
+    use crossterm::event::{self, Event, KeyCode, KeyModifiers};
+    use ratatui::widgets::{Block, Borders, Paragraph};
+
+    let mut changes = Box::pin(change_notifications(doc_handle, poll_interval));
+    let mut dirty = true;
+    loop {
+        if dirty {
+            let state: Doc = doc_handle
+                .with_document(|doc| hydrate(doc).context("Failed to hydrate document"))?;
+            let title = match state.metadata.title.as_ref().map(|t| t.as_str()) {
+                Some(title) if !title.is_empty() => format!(" {} — q to quit ", title),
+                _ => " watch — q to quit ".to_string(),
+            };
+            // Keep the box inside the frame's borders
+            let mut options = options.clone();
+            let cols = usize::from(terminal.size()?.width).saturating_sub(2);
+            options.width = Some(options.width.map_or(cols, |width| width.min(cols)));
+            let text = state.render(&options);
+            terminal.draw(|frame| {
+                let view = Paragraph::new(text.as_str())
+                    .block(Block::default().borders(Borders::ALL).title(title.as_str()));
+                frame.render_widget(view, frame.area());
+            })?;
+            dirty = false;
+        }
+
+        while event::poll(Duration::ZERO)? {
+            match event::read()? {
+                Event::Key(key) => {
+                    let ctrl_c = key.code == KeyCode::Char('c')
+                        && key.modifiers.contains(KeyModifiers::CONTROL);
+                    if ctrl_c || matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) {
+                        return Ok(());
+                    }
+                }
+                Event::Resize(..) => dirty = true,
+                _ => {}
+            }
+        }
+
+        match tokio::time::timeout(Duration::from_millis(100), changes.next()).await {
+            Ok(Some(())) => dirty = true,
+            Ok(None) => return Ok(()),
+            Err(_) => {}
+        }
+    }
+}
+
+/// Splice `insert` into `text` at byte offset `pos`, refusing it when it exceeds
+/// `max_bytes` and `reject_oversized` is set. Otherwise an oversized insert is
+/// split into several splice ops when reconciled; see [`reconcile_doc`].
+fn splice_bounded(
+    text: &mut autosurgeon::Text,
+    pos: usize,
+    delete: isize,
+    insert: &str,
+    max_bytes: Option<usize>,
+    reject_oversized: bool,
+) -> Result<()> {
+    match max_bytes {
+        Some(0) => anyhow::bail!("--max-change-size must be greater than zero"),
+        Some(max) if reject_oversized && insert.len() > max => anyhow::bail!(
+            "Insert of {} bytes exceeds --max-change-size of {} bytes",
+            insert.len(),
+            max
+        ),
+        _ => text.splice(pos, delete, insert),
+    }
+    Ok(())
+}
+
+/// Extract the document id from a plain `automerge:` URL or a browser URL.
+fn parse_doc_url(doc_url: &str) -> Result<&str> {
+    if let Some(hash_pos) = doc_url.find("#automerge:") {
+        // Extract from browser URL: http://localhost:5173/#automerge:DOCID
+        Ok(&doc_url[hash_pos + 11..]) // Skip "#automerge:"
+    } else if let Some(doc_id_str) = doc_url.strip_prefix("automerge:") {
+        // Plain automerge URL: automerge:DOCID
+        Ok(doc_id_str)
+    } else {
+        anyhow::bail!(
+            "URL must contain 'automerge:' or '#automerge:' - got: {}",
+            doc_url
+        );
+    }
+}
+
+struct DoctorCheck {
+    label: String,
+    ok: bool,
+    hint: Option<&'static str>,
+}
+
+fn dump_storage_command(storage_dir: &std::path::Path) -> Result<()> {
+    let documents = list_stored_documents(storage_dir)
+        .with_context(|| format!("Failed to read {}", storage_dir.display()))?;
+    if documents.is_empty() {
+        println!("No documents in {}", storage_dir.display());
+        return Ok(());
+    }
+
+    println!("📦 {} document(s) in {}:\n", documents.len(), storage_dir.display());
+    for document in &documents {
+        println!("automerge:{}", document.id);
+        println!("  Files: {} ({} bytes)", document.files.len(), document.size);
+        match document.load() {
+            Ok(doc) => println!("  Heads: {}", format_heads(&doc.get_heads())),
+            Err(e) => println!("  Heads: (unreadable: {})", e),
+        }
+    }
+    Ok(())
+}
+
+/// Parse a duration like `30d`, `12h`, `45m` or `90s`
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("expected a number followed by s, m, h or d, got '{}'", s))?;
+    let seconds = match unit {
+        "s" | "" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("unknown unit '{}', expected s, m, h or d", unit)),
+    };
+    Ok(Duration::from_secs(number * seconds))
+}
+
+fn gc_storage_command(
+    storage_dir: &std::path::Path,
+    older_than: Option<Duration>,
+    keep: &[String],
+    yes: bool,
+    dry_run: bool,
+) -> Result<()> {
+    if !yes && !dry_run {
+        anyhow::bail!("gc-storage deletes data: pass --yes to delete, or --dry-run to preview");
+    }
+
+    let now = std::time::SystemTime::now();
+    let documents = list_stored_documents(storage_dir)
+        .with_context(|| format!("Failed to read {}", storage_dir.display()))?;
+    let doomed: Vec<_> = documents
+        .iter()
+        .filter(|document| !keep.iter().any(|id| id.trim_start_matches("automerge:") == document.id))
+        .filter(|document| match (older_than, document.modified) {
+            (None, _) => true,
+            (Some(age), Some(modified)) => now.duration_since(modified).is_ok_and(|d| d >= age),
+            // Without a modification time we can't tell its age, so leave it alone
+            (Some(_), None) => false,
+        })
+        .collect();
+
+    let mut freed = 0;
+    for document in &doomed {
+        if dry_run {
+            println!("Would remove automerge:{} ({} bytes)", document.id, document.size);
+        } else {
+            std::fs::remove_dir_all(&document.dir)
+                .with_context(|| format!("Failed to remove {}", document.dir.display()))?;
+            println!("🗑️  Removed automerge:{} ({} bytes)", document.id, document.size);
+        }
+        freed += document.size;
+    }
+
+    let verb = if dry_run { "Would free" } else { "Freed" };
+    println!(
+        "\n{} {} bytes from {} of {} document(s)",
+        verb,
+        freed,
+        doomed.len(),
+        documents.len()
+    );
+    Ok(())
+}
+
+/// The parts of `whoami` that don't need a document
+fn print_session_info(persist: bool) {
+    println!("Server:  {}", ConnectOptions::default().server_url);
+    if persist {
+        let storage = std::path::Path::new(DEFAULT_STORAGE_DIR);
+        let storage = std::fs::canonicalize(storage).unwrap_or_else(|_| storage.to_path_buf());
+        println!("Storage: {}", storage.display());
+    } else {
+        println!("Storage: (in memory, --no-persist)");
+    }
+}
+
+async fn doctor_command(doc_url: Option<&str>, connect: &ConnectOptions) -> Result<()> {
+    println!("\n🩺 Autodash doctor\n");
+    let mut checks = Vec::new();
+    let server = &connect.server_url;
+
+    // 1. Sync server reachable, with the same handshake a real connection sends
+    let probe = tokio::time::timeout(Duration::from_secs(3), probe_server(connect)).await;
+    checks.push(match probe {
+        Ok(Ok(())) => DoctorCheck {
+            label: format!("Sync server reachable at {}", server),
+            ok: true,
+            hint: None,
+        },
+        Ok(Err(e)) => DoctorCheck {
+            label: format!("Sync server at {} refused the connection: {}", server, e),
+            ok: false,
+            hint: Some(match e {
+                AutodashError::AuthRequired { .. } | AutodashError::AuthRejected { .. } => {
+                    "Pass a valid token with --auth-token or set one in the config file"
+                }
+                _ => "Start it with: pnpx @automerge/automerge-repo-sync-server",
+            }),
+        },
+        Err(_) => DoctorCheck {
+            label: format!("Sync server at {} did not answer within 3s", server),
+            ok: false,
+            hint: Some("Check the server is running and not blocked by a firewall"),
+        },
+    });
+
+    // 2. Storage directory writable
+    let storage_dir = std::path::Path::new(DEFAULT_STORAGE_DIR);
+    let probe_file = storage_dir.join(".doctor-probe");
+    let writable = std::fs::create_dir_all(storage_dir)
+        .and_then(|_| std::fs::write(&probe_file, b"ok"))
+        .and_then(|_| std::fs::remove_file(&probe_file));
+    checks.push(match writable {
+        Ok(()) => DoctorCheck {
+            label: format!("Storage directory {} is writable", DEFAULT_STORAGE_DIR),
+            ok: true,
+            hint: None,
+        },
+        Err(e) => DoctorCheck {
+            label: format!("Storage directory {} is not writable: {}", DEFAULT_STORAGE_DIR, e),
+            ok: false,
+            hint: Some("Run from a directory you can write to, or fix its permissions"),
+        },
+    });
+
+    // 3. Document URL parses
+    match doc_url {
+        Some(doc_url) => {
+            let parsed = parse_doc_url(doc_url)
+                .and_then(|id| id.parse::<samod::DocumentId>().map_err(|e| anyhow::anyhow!("{:?}", e)));
+            checks.push(match parsed {
+                Ok(doc_id) => DoctorCheck {
+                    label: format!("Document URL parses (automerge:{})", doc_id),
+                    ok: true,
+                    hint: None,
+                },
+                Err(e) => DoctorCheck {
+                    label: format!("Document URL is invalid: {}", e),
+                    ok: false,
+                    hint: Some("Copy the URL from the browser address bar (after #automerge:)"),
+                },
+            });
+        }
+        None => println!("  -  Document URL not given, skipping parse check"),
+    }
+
+    // 4. Local samod storage loads
+    let mut files = Vec::new();
+    if storage_dir.exists() {
+        automerge_cli::storage::collect_files(storage_dir, &mut files)?;
+    }
+    let corrupt: Vec<_> = files
+        .iter()
+        .filter(|path| {
+            std::fs::read(path)
+                .map(|bytes| automerge::Automerge::load(&bytes).is_err())
+                .unwrap_or(true)
+        })
+        .collect();
+    checks.push(if corrupt.is_empty() {
+        DoctorCheck {
+            label: format!("Local storage is readable ({} files)", files.len()),
+            ok: true,
+            hint: None,
+        }
+    } else {
+        for path in &corrupt {
+            tracing::warn!("Unreadable storage file: {}", path.display());
+        }
+        DoctorCheck {
+            label: format!("{} of {} local storage files are corrupt", corrupt.len(), files.len()),
+            ok: false,
+            hint: Some("Remove ./autodash-data/ to resync documents from the server"),
+        }
+    });
+
+    for check in &checks {
+        println!("  {} {}", if check.ok { "✓" } else { "✗" }, check.label);
+        if let (false, Some(hint)) = (check.ok, check.hint) {
+            println!("      → {}", hint);
+        }
+    }
+
+    let failed = checks.iter().filter(|check| !check.ok).count();
+    if failed > 0 {
+        anyhow::bail!("{} check(s) failed", failed);
+    }
+    println!("\nAll checks passed.");
+    Ok(())
+}
+
+/// The sidecar file holding `path`'s checksum
+fn checksum_path(path: &std::path::Path) -> std::path::PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".sha256");
+    std::path::PathBuf::from(name)
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    use sha2::Digest;
+    format!("{:x}", sha2::Sha256::digest(bytes))
+}
+
+/// Refuse `bytes` read from `path` if a `.sha256` sidecar exists and doesn't match
+fn verify_checksum(path: &std::path::Path, bytes: &[u8]) -> Result<()> {
+    let sidecar = checksum_path(path);
+    let expected = match std::fs::read_to_string(&sidecar) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read {}", sidecar.display()))
+        }
+    };
+    // Same layout as sha256sum: `<hex>  <file name>`
+    let expected = expected.split_whitespace().next().unwrap_or("");
+    if !expected.eq_ignore_ascii_case(&sha256_hex(bytes)) {
+        anyhow::bail!(
+            "Checksum mismatch for {}: the file is corrupted or was modified after export",
+            path.display()
+        );
+    }
+    tracing::debug!("Checksum verified for {}", path.display());
+    Ok(())
+}
+
+/// A progress bar on stderr, or a hidden one under `--quiet` or when stderr isn't a terminal
+fn progress_bar(len: u64, template: &str, quiet: bool) -> indicatif::ProgressBar {
+    use std::io::IsTerminal;
+    if quiet || !std::io::stderr().is_terminal() {
+        return indicatif::ProgressBar::hidden();
+    }
+    let bar = indicatif::ProgressBar::new(len);
+    if let Ok(style) = indicatif::ProgressStyle::with_template(template) {
+        bar.set_style(style);
+    }
+    bar
+}
+
+/// Bytes written per progress bar update during export
+const EXPORT_CHUNK: usize = 64 * 1024;
+
+fn export_command(
+    doc_handle: &samod::DocHandle,
+    path: &std::path::Path,
+    since_heads: Option<&str>,
+    checksum: bool,
+    quiet: bool,
+) -> Result<()> {
+    let since = since_heads.map(parse_heads).transpose()?;
+    let (bytes, heads) = doc_handle.with_document(|doc| -> Result<_> {
+        let bytes = match &since {
+            Some(since) => {
+                if let Some(unknown) = since.iter().find(|h| doc.get_change_by_hash(h).is_none()) {
+                    anyhow::bail!("Change {} is not in this document's history", unknown);
+                }
+                doc.save_after(since)
+            }
+            None => doc.save(),
+        };
+        Ok((bytes, doc.get_heads()))
+    })?;
+
+    let bar = progress_bar(bytes.len() as u64, "{bar:40} {bytes}/{total_bytes}", quiet);
+    let write = || -> std::io::Result<()> {
+        use std::io::Write;
+        let mut file = std::fs::File::create(path)?;
+        for chunk in bytes.chunks(EXPORT_CHUNK) {
+            file.write_all(chunk)?;
+            bar.inc(chunk.len() as u64);
+        }
+        file.flush()
+    };
+    write().with_context(|| format!("Failed to write export to {}", path.display()))?;
+    bar.finish_and_clear();
+    if checksum {
+        let sidecar = checksum_path(path);
+        let name = path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+        std::fs::write(&sidecar, format!("{}  {}\n", sha256_hex(&bytes), name))
+            .with_context(|| format!("Failed to write {}", sidecar.display()))?;
+        println!("🔏 Wrote checksum to {}", sidecar.display());
+    }
+
+    let kind = if since.is_some() { "delta" } else { "full" };
+    println!("💾 Wrote {} byte {} export to {}", bytes.len(), kind, path.display());
+    // Record these to export the next delta with --since-heads
+    println!("Heads: {}", format_heads(&heads));
+    Ok(())
+}
+
+fn replay_command(
+    doc_handle: &samod::DocHandle,
+    patches: &[std::path::PathBuf],
+    quiet: bool,
+) -> Result<()> {
+    // Read everything up front so a missing file doesn't leave a half-applied replay
+    let contents = patches
+        .iter()
+        .map(|path| {
+            let bytes = std::fs::read(path)
+                .with_context(|| format!("Failed to read patch {}", path.display()))?;
+            verify_checksum(path, &bytes)?;
+            Ok(bytes)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    println!("\n⏯️  Replaying {} patch(es)", patches.len());
+    let bar = progress_bar(patches.len() as u64, "{bar:40} {pos}/{len} patches", quiet);
+    doc_handle.with_document(|doc| -> Result<()> {
+        for (path, bytes) in patches.iter().zip(&contents) {
+            // Already-applied changes are skipped, so duplicates apply 0 ops
+            let ops = doc
+                .load_incremental(bytes)
+                .map_err(|e| anyhow::anyhow!("Patch {} is invalid: {}", path.display(), e))?;
+            bar.suspend(|| println!("  ✓ {} ({} ops)", path.display(), ops));
+            bar.inc(1);
+        }
+        bar.finish_and_clear();
+
+        // Changes whose dependencies haven't arrived yet wait in a queue
+        let missing = doc.get_missing_deps(&[]);
+        if !missing.is_empty() {
+            println!(
+                "  ⚠️  {} change(s) are waiting on dependencies not in these patches",
+                missing.len()
+            );
+        }
+        Ok(())
+    })?;
+
+    let doc_data: Doc = doc_handle.with_document(|doc| {
+        hydrate(doc).context("Failed to hydrate document after replay")
+    })?;
+    doc_data.display();
+    Ok(())
+}
+
+/// Easing curves for temperature animations
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum Ease {
+    Linear,
+    /// Quadratic: slow start, fast end
+    In,
+    /// Quadratic: fast start, slow end
+    Out,
+    /// Slow at both ends
+    InOut,
+}
+
+impl Ease {
+    /// Map linear progress in `0.0..=1.0` onto the curve
+    fn apply(self, progress: f64) -> f64 {
+        match self {
+            Ease::Linear => progress,
+            Ease::In => progress.powf(2.0),
+            Ease::Out => 1.0 - (1.0 - progress).powf(2.0),
+            Ease::InOut if progress < 0.5 => 2.0 * progress.powf(2.0),
+            Ease::InOut => 1.0 - (2.0 - 2.0 * progress).powf(2.0) / 2.0,
+        }
+    }
+}
+
+/// A temperature animation from one value to another
+struct Sweep {
+    from: i64,
+    to: i64,
+    duration: Duration,
+    ease: Ease,
+}
+
+/// Animate the temperature along `sweep`, printing every tick and writing every
+/// `commit_every`th one. The first and last values are always written exactly.
+/// Each write stamps `metadata.lastModified` unless `stamp` is off (`--no-stamp`).
+async fn animate_temp(
+    doc_handle: &samod::DocHandle,
+    sweep: &Sweep,
+    commit_every: u64,
+    stamp: bool,
+) -> Result<()> {
+    let set_temperature = |temperature: i64| {
+        let update = |state: &mut Doc| state.temperature = temperature;
+        if stamp {
+            with_hydrated(doc_handle, update)
+        } else {
+            with_hydrated_unstamped(doc_handle, update)
+        }
+    };
+
+    set_temperature(sweep.from)?;
+
+    println!("🌡️  Temperature: {}°C", sweep.from);
+    sleep(Duration::from_millis(200)).await;
+
+    let span = (sweep.to - sweep.from) as f64;
+    let duration_ms = sweep.duration.as_millis().max(1) as f64;
+    let start_time = std::time::Instant::now();
+    let mut ticks: u64 = 0;
+
+    loop {
+        let elapsed_ms = start_time.elapsed().as_millis() as f64;
+        let progress = (elapsed_ms / duration_ms).min(1.0);
+        let new_temp = sweep.from + (sweep.ease.apply(progress) * span).round() as i64;
+
+        if new_temp == sweep.to || progress >= 1.0 {
+            // Final update to exactly the target
+            set_temperature(sweep.to)?;
+            println!("🌡️  Temperature: {}°C", sweep.to);
+            break;
+        }
+
+        // Update temperature on every Nth tick; the final write above is always exact.
+        // A failed tick is rolled back by the transaction, so log it and keep
+        // animating rather than abandoning the session.
+        ticks += 1;
+        if ticks % commit_every == 0 {
+            if let Err(e) = set_temperature(new_temp) {
+                tracing::error!("Skipping temperature update to {}°C: {:#}", new_temp, e);
+            }
+        }
+
+        println!("🌡️  Temperature: {}°C", new_temp);
+
+        sleep(Duration::from_millis(100)).await;
+    }
+
+    Ok(())
+}
+
+fn print_final_state(doc_handle: &samod::DocHandle) -> Result<()> {
+    println!("\n📄 Final state:");
+    let doc_data: Doc = doc_handle.with_document(|doc| {
+        hydrate(doc).context("Failed to hydrate document")
+    })?;
+    doc_data.display();
+    Ok(())
+}
+
+/// The `heat` preset: ease in from 0°C to 40°C over 8 seconds
+async fn heat_command(doc_handle: &samod::DocHandle, commit_every: u64, stamp: bool) -> Result<()> {
+    println!("\n🔥 Heating with smooth ease-in... (press Ctrl+C to stop)");
+    println!("Starting from 0°C, easing to 40°C\n");
+    let sweep = Sweep {
+        from: 0,
+        to: 40,
+        duration: Duration::from_secs(8),
+        ease: Ease::In,
+    };
+    animate_temp(doc_handle, &sweep, commit_every, stamp).await?;
+    println!("🔥 Maximum temperature reached!");
+    print_final_state(doc_handle)
+}
+
+/// The `cool` preset: ease out from the current temperature down to 0°C over 8 seconds
+async fn cool_command(doc_handle: &samod::DocHandle, commit_every: u64, stamp: bool) -> Result<()> {
+    let current: Doc = doc_handle.with_document(|doc| {
+        hydrate(doc).context("Failed to hydrate document")
+    })?;
+    println!("\n❄️  Cooling with smooth ease-out... (press Ctrl+C to stop)");
+    println!("Starting from {}°C, easing to 0°C\n", current.temperature);
+    let sweep = Sweep {
+        from: current.temperature,
+        to: 0,
+        duration: Duration::from_secs(8),
+        ease: Ease::Out,
+    };
+    animate_temp(doc_handle, &sweep, commit_every, stamp).await?;
+    println!("❄️  Minimum temperature reached!");
+    print_final_state(doc_handle)
+}
+
+/// The heads the document had before its latest change(s): the dependencies of its current heads
+fn previous_heads(doc: &automerge::Automerge) -> Vec<automerge::ChangeHash> {
+    let mut previous = Vec::new();
+    for head in doc.get_heads() {
+        if let Some(change) = doc.get_change_by_hash(&head) {
+            for dep in change.deps() {
+                if !previous.contains(dep) {
+                    previous.push(*dep);
+                }
+            }
+        }
+    }
+    previous
+}
+
+/// Create a new document from `doc_handle`'s state at `to`, returning its handle
+async fn rewind_command(
+    repo: &samod::Repo,
+    doc_handle: &samod::DocHandle,
+    to: &str,
+) -> Result<samod::DocHandle> {
+    let heads = parse_heads(to)?;
+    if heads.is_empty() {
+        anyhow::bail!("--to requires at least one change hash");
+    }
+
+    // Fork at the requested heads, leaving the original document untouched
+    let forked = doc_handle.with_document(|doc| -> Result<automerge::Automerge> {
+        for head in &heads {
+            if doc.get_change_by_hash(head).is_none() {
+                anyhow::bail!("Change {} is not in this document's history", head);
+            }
+        }
+        doc.fork_at(&heads)
+            .map_err(|e| anyhow::anyhow!("Failed to fork document: {:?}", e))
+    })?;
+
+    let new_handle = repo
+        .create(forked)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to create rewound document: {:?}", e))?;
+
+    println!("\n⏪ Rewound to {}", format_heads(&heads));
+    println!("New document: automerge:{}", new_handle.document_id());
+
+    let doc_data: Doc = new_handle.with_document(|doc| {
+        hydrate(doc).context("Failed to hydrate rewound document")
+    })?;
+    doc_data.display();
+
+    // Give the new document time to reach the sync server
+    sleep(Duration::from_secs(1)).await;
+
+    Ok(new_handle)
+}
+
+/// A repo with filesystem storage in `storage_dir`, or in-memory storage when nothing should persist
+async fn open_repo(storage_dir: &std::path::Path, persist: bool) -> samod::Repo {
+    let builder = samod::Repo::build_tokio();
+    if persist {
+        builder
+            .with_storage(samod::storage::TokioFilesystemStorage::new(storage_dir))
+            .load()
+            .await
+    } else {
+        builder
+            .with_storage(samod::storage::InMemoryStorage::new())
+            .load()
+            .await
+    }
+}
+
+/// Options that shape how a mutating command is applied
+struct ExecOptions {
+    only_if_changed: bool,
+    as_patch: Option<std::path::PathBuf>,
+    user: String,
+    force: bool,
+    /// Bump metadata.lastModified when the command changes the document
+    stamp: bool,
+    /// How to read fields stored in an unusual shape (`--as`)
+    overrides: TypeOverrides,
+    /// Reconcile only the fields that changed
+    dedupe_changes: bool,
+    /// Reject documents with missing or mistyped fields instead of filling them in
+    strict_hydrate: bool,
+}
+
+async fn execute_command(
+    doc_handle: &samod::DocHandle,
+    command: &Command,
+    opts: &ExecOptions,
+) -> Result<()> {
+    // A summary to print once the change has been committed
+    let report = doc_handle.with_document(|doc| -> Result<Option<String>> {
+        let mut report = None;
+        // A file copied for the document to reference, removed again if the change fails
+        let mut copied = None;
+
+        // Fields a minimal client may not have created are filled in, but only
+        // written to the document once we know the command will commit
+        let schema_missing = !opts.strict_hydrate && !missing_fields(&*doc).is_empty();
+        let mut state: Doc = if opts.strict_hydrate {
+            hydrate_strict(&*doc).context("Document does not match the expected schema")?
+        } else if schema_missing {
+            let mut filled = doc.fork();
+            ensure_schema(&mut filled).context("Failed to initialise document schema")?;
+            hydrate_with_overrides(&filled, &opts.overrides)
+                .context("Failed to hydrate document state")?
+        } else {
+            hydrate_with_overrides(&*doc, &opts.overrides)
+                .context("Failed to hydrate document state")?
+        };
+        let before = state.clone();
+
+        if !opts.force {
+            check_locks(&state, command, &opts.user)?;
+        }
+
+        // Counter commands carrying an idempotency key apply at most once
+        if let Some(key) = command.idempotency_key() {
+            if state.metadata.appliedKeys.iter().any(|k| k == key) {
+                println!("↺ Idempotency key '{}' was already applied, skipping", key);
+                return Ok(None);
+            }
+            state.metadata.appliedKeys.push(key.to_string());
+        }
+
+        // Apply command to local state
+        match command {
+            Command::Increment { .. } => {
+                state.counter += 1;
+                tracing::debug!("Incremented counter to {}", state.counter);
+            }
+            Command::Decrement { .. } => {
+                state.counter -= 1;
+                tracing::debug!("Decremented counter to {}", state.counter);
+            }
+            Command::SetCounter { value, .. } => {
+                state.counter = *value;
+                tracing::debug!("Set counter to {}", value);
+            }
+            Command::SetTemp { value, fahrenheit } => {
+                let celsius = if *fahrenheit { fahrenheit_to_celsius(*value) } else { *value };
+                let temp = celsius.clamp(0, 40);
+                state.temperature = temp;
+                tracing::debug!("Set temperature to {}°C", temp);
+            }
+            Command::Heat { .. } | Command::Cool { .. } | Command::AnimateTemp { .. } => {
+                // Animations are handled by animate_temp(), one transaction per tick
+                tracing::debug!("Temperature animation - handled separately");
+            }
+            Command::ToggleDark => {
+                state.darkMode = !state.darkMode;
+                tracing::debug!("Toggled dark mode to {}", state.darkMode);
+            }
+            Command::SetDark { enabled } => {
+                state.darkMode = *enabled;
+                tracing::debug!("Set dark mode to {}", enabled);
+            }
+            Command::AddNote { text, max_change_size, reject_oversized } => {
+                if state.notes.as_str().is_empty() {
+                    splice_bounded(&mut state.notes, 0, 0, text, *max_change_size, *reject_oversized)?;
+                } else {
+                    let len = state.notes.as_str().len();
+                    splice_bounded(
+                        &mut state.notes,
+                        len,
+                        0,
+                        &format!("\n{}", text),
+                        *max_change_size,
+                        *reject_oversized,
+                    )?;
+                }
+                tracing::debug!("Added note");
+            }
+            Command::ClearNotes => {
+                let len = state.notes.as_str().len();
+                state.notes.splice(0, len as isize, "");
+                tracing::debug!("Cleared notes");
+            }
+            Command::SetNotes { text, max_change_size, reject_oversized } => {
+                let len = state.notes.as_str().len();
+                splice_bounded(&mut state.notes, 0, len as isize, text, *max_change_size, *reject_oversized)?;
+                tracing::debug!("Set notes to: {}", text);
+            }
+            Command::SetCode { text } => {
+                replace_text(&mut state.code, text);
+                tracing::debug!("Set code to: {}", text);
+            }
+            Command::AppendCode { text } => {
+                if state.code.as_str().is_empty() {
+                    state.code.splice(0, 0, text);
+                } else {
+                    let len = state.code.as_str().len();
+                    state.code.splice(len, 0, format!("\n{}", text));
+                }
+                tracing::debug!("Appended code");
+            }
+            Command::SetNotesFromFile { path, encoding, max_change_size, reject_oversized } => {
+                let text = read_encoded(path, encoding)?;
+                let plan = splice_plan(state.notes.as_str(), &text);
+                let range = plan.byte_range(state.notes.as_str());
+                splice_bounded(
+                    &mut state.notes,
+                    range.start,
+                    range.len() as isize,
+                    &plan.insert,
+                    *max_change_size,
+                    *reject_oversized,
+                )?;
+                report = Some(format!(
+                    "📥 Loaded {}: replaced {} character(s) at {} with {}",
+                    path.display(),
+                    plan.delete,
+                    plan.start,
+                    plan.insert.chars().count()
+                ));
+            }
+            Command::InsertNotes { position, text } => {
+                let char_count = state.notes.as_str().chars().count();
+                let insert_pos = (*position).min(char_count);
+                state.notes.splice(insert_pos, 0, text);
+                tracing::debug!("Inserted '{}' at character position {}", text, insert_pos);
+            }
+            Command::DeleteNotes { start, length } => {
+                let char_count = state.notes.as_str().chars().count();
+                let start_char = (*start).min(char_count);
+                let delete_length = (*length).min(char_count - start_char);
+
+                if delete_length > 0 {
+                    state.notes.splice(start_char, delete_length as isize, "");
+                    tracing::debug!("Deleted {} characters from position {}", delete_length, start_char);
+                }
+            }
+            Command::AddTodo { text } => {
+                state.todos.push(TodoItem::new(text, false));
+                tracing::debug!("Added todo: {}", text);
+            }
+            Command::ImportJson { path } => {
+                let input = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                let json: serde_json::Value = serde_json::from_str(&input)
+                    .with_context(|| format!("{} is not valid JSON", path.display()))?;
+                state
+                    .merge_json(json)
+                    .with_context(|| format!("{} doesn't match the document's fields", path.display()))?;
+                // Keep our lastModified rather than the file's; it's stamped below if the import changed anything
+                state.metadata.lastModified = before.metadata.lastModified;
+                report = Some(format!("📥 Imported {}", path.display()));
+            }
+            Command::ImportTodos { path } => {
+                let input = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                let items = parse_checklist(&input);
+                for (text, completed) in &items {
+                    state.todos.push(TodoItem::new(text, *completed));
+                }
+                report = Some(format!("📥 Imported {} todo(s) from {}", items.len(), path.display()));
+            }
+            Command::ToggleTodo { id } => {
+                // Look the todo up by id in this transaction's state, not by a remembered position
+                let index = find_todo(&state.todos, id);
+                if let Some(todo) = index.and_then(|i| state.todos.get_mut(i)) {
+                    todo.completed = !todo.completed;
+                    tracing::debug!("Toggled todo {}", id);
+                } else {
+                    tracing::warn!("Todo {} not found", id);
+                }
+            }
+            Command::EditTodo { id, text } => {
+                let index = find_todo(&state.todos, id);
+                if let Some(todo) = index.and_then(|i| state.todos.get_mut(i)) {
+                    // Leave an unchanged todo alone so lastModified isn't bumped for nothing
+                    if todo.text.as_str() != text {
+                        replace_text(&mut todo.text, text);
+                        tracing::debug!("Edited todo {}", id);
+                    }
+                } else {
+                    tracing::warn!("Todo {} not found", id);
+                }
+            }
+            Command::DeleteTodo { id } => {
+                let index = find_todo(&state.todos, id);
+                if let Some(pos) = index {
+                    state.todos.remove(pos);
+                    tracing::debug!("Deleted todo {}", id);
+                } else {
+                    tracing::warn!("Todo {} not found", id);
+                }
+            }
+            Command::ClearCompleted => {
+                let total = state.todos.len();
+                state.todos.retain(|todo| !todo.completed);
+                report = Some(format!("🧹 Removed {} completed todo(s)", total - state.todos.len()));
+            }
+            Command::BulkTag { tag, pattern, completed, open } => {
+                let mut tagged = 0;
+                for todo in state.todos.iter_mut() {
+                    let status_matches = match (*completed, *open) {
+                        (true, _) => todo.completed,
+                        (_, true) => !todo.completed,
+                        _ => true,
+                    };
+                    if status_matches
+                        && todo.text.as_str().contains(pattern.as_str())
+                        && !todo.tags.contains(tag)
+                    {
+                        todo.tags.push(tag.clone());
+                        tagged += 1;
+                    }
+                }
+                report = Some(format!("🏷️  Tagged {} todo(s) with '{}'", tagged, tag));
+            }
+            Command::Attach { path, to_todo, attachments_dir } => {
+                let todo_id = find_todo(&state.todos, to_todo)
+                    .and_then(|i| state.todos.get(i))
+                    .map(|todo| todo.id.as_str().to_string())
+                    .with_context(|| format!("Todo {} not found", to_todo))?;
+                let (attachment, stored) = store_attachment(path, attachments_dir, &todo_id)?;
+                copied = Some(stored);
+                report = Some(format!(
+                    "📎 Attached {} to todo {} as {}",
+                    path.display(),
+                    todo_id,
+                    attachment.id
+                ));
+                state.attachments.push(attachment);
+            }
+            Command::AddTag { tag } => {
+                if !state.tags.iter().any(|t| t == tag) {
+                    state.tags.push(tag.clone());
+                    tracing::debug!("Added tag: {}", tag);
+                } else {
+                    tracing::debug!("Tag '{}' already exists", tag);
+                }
+            }
+            Command::RemoveTag { tag } => {
+                if let Some(pos) = state.tags.iter().position(|t| t == tag) {
+                    state.tags.remove(pos);
+                    tracing::debug!("Removed tag: {}", tag);
+                } else {
+                    tracing::warn!("Tag '{}' not found", tag);
+                }
+            }
+            Command::Dedupe { ignore_case, trim } => {
+                let removed = dedupe_strings(&mut state.tags, *ignore_case, *trim);
+                report = Some(format!("🧹 Removed {} duplicate tag(s)", removed));
+            }
+            Command::SetTitle { title } => {
+                state.metadata.title = Some(autosurgeon::Text::with_value(title));
+                tracing::debug!("Set title to: {}", title);
+            }
+            Command::Label { label } => {
+                // The alias is saved below, once the title has been written
+                state.set_path("metadata.title", label)?;
+            }
+            Command::AddSection { name } => {
+                if state.sections.contains_key(name) {
+                    tracing::warn!("Section '{}' already exists", name);
+                } else {
+                    state.sections.insert(name.clone(), autosurgeon::Text::with_value(""));
+                    tracing::debug!("Added section {}", name);
+                }
+            }
+            Command::EditSection { name, text } => {
+                if let Some(section) = state.sections.get_mut(name) {
+                    replace_text(section, text);
+                    tracing::debug!("Set section {} to: {}", name, text);
+                } else {
+                    tracing::warn!("Section '{}' not found (add it with add-section)", name);
+                }
+            }
+            Command::SetSetting { key, value } => {
+                state.settings.insert(key.clone(), value.clone());
+                tracing::debug!("Set setting {} = {}", key, value);
+            }
+            Command::UnsetSetting { key } => {
+                if state.settings.remove(key).is_some() {
+                    tracing::debug!("Unset setting {}", key);
+                } else {
+                    tracing::warn!("Setting '{}' not found", key);
+                }
+            }
+            Command::Set { path, value } => {
+                state.set_path(path, value)?;
+                tracing::debug!("Set {} to {}", path, value);
+            }
+            Command::Lock { field, by } => {
+                let owner = by.clone().unwrap_or_else(|| opts.user.clone());
+                if let Some(holder) = state.metadata.locks.get(field.name()) {
+                    if *holder != owner && !opts.force {
+                        anyhow::bail!(
+                            "{} is already locked by {} (pass --force to take it)",
+                            field.name(),
+                            holder
+                        );
+                    }
+                }
+                state.metadata.locks.insert(field.name().to_string(), owner.clone());
+                report = Some(format!("🔒 Locked {} for {}", field.name(), owner));
+            }
+            Command::Unlock { field } => match state.metadata.locks.get(field.name()).cloned() {
+                None => tracing::warn!("{} is not locked", field.name()),
+                Some(holder) if holder != opts.user && !opts.force => {
+                    anyhow::bail!(
+                        "{} is locked by {} (pass --force to release it anyway)",
+                        field.name(),
+                        holder
+                    );
+                }
+                Some(_) => {
+                    state.metadata.locks.remove(field.name());
+                    report = Some(format!("🔓 Unlocked {}", field.name()));
+                }
+            },
+            Command::Show { .. } => {
+                // No changes needed
+            }
+            Command::Heads
+            | Command::Rewind { .. }
+            | Command::History { .. }
+            | Command::TailHistory { .. }
+            | Command::Watch { .. }
+            | Command::Doctor
+            | Command::Whoami
+            | Command::DumpStorage { .. }
+            | Command::GcStorage { .. }
+            | Command::Search { .. }
+            | Command::ListFields { .. }
+            | Command::Tree { .. }
+            | Command::ListTodos { .. }
+            | Command::DiffAgainst { .. }
+            | Command::Diff { .. }
+            | Command::ExportTodos { .. }
+            | Command::ExportNotes { .. }
+            | Command::ExportJson { .. }
+            | Command::GetSetting { .. }
+            | Command::ListSections
+            | Command::ResolveConflicts { .. }
+            | Command::MergeFile { .. }
+            | Command::Migrate
+            | Command::Replay { .. }
+            | Command::Export { .. } => {
+                // Read-only, handled separately
+            }
+        }
+
+        // Stamp lastModified once, and only if the command changed something
+        if opts.stamp && !state.same_content(&before) {
+            state.touch();
+        }
+
+        // Avoid polluting history with changes that only bump lastModified
+        if opts.only_if_changed && state.same_content(&before) {
+            println!("\nℹ️  No change, skipping transaction");
+            return Ok(report);
+        }
+
+        if schema_missing {
+            ensure_schema(doc).context("Failed to initialise document schema")?;
+        }
+        // The patch for --as-patch holds this command's change alone, not the schema's
+        let heads_before = doc.get_heads();
+
+        // Reconcile changes back to document. On failure the transaction is
+        // rolled back, so the document is left as it was before the command
+        // (apart from any missing fields filled in just above).
+        doc.transact(|tx| {
+            let max_splice = command.max_change_size();
+            if !opts.overrides.is_empty() {
+                // Overridden fields go back in the shape they're stored in
+                reconcile_with_overrides(tx, &before, &state, &opts.overrides, max_splice)
+            } else if opts.dedupe_changes {
+                reconcile_diff(tx, &before, &state, max_splice)
+            } else {
+                // The counter isn't reconciled; it's incremented by however much it moved
+                reconcile_doc(tx, &state, max_splice)?;
+                write_counter(tx, before.counter, state.counter)?;
+                Ok(())
+            }
+        })
+        .map_err(|e| {
+            tracing::error!("Reconcile failed, no changes were applied: {:?}", e);
+            if let Some(stored) = &copied {
+                if let Err(e) = std::fs::remove_file(stored) {
+                    tracing::warn!("Failed to remove {}: {}", stored.display(), e);
+                }
+            }
+            anyhow::anyhow!("Failed to reconcile document (no changes applied): {:?}", e)
+        })?;
+
+        // Emit just this command's change, relative to the heads we started from
+        if let Some(path) = &opts.as_patch {
+            let patch = doc.save_after(&heads_before);
+            std::fs::write(path, &patch)
+                .with_context(|| format!("Failed to write patch to {}", path.display()))?;
+            println!("\n💾 Wrote {} byte patch to {}", patch.len(), path.display());
+        }
+
+        Ok(report)
+    })?;
+
+    if let Some(report) = report {
+        println!("{}", report);
+    }
+
+    if let Command::Label { label } = command {
+        let url = format!("automerge:{}", doc_handle.document_id());
+        Config::save_alias(label, &url).context("Failed to save the label")?;
+        println!("🏷️  Labelled {} as '{}'", url, label);
+    }
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    // Initialize logging based on verbose flag
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            if cli.verbose {
+                tracing_subscriber::EnvFilter::new("debug")
+            } else {
+                tracing_subscriber::EnvFilter::try_from_default_env()
+                    .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("warn"))
+            },
+        )
+        .init();
+
+    let mut command = cli.command.unwrap_or(Command::Show {
+        field: None,
+        limit: None,
+        offset: 0,
+        all: false,
+    });
+    if let Some(policy) = cli.strict_utf8 {
+        screen_command(&mut command, policy)?;
+    }
+    let type_overrides = TypeOverrides::parse(&cli.type_overrides)?;
+    let json_pretty = match (cli.pretty, cli.compact) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    };
+
+    // Doctor runs its own probes and doesn't need a document
+    if matches!(command, Command::Doctor) {
+        let config = Config::load()?;
+        let connect = ConnectOptions {
+            auth_token: config.resolve_auth_token(cli.auth_token.as_deref()),
+            peer_name: cli.peer_name.clone(),
+            ..Default::default()
+        };
+        return doctor_command(cli.doc_url.as_deref(), &connect).await;
+    }
+
+    // Storage inspection works offline, straight from the filesystem
+    if let Command::DumpStorage { storage_dir } = &command {
+        return dump_storage_command(storage_dir);
+    }
+    if let Command::GcStorage { older_than, keep, storage_dir, yes, dry_run } = &command {
+        return gc_storage_command(storage_dir, *older_than, keep, *yes, *dry_run);
+    }
+
+    // Without a document there's no actor yet: samod assigns one per document
+    if matches!(command, Command::Whoami) && cli.doc_url.is_none() && cli.doc_label.is_none() {
+        print_session_info(!cli.no_persist);
+        println!("Actor:   (a fresh random actor per session; pass a URL to see it)");
+        return Ok(());
+    }
+
+    let doc_url = cli
+        .doc_url
+        .as_deref()
+        .or(cli.doc_label.as_deref())
+        .context("A document URL is required for this command")?;
+
+    // Anything that isn't a URL is looked up among the labels saved by `label`
+    let labelled_url;
+    let doc_url = if doc_url.contains("automerge:") {
+        doc_url
+    } else {
+        labelled_url = Config::resolve_alias(doc_url)?.with_context(|| {
+            format!(
+                "'{}' is not an automerge: URL or the label of a document (see `label`)",
+                doc_url
+            )
+        })?;
+        labelled_url.as_str()
+    };
+
+    let config = Config::load()?;
+    let mut render_options = RenderOptions::default();
+    if let Some(names) = cli.field_order.as_ref().or(config.field_order.as_ref()) {
+        render_options.fields = parse_field_order(names.iter().map(String::as_str))?;
+    }
+    if let Some(preview_len) = cli.preview_len.or(config.preview_len) {
+        render_options.preview_len = preview_len;
+    }
+    if cli.ascii || config.ascii == Some(true) {
+        render_options.ascii = true;
+    }
+    render_options.relative_times = cli.relative;
+    render_options.fahrenheit = cli.fahrenheit;
+    render_options.width = cli
+        .render_width
+        .or_else(|| {
+            let is_tty = std::io::IsTerminal::is_terminal(&std::io::stdout());
+            is_tty.then(|| crossterm::terminal::size().ok()).flatten().map(|(cols, _)| cols)
+        })
+        .map(usize::from);
+
+    // Parse the automerge URL - accept both plain URLs and browser URLs
+    let doc_id_str = parse_doc_url(doc_url)?;
+
+    tracing::debug!("Initializing automerge-repo");
+
+    let repo = open_repo(std::path::Path::new(DEFAULT_STORAGE_DIR), !cli.no_persist).await;
+
+    // Connect to the sync server (reconnecting in the background unless disabled)
+    let connection = connect_repo(
+        &repo,
+        ConnectOptions {
+            reconnect: !cli.no_reconnect_on_eof,
+            auth_token: config.resolve_auth_token(cli.auth_token.as_deref()),
+            peer_name: cli.peer_name.clone(),
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    tracing::debug!("Loading document: automerge:{}", doc_id_str);
+
+    // Create DocumentId from string
+    let doc_id: samod::DocumentId = doc_id_str.parse()?;
+
+    // Try to find the document (may return None if not synced yet)
+    tracing::debug!("Looking for document...");
+    let mut doc_handle = repo.find(doc_id.clone()).await?;
 
     // TODO: Replace sleep with proper reactive sync completion detection
     // Race condition: samod's sync happens asynchronously. We need to wait for
@@ -455,41 +2296,363 @@ async fn main() -> Result<()> {
         "Document not found. Make sure:\n  1. The sync server is running\n  2. The document exists in the browser\n  3. The document ID is correct"
     )?;
 
-    // Special handling for Heat command
-    if matches!(command, Command::Heat) {
-        heat_command(&doc_handle).await?;
-    } else {
-        // Normal command execution
-        let doc_data: Doc = doc_handle.with_document(|doc| {
-            match hydrate(doc) {
-                Ok(data) => Ok(data),
-                Err(e) => {
-                    tracing::error!("Failed to hydrate document: {:?}", e);
-                    Err(anyhow::anyhow!("Failed to hydrate document for display: {:?}", e))
+    if cli.strict_hydrate {
+        doc_handle
+            .with_document(|doc| check_schema(&*doc))
+            .context("Document does not match the expected schema")?;
+    }
+
+    match &command {
+        Command::Heat { .. } | Command::Cool { .. } | Command::AnimateTemp { .. } => {
+            if !cli.force {
+                // Read the locks the same way execute_command does
+                let state: Doc = doc_handle.with_document(|doc| {
+                    if cli.strict_hydrate {
+                        hydrate_strict(&*doc).context("Document does not match the expected schema")
+                    } else {
+                        hydrate_with_overrides(&*doc, &type_overrides)
+                            .context("Failed to hydrate document state")
+                    }
+                })?;
+                check_locks(&state, &command, &current_user(cli.user.as_deref()))?;
+            }
+            match &command {
+                Command::AnimateTemp { from, to, duration, ease, commit_every } => {
+                    if !duration.is_finite() || *duration < 0.0 {
+                        anyhow::bail!("--duration must be a non-negative number of seconds");
+                    }
+                    let sweep = Sweep {
+                        from: *from,
+                        to: *to,
+                        duration: Duration::from_secs_f64(*duration),
+                        ease: *ease,
+                    };
+                    println!("\n🌡️  Animating {}°C → {}°C over {}s... (press Ctrl+C to stop)\n", from, to, duration);
+                    animate_temp(&doc_handle, &sweep, *commit_every, !cli.no_stamp).await?;
+                    print_final_state(&doc_handle)?
+                }
+                Command::Heat { commit_every } => {
+                    heat_command(&doc_handle, *commit_every, !cli.no_stamp).await?
                 }
+                Command::Cool { commit_every } => {
+                    cool_command(&doc_handle, *commit_every, !cli.no_stamp).await?
+                }
+                _ => unreachable!(),
             }
-        })?;
+        }
+        Command::Heads => {
+            let heads = doc_handle.with_document(|doc| doc.get_heads());
+            println!("{}", format_heads(&heads));
+        }
+        Command::Rewind { to } => {
+            rewind_command(&repo, &doc_handle, to).await?;
+        }
+        Command::ListSections => {
+            let state: Doc = doc_handle.with_document(|doc| {
+                hydrate(doc).context("Failed to hydrate document")
+            })?;
+            if state.sections.is_empty() {
+                println!("No sections (add one with add-section)");
+            }
+            for name in state.section_names() {
+                let text = state.sections[name].as_str();
+                println!("{} ({} lines, {} chars)", name, text.lines().count(), text.chars().count());
+            }
+        }
+        Command::GetSetting { key } => {
+            let state: Doc = doc_handle.with_document(|doc| {
+                hydrate(doc).context("Failed to hydrate document")
+            })?;
+            match state.settings.get(key) {
+                Some(value) => println!("{}", value),
+                None => anyhow::bail!("Setting '{}' is not set", key),
+            }
+        }
+        Command::Export { path, since_heads, checksum, .. } => {
+            export_command(&doc_handle, path, since_heads.as_deref(), *checksum, cli.quiet)?
+        }
+        Command::Replay { patches } => replay_command(&doc_handle, patches, cli.quiet)?,
+        Command::ResolveConflicts { policy } => {
+            let resolved = doc_handle.with_document(|doc| resolve_conflicts(doc, *policy))?;
+            if resolved.is_empty() {
+                println!("✓ No conflicts found");
+            } else {
+                println!("\n⚔️  Resolved {} conflict(s):", resolved.len());
+                for (path, value) in &resolved {
+                    println!("  {} = {}", path, value);
+                }
+            }
+        }
+        Command::MergeFile { path, merge_strategy } => {
+            let bytes = std::fs::read(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            verify_checksum(path, &bytes)?;
+            let mut other = automerge::Automerge::load(&bytes)
+                .with_context(|| format!("{} is not a saved Automerge document", path.display()))?;
 
-        // Handle Show command with optional field
-        if let Command::Show { field } = &command {
-            if let Some(field_name) = field {
-                doc_data.display_field(field_name);
+            let resolved = doc_handle
+                .with_document(|doc| merge_with_strategy(doc, &mut other, *merge_strategy))?;
+            println!("\n🔀 Merged {}", path.display());
+            for (path, value) in &resolved {
+                println!("  {} = {}", path, value);
+            }
+            let doc_data: Doc = doc_handle.with_document(|doc| {
+                hydrate(doc).context("Failed to hydrate document after merge")
+            })?;
+            doc_data.display_with(&render_options);
+        }
+        Command::Migrate => {
+            let (from, applied) = doc_handle
+                .with_document(|doc| Ok::<_, AutodashError>((schema_version(&*doc)?, migrate(doc)?)))?;
+            if applied.is_empty() {
+                println!("✓ Already at schema version {}", from);
+            } else {
+                println!("\n🚚 Migrated from schema version {} to {}:", from, CURRENT_SCHEMA_VERSION);
+                for migration in &applied {
+                    println!("  v{}: {}", migration.to, migration.description);
+                }
+            }
+        }
+        Command::History { format, limit } => {
+            let entries = doc_handle.with_document(|doc| {
+                let changes = doc.get_changes(&[]);
+                // Changes come oldest first, so the most recent are at the end
+                let skip = limit.map_or(0, |limit| changes.len().saturating_sub(limit));
+                changes
+                    .iter()
+                    .skip(skip)
+                    .map(|change| HistoryEntry::from_change(change))
+                    .collect::<Vec<_>>()
+            });
+            print!("{}", render_history(&entries, *format, json_pretty)?);
+        }
+        Command::TailHistory { format } => {
+            let poll_interval = Duration::from_millis(cli.poll_changes);
+            tail_history_command(&doc_handle, *format, poll_interval).await?
+        }
+        Command::Watch { render } => {
+            let poll_interval = Duration::from_millis(cli.poll_changes);
+            let is_tty = std::io::IsTerminal::is_terminal(&std::io::stdout());
+            if *render == WatchRender::Tui && !is_tty {
+                eprintln!("⚠️  stdout is not a terminal; falling back to plain watch");
+            }
+            if *render == WatchRender::Tui && is_tty {
+                watch_tui(&doc_handle, &render_options, poll_interval).await?
             } else {
-                doc_data.display();
+                watch_plain(&doc_handle, &render_options, poll_interval).await?
             }
-        } else {
-            // Display state before changes for non-Show commands
-            println!("\n📄 Before:");
-            doc_data.display();
+        }
+        Command::DiffAgainst { path } => {
+            let bytes = std::fs::read(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            verify_checksum(path, &bytes)?;
+            let snapshot = automerge::Automerge::load(&bytes)
+                .with_context(|| format!("{} is not a saved Automerge document", path.display()))?;
+            let before: Doc = hydrate(&snapshot).context("Failed to hydrate snapshot")?;
+            let after: Doc = doc_handle.with_document(|doc| {
+                hydrate(doc).context("Failed to hydrate document")
+            })?;
 
-            // Execute the command
-            execute_command(&doc_handle, &command).await?;
+            let changes = diff_docs(&before, &after);
+            if changes.is_empty() {
+                println!("✓ No changes since {}", path.display());
+            } else {
+                println!("🔀 {} field(s) changed since {}:", changes.len(), path.display());
+                for change in &changes {
+                    println!("  {}: {} → {}", change.field.name(), change.before, change.after);
+                }
+            }
+        }
+        Command::Diff { since } => {
+            let since = since.as_deref().map(parse_heads).transpose()?;
+            let diffed: Option<(Vec<automerge::ChangeHash>, Doc, Doc)> =
+                doc_handle.with_document(|doc| -> Result<_> {
+                    let heads = match since {
+                        Some(heads) => {
+                            let unknown = heads.iter().find(|h| doc.get_change_by_hash(h).is_none());
+                            if let Some(unknown) = unknown {
+                                anyhow::bail!("Change {} is not in this document's history", unknown);
+                            }
+                            heads
+                        }
+                        None => previous_heads(doc),
+                    };
+                    if heads.is_empty() {
+                        return Ok(None);
+                    }
+                    let snapshot = doc
+                        .fork_at(&heads)
+                        .map_err(|e| anyhow::anyhow!("Failed to fork document: {:?}", e))?;
+                    let before: Doc = hydrate(&snapshot).context("Failed to hydrate earlier state")?;
+                    let after: Doc = hydrate(doc).context("Failed to hydrate document")?;
+                    Ok(Some((heads, before, after)))
+                })?;
 
-            println!("\n📄 After:");
+            match diffed {
+                None => println!("✓ No changes (the document has no earlier state)"),
+                Some((heads, before, after)) => {
+                    let changes = diff_docs(&before, &after);
+                    if changes.is_empty() {
+                        println!("✓ No changes since {}", format_heads(&heads));
+                    } else {
+                        println!("🔀 {} field(s) changed since {}:", changes.len(), format_heads(&heads));
+                        for change in &changes {
+                            println!("  {}: {} → {}", change.field.name(), change.before, change.after);
+                        }
+                    }
+                }
+            }
+        }
+        Command::ListFields { recursive } => {
+            print!("{}", doc_handle.with_document(|doc| list_fields(&*doc, *recursive))?);
+        }
+        Command::ListTodos { completed } => {
+            let state: Doc = doc_handle.with_document(|doc| {
+                hydrate(doc).context("Failed to hydrate document")
+            })?;
+            let mut render_options = render_options.clone();
+            render_options.todo_status = *completed;
+            render_options.todo_limit = None;
+            state.display_field_with("todos", &render_options);
+        }
+        Command::Tree { max_depth, json } => {
+            let output = doc_handle.with_document(|doc| -> Result<String> {
+                if *json {
+                    let root = automerge::Value::Object(automerge::ObjType::Map);
+                    let tree = tree_json(&*doc, &root, &automerge::ROOT, 0, *max_depth)?;
+                    Ok(format!("{}\n", to_json_string(&tree, json_pretty)?))
+                } else {
+                    render_tree(&*doc, *max_depth)
+                }
+            })?;
+            print!("{}", output);
+        }
+        Command::Search { term, case_sensitive, regex } => {
+            let state: Doc = doc_handle.with_document(|doc| {
+                hydrate(doc).context("Failed to hydrate document")
+            })?;
+            let options = SearchOptions {
+                case_sensitive: *case_sensitive,
+                regex: *regex,
+            };
+            let matches = search_doc(&state, term, options)?;
+            if matches.is_empty() {
+                println!("No matches for '{}'", term);
+            } else {
+                println!("🔍 {} match(es) for '{}':", matches.len(), term);
+                for found in &matches {
+                    println!("  {}: {}", found.field, found.snippet);
+                }
+            }
+        }
+        Command::ExportNotes { output, encoding } => {
+            let state: Doc = doc_handle.with_document(|doc| {
+                hydrate(doc).context("Failed to hydrate document")
+            })?;
+            let bytes = encode_text(state.notes.as_str(), encoding)?;
+            match output {
+                Some(path) => {
+                    std::fs::write(path, &bytes)
+                        .with_context(|| format!("Failed to write {}", path.display()))?;
+                    println!("📤 Wrote notes ({} bytes, {}) to {}", bytes.len(), encoding.name(), path.display());
+                }
+                None => {
+                    use std::io::Write;
+                    std::io::stdout().write_all(&bytes)?;
+                }
+            }
+        }
+        Command::ExportTodos { output } => {
+            let state: Doc = doc_handle.with_document(|doc| {
+                hydrate(doc).context("Failed to hydrate document")
+            })?;
+            let checklist = render_checklist(&state.todos);
+            match output {
+                Some(path) => {
+                    std::fs::write(path, &checklist)
+                        .with_context(|| format!("Failed to write {}", path.display()))?;
+                    println!("📤 Wrote {} todo(s) to {}", state.todos.len(), path.display());
+                }
+                None => print!("{}", checklist),
+            }
+        }
+        Command::ExportJson { path } => {
+            let state: Doc = doc_handle.with_document(|doc| {
+                hydrate(doc).context("Failed to hydrate document")
+            })?;
+            let json = to_json_string(&state, json_pretty)?;
+            match path {
+                Some(path) => {
+                    std::fs::write(path, format!("{}\n", json))
+                        .with_context(|| format!("Failed to write {}", path.display()))?;
+                    println!("📤 Wrote the document as JSON to {}", path.display());
+                }
+                None => println!("{}", json),
+            }
+        }
+        Command::Whoami => {
+            let (actor, heads) =
+                doc_handle.with_document(|doc| (doc.get_actor().to_string(), doc.get_heads()));
+            print_session_info(!cli.no_persist);
+            println!("Actor:   {}", actor);
+            if let Some(name) = &cli.peer_name {
+                println!("Peer:    {}", name);
+            }
+            println!("Doc:     automerge:{}", doc_handle.document_id());
+            println!("Heads:   {}", format_heads(&heads));
+        }
+        _ => {
+            // Normal command execution
             let doc_data: Doc = doc_handle.with_document(|doc| {
-                hydrate(doc).context("Failed to hydrate document after command")
+                match hydrate_with_overrides(&*doc, &type_overrides) {
+                    Ok(data) => Ok(data),
+                    Err(e) => {
+                        tracing::error!("Failed to hydrate document: {:?}", e);
+                        Err(anyhow::anyhow!("Failed to hydrate document for display: {:?}", e))
+                    }
+                }
             })?;
-            doc_data.display();
+
+            // Handle Show command with optional field
+            if let Command::Show { field, limit, offset, all } = &command {
+                let mut render_options = render_options.clone();
+                if *all {
+                    render_options.todo_limit = None;
+                } else if let Some(limit) = limit {
+                    render_options.todo_limit = Some(*limit);
+                }
+                render_options.todo_offset = *offset;
+
+                if let Some(field_name) = field {
+                    doc_data.display_field_with(field_name, &render_options);
+                } else {
+                    doc_data.display_with(&render_options);
+                }
+            } else {
+                // Display state before changes for non-Show commands
+                println!("\n📄 Before:");
+                doc_data.display_with(&render_options);
+
+                // Execute the command
+                let exec_options = ExecOptions {
+                    only_if_changed: cli.only_if_changed,
+                    as_patch: cli.as_patch.clone(),
+                    user: current_user(cli.user.as_deref()),
+                    force: cli.force,
+                    stamp: !cli.no_stamp,
+                    overrides: type_overrides.clone(),
+                    dedupe_changes: cli.dedupe_changes,
+                    strict_hydrate: cli.strict_hydrate,
+                };
+                execute_command(&doc_handle, &command, &exec_options).await?;
+
+                println!("\n📄 After:");
+                let doc_data: Doc = doc_handle.with_document(|doc| {
+                    hydrate_with_overrides(&*doc, &type_overrides)
+                        .context("Failed to hydrate document after command")
+                })?;
+                doc_data.display_with(&render_options);
+            }
         }
     }
 
@@ -498,8 +2661,718 @@ async fn main() -> Result<()> {
     sleep(Duration::from_millis(100)).await;
 
     // Clean up connection tasks
-    ws_to_samod_handle.abort();
-    samod_to_ws_handle.abort();
+    connection.close();
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A handle to a fresh document in an in-memory repo; keep the repo alive alongside it
+    async fn new_handle() -> (samod::Repo, samod::DocHandle) {
+        let repo = samod::Repo::build_tokio().load().await;
+        let handle = repo.create(automerge::Automerge::new()).await.unwrap();
+        (repo, handle)
+    }
+
+    fn options() -> ExecOptions {
+        ExecOptions {
+            only_if_changed: false,
+            as_patch: None,
+            user: "alice".to_string(),
+            force: false,
+            stamp: true,
+            overrides: TypeOverrides::default(),
+            dedupe_changes: false,
+            strict_hydrate: false,
+        }
+    }
+
+    async fn run(handle: &samod::DocHandle, command: Command) {
+        execute_command(handle, &command, &options()).await.unwrap();
+    }
+
+    fn state_of(handle: &samod::DocHandle) -> Doc {
+        handle.with_document(|doc| hydrate(&*doc).unwrap())
+    }
+
+    /// A fresh, empty directory under the system temp dir
+    fn scratch_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("automerge-cli-{}-{}", name, new_id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn rewind_forks_the_document_as_it_was() {
+        let (repo, handle) = new_handle().await;
+        run(&handle, Command::SetTemp { value: 10, fahrenheit: false }).await;
+        let earlier = handle.with_document(|doc| doc.get_heads());
+        run(&handle, Command::SetTemp { value: 30, fahrenheit: false }).await;
+
+        let rewound = rewind_command(&repo, &handle, &format_heads(&earlier)).await.unwrap();
+        assert_ne!(rewound.document_id(), handle.document_id());
+        assert_eq!(state_of(&rewound).temperature, 10);
+        assert_eq!(state_of(&handle).temperature, 30);
+    }
+
+    #[tokio::test]
+    async fn oversized_paste_is_split_and_arrives_intact() {
+        let max = 64 * 1024;
+        let paste = "abcdefgh".repeat(128 * 1024);
+        let chunks = chunk_str(&paste, max);
+        assert_eq!(chunks.len(), 16);
+        assert!(chunks.iter().all(|chunk| chunk.len() <= max));
+        // Chunks never split a character
+        assert_eq!(chunk_str("ééé", 3), vec!["é", "é", "é"]);
+
+        let (_repo, handle) = new_handle().await;
+        run(&handle, Command::AddNote { text: "naïve".to_string(), max_change_size: None, reject_oversized: false }).await;
+        let heads = handle.with_document(|doc| doc.get_heads());
+        run(&handle, Command::AddNote { text: paste.clone(), max_change_size: Some(max), reject_oversized: false }).await;
+        assert_eq!(state_of(&handle).notes.as_str(), format!("naïve\n{}", paste));
+
+        // One change for the command, holding an op per inserted character
+        let changes = handle.with_document(|doc| doc.get_changes(&heads));
+        assert_eq!(changes.len(), 1);
+        assert!(changes[0].len() > paste.len(), "{} ops", changes[0].len());
+
+        let rejected = Command::AddNote { text: paste, max_change_size: Some(max), reject_oversized: true };
+        assert!(execute_command(&handle, &rejected, &options()).await.is_err());
+    }
+
+    #[test]
+    fn csv_history_has_a_header_and_a_row_per_change() {
+        use automerge::transaction::{CommitOptions, Transactable};
+
+        let mut doc = automerge::Automerge::new();
+        doc.transact_with(
+            |_| CommitOptions::default().with_message("set counter, again").with_time(1_700_000_000),
+            |tx| -> Result<(), automerge::AutomergeError> {
+                tx.put(automerge::ROOT, "counter", 1)?;
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        let changes = doc.get_changes(&[]);
+        let entries: Vec<HistoryEntry> = changes.iter().map(HistoryEntry::from_change).collect();
+        let csv = render_history(&entries, HistoryFormat::Csv, None).unwrap();
+        let expected = format!(
+            "actor,timestamp,message,op_count,hash\n{},1700000000,\"set counter, again\",1,{}\n",
+            changes[0].actor_id(),
+            changes[0].hash()
+        );
+        assert_eq!(csv, expected);
+    }
+
+    #[tokio::test]
+    async fn an_idempotency_key_applies_once() {
+        let (_repo, handle) = new_handle().await;
+        let increment = || Command::Increment { idempotency_key: Some("retry-1".to_string()) };
+        run(&handle, increment()).await;
+        run(&handle, increment()).await;
+        assert_eq!(state_of(&handle).counter, 1);
+
+        run(&handle, Command::Increment { idempotency_key: Some("retry-2".to_string()) }).await;
+        assert_eq!(state_of(&handle).counter, 2);
+    }
+
+    #[tokio::test]
+    async fn unsetting_a_setting_leaves_the_others() {
+        let (_repo, handle) = new_handle().await;
+        let set = |key: &str, value: &str| Command::SetSetting { key: key.to_string(), value: value.to_string() };
+        run(&handle, set("theme", "solarized")).await;
+        run(&handle, set("fontSize", "14")).await;
+        run(&handle, Command::UnsetSetting { key: "theme".to_string() }).await;
+
+        let settings = state_of(&handle).settings;
+        assert_eq!(settings.len(), 1);
+        assert_eq!(settings.get("fontSize").map(String::as_str), Some("14"));
+    }
+
+    #[tokio::test]
+    async fn only_if_changed_skips_a_no_op() {
+        let (_repo, handle) = new_handle().await;
+        run(&handle, Command::SetDark { enabled: true }).await;
+        let heads = handle.with_document(|doc| doc.get_heads());
+
+        let opts = ExecOptions { only_if_changed: true, ..options() };
+        execute_command(&handle, &Command::SetDark { enabled: true }, &opts).await.unwrap();
+        assert_eq!(handle.with_document(|doc| doc.get_heads()), heads);
+
+        execute_command(&handle, &Command::SetDark { enabled: false }, &opts).await.unwrap();
+        assert_ne!(handle.with_document(|doc| doc.get_heads()), heads);
+    }
+
+    #[tokio::test]
+    async fn as_patch_holds_just_the_commands_change() {
+        let (_repo, handle) = new_handle().await;
+        run(&handle, Command::SetTemp { value: 10, fahrenheit: false }).await;
+        let before = handle.with_document(|doc| doc.get_heads());
+
+        let patch = scratch_dir("as-patch").join("change.automerge");
+        let opts = ExecOptions { as_patch: Some(patch.clone()), ..options() };
+        execute_command(&handle, &Command::AddTodo { text: "ship it".to_string() }, &opts).await.unwrap();
+
+        // Applied to the document as it was before the command, the patch reproduces the after state
+        let (mut fork, after) = handle.with_document(|doc| (doc.fork_at(&before).unwrap(), doc.get_heads()));
+        fork.load_incremental(&std::fs::read(&patch).unwrap()).unwrap();
+        assert_eq!(fork.get_heads(), after);
+        let state: Doc = hydrate(&fork).unwrap();
+        assert_eq!(state.temperature, 10);
+        assert_eq!(state.todos[0].text.as_str(), "ship it");
+    }
+
+    #[tokio::test]
+    async fn replay_applies_an_as_patch_file() {
+        let (repo, handle) = new_handle().await;
+        run(&handle, Command::AddTag { tag: "draft".to_string() }).await;
+        let copy = handle.with_document(|doc| doc.fork());
+        let copy = repo.create(copy).await.unwrap();
+
+        let patch = scratch_dir("replay").join("change.automerge");
+        let opts = ExecOptions { as_patch: Some(patch.clone()), ..options() };
+        execute_command(&handle, &Command::SetTitle { title: "Launch".to_string() }, &opts).await.unwrap();
+
+        replay_command(&copy, &[patch.clone()], true).unwrap();
+        let replayed = state_of(&copy);
+        assert_eq!(replayed.metadata.title.as_ref().map(|t| t.as_str()), Some("Launch"));
+        assert_eq!(replayed.tags, vec!["draft"]);
+        assert_eq!(copy.with_document(|doc| doc.get_heads()), handle.with_document(|doc| doc.get_heads()));
+
+        // Replaying the same patch again changes nothing
+        replay_command(&copy, &[patch], true).unwrap();
+        assert_eq!(state_of(&copy).metadata.title.as_ref().map(|t| t.as_str()), Some("Launch"));
+    }
+
+    #[tokio::test]
+    async fn a_delta_export_on_top_of_a_full_export_rebuilds_the_document() {
+        let (_repo, handle) = new_handle().await;
+        run(&handle, Command::AddTodo { text: "first".to_string() }).await;
+        let dir = scratch_dir("export");
+        let full = dir.join("full.automerge");
+        export_command(&handle, &full, None, false, true).unwrap();
+        let since = handle.with_document(|doc| format_heads(&doc.get_heads()));
+
+        run(&handle, Command::AddTodo { text: "second".to_string() }).await;
+        run(&handle, Command::Increment { idempotency_key: None }).await;
+        let delta = dir.join("delta.automerge");
+        export_command(&handle, &delta, Some(since.as_str()), false, true).unwrap();
+
+        let mut rebuilt = automerge::Automerge::load(&std::fs::read(&full).unwrap()).unwrap();
+        rebuilt.load_incremental(&std::fs::read(&delta).unwrap()).unwrap();
+        assert_eq!(rebuilt.get_heads(), handle.with_document(|doc| doc.get_heads()));
+        let state: Doc = hydrate(&rebuilt).unwrap();
+        assert_eq!(state.counter, 1);
+        assert_eq!(state.todos.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn a_failed_command_changes_nothing_and_later_commands_still_run() {
+        let (_repo, handle) = new_handle().await;
+        let batch = [
+            Command::SetTemp { value: 15, fahrenheit: false },
+            Command::Set { path: "temperature".to_string(), value: "hot".to_string() },
+            Command::AddTag { tag: "after".to_string() },
+        ];
+
+        let mut failures = 0;
+        for command in &batch {
+            let heads = handle.with_document(|doc| doc.get_heads());
+            if execute_command(&handle, command, &options()).await.is_err() {
+                failures += 1;
+                assert_eq!(handle.with_document(|doc| doc.get_heads()), heads);
+            }
+        }
+
+        assert_eq!(failures, 1);
+        let state = state_of(&handle);
+        assert_eq!(state.temperature, 15);
+        assert_eq!(state.tags, vec!["after"]);
+    }
+
+    #[tokio::test]
+    async fn a_locked_field_rejects_other_users_until_unlocked() {
+        let (_repo, handle) = new_handle().await;
+        run(&handle, Command::Lock { field: Field::Notes, by: None }).await;
+
+        let bob = ExecOptions { user: "bob".to_string(), ..options() };
+        let edit = Command::AddNote { text: "from bob".to_string(), max_change_size: None, reject_oversized: false };
+        assert!(execute_command(&handle, &edit, &bob).await.is_err());
+        assert!(execute_command(&handle, &Command::Unlock { field: Field::Notes }, &bob).await.is_err());
+        // Other fields stay editable
+        execute_command(&handle, &Command::AddTag { tag: "bob".to_string() }, &bob).await.unwrap();
+
+        run(&handle, Command::Unlock { field: Field::Notes }).await;
+        execute_command(&handle, &edit, &bob).await.unwrap();
+        assert_eq!(state_of(&handle).notes.as_str(), "from bob");
+    }
+
+    #[tokio::test]
+    async fn attach_copies_the_file_and_stores_a_reference() {
+        let (_repo, handle) = new_handle().await;
+        run(&handle, Command::AddTodo { text: "review diagram".to_string() }).await;
+        let todo_id = state_of(&handle).todos[0].id.as_str().to_string();
+
+        let dir = scratch_dir("attach");
+        let file = dir.join("diagram.png");
+        std::fs::write(&file, b"not really a png").unwrap();
+        let attach = Command::Attach {
+            path: file,
+            to_todo: todo_id.clone(),
+            attachments_dir: dir.join("stored"),
+        };
+        run(&handle, attach).await;
+
+        let attachments = state_of(&handle).attachments;
+        assert_eq!(attachments.len(), 1);
+        assert_eq!(attachments[0].mime, "image/png");
+        assert_eq!(attachments[0].todoId.as_deref(), Some(todo_id.as_str()));
+        let stored = attachments[0].url.strip_prefix("file://").unwrap();
+        assert_eq!(std::fs::read(stored).unwrap(), b"not really a png");
+    }
+
+    #[test]
+    fn gc_storage_dry_run_deletes_nothing() {
+        let storage = scratch_dir("gc");
+        let chunk = storage.join("4V").join("gLSsiuVNfWeZk17m85GgA18VVp").join("snapshot").join("abc123");
+        std::fs::create_dir_all(chunk.parent().unwrap()).unwrap();
+        std::fs::write(&chunk, automerge::Automerge::new().save()).unwrap();
+        let id = "automerge:4VgLSsiuVNfWeZk17m85GgA18VVp".to_string();
+
+        // Deleting needs --yes or --dry-run
+        assert!(gc_storage_command(&storage, None, &[], false, false).is_err());
+
+        gc_storage_command(&storage, None, &[], false, true).unwrap();
+        assert!(chunk.exists());
+
+        gc_storage_command(&storage, None, &[id], true, false).unwrap();
+        assert!(chunk.exists());
+
+        gc_storage_command(&storage, None, &[], true, false).unwrap();
+        assert!(!chunk.exists());
+        assert!(list_stored_documents(&storage).unwrap().is_empty());
+    }
+
+    /// Run a one-second 0 → 40°C sweep, returning how many changes it wrote
+    async fn sweep_changes(commit_every: u64, ease: Ease) -> (usize, Doc) {
+        let (_repo, handle) = new_handle().await;
+        run(&handle, Command::SetTemp { value: 20, fahrenheit: false }).await;
+        let heads = handle.with_document(|doc| doc.get_heads());
+        let sweep = Sweep { from: 0, to: 40, duration: Duration::from_secs(1), ease };
+        animate_temp(&handle, &sweep, commit_every, true).await.unwrap();
+        let changes = handle.with_document(|doc| doc.get_changes(&heads).len());
+        (changes, state_of(&handle))
+    }
+
+    #[tokio::test]
+    async fn commit_every_batches_animation_writes() {
+        let (every_tick, _) = sweep_changes(1, Ease::Linear).await;
+        let (batched, state) = sweep_changes(10, Ease::Linear).await;
+        assert!(every_tick >= 6, "{} changes", every_tick);
+        // The first and last values, plus at most one write per ten ticks
+        assert!((2..=3).contains(&batched), "{} changes", batched);
+        assert_eq!(state.temperature, 40);
+    }
+
+    /// Create a document in a repo over `storage`, edit it, and shut the repo down
+    async fn edit_in_repo(storage: &std::path::Path, persist: bool) {
+        let repo = open_repo(storage, persist).await;
+        let handle = repo.create(automerge::Automerge::new()).await.unwrap();
+        run(&handle, Command::AddTodo { text: "remember me".to_string() }).await;
+        repo.stop().await;
+    }
+
+    #[tokio::test]
+    async fn no_persist_leaves_storage_empty() {
+        let storage = scratch_dir("no-persist");
+        edit_in_repo(&storage, false).await;
+        assert_eq!(std::fs::read_dir(&storage).unwrap().count(), 0);
+
+        // The same run with persistence on does write the document
+        let storage = scratch_dir("persist");
+        edit_in_repo(&storage, true).await;
+        assert_eq!(list_stored_documents(&storage).unwrap().len(), 1);
+    }
+
+    /// A document with one of each kind of value, outside the Autodash schema
+    fn mixed_document() -> automerge::Automerge {
+        use automerge::{transaction::Transactable, ObjType, ScalarValue};
+        let mut doc = automerge::Automerge::new();
+        doc.transact(|tx| {
+            tx.put(automerge::ROOT, "count", ScalarValue::counter(3))?;
+            tx.put(automerge::ROOT, "done", true)?;
+            tx.put(automerge::ROOT, "name", "demo")?;
+            let notes = tx.put_object(automerge::ROOT, "notes", ObjType::Text)?;
+            tx.splice_text(&notes, 0, 0, "hi")?;
+            let settings = tx.put_object(automerge::ROOT, "settings", ObjType::Map)?;
+            tx.put(&settings, "theme", "dark")?;
+            let recent = tx.put_object(&settings, "recent", ObjType::List)?;
+            tx.insert(&recent, 0, 1)?;
+            tx.insert(&recent, 1, 2)?;
+            Ok::<_, automerge::AutomergeError>(())
+        })
+        .unwrap();
+        doc
+    }
+
+    #[test]
+    fn list_fields_names_each_type() {
+        let doc = mixed_document();
+        let squeeze = |out: &str| -> Vec<String> {
+            out.lines().map(|line| line.split_whitespace().collect::<Vec<_>>().join(" ")).collect()
+        };
+
+        assert_eq!(
+            squeeze(&list_fields(&doc, false).unwrap()),
+            [
+                "count Counter Counter: 3",
+                "done Boolean true",
+                "name Str \"demo\"",
+                "notes Text (2)",
+                "settings Map (2)",
+            ]
+        );
+
+        let recursive = list_fields(&doc, true).unwrap();
+        assert_eq!(squeeze(&recursive)[5..], ["recent List (2)", "theme Str \"dark\""]);
+        assert!(recursive.lines().nth(5).unwrap().starts_with("  recent"));
+
+        assert_eq!(list_fields(&automerge::Automerge::new(), false).unwrap(), "(empty document)\n");
+    }
+
+    #[tokio::test]
+    async fn set_notes_from_file_only_splices_the_changed_span() {
+        let (_repo, handle) = new_handle().await;
+        let original = "line of notes\n".repeat(300);
+        run(&handle, Command::SetNotes { text: original.clone(), max_change_size: None, reject_oversized: false }).await;
+
+        let middle = original.len() / 2;
+        let edited = format!("{}edited {}", &original[..middle], &original[middle..]);
+        let file = scratch_dir("notes-from-file").join("notes.txt");
+        std::fs::write(&file, &edited).unwrap();
+
+        let heads = handle.with_document(|doc| doc.get_heads());
+        let load = Command::SetNotesFromFile {
+            path: file,
+            encoding: encoding_rs::UTF_8,
+            max_change_size: None,
+            reject_oversized: false,
+        };
+        run(&handle, load).await;
+
+        assert_eq!(state_of(&handle).notes.as_str(), edited);
+        let ops: usize = handle.with_document(|doc| doc.get_changes(&heads).iter().map(|c| c.len()).sum());
+        assert!(ops < 20, "{} ops for a one-word edit", ops);
+    }
+
+    #[tokio::test]
+    async fn a_latin1_file_is_imported_as_utf8() {
+        let (_repo, handle) = new_handle().await;
+        let file = scratch_dir("latin1").join("notes.txt");
+        std::fs::write(&file, b"caf\xe9 cr\xe8me br\xfbl\xe9e").unwrap();
+
+        let load = |encoding| Command::SetNotesFromFile {
+            path: file.clone(),
+            encoding,
+            max_change_size: None,
+            reject_oversized: false,
+        };
+        // Not valid UTF-8, so the default encoding refuses it
+        assert!(execute_command(&handle, &load(encoding_rs::UTF_8), &options()).await.is_err());
+
+        run(&handle, load(parse_encoding("latin1").unwrap())).await;
+        assert_eq!(state_of(&handle).notes.as_str(), "café crème brûlée");
+        assert!(parse_encoding("not-an-encoding").is_err());
+    }
+
+    #[tokio::test]
+    async fn a_tampered_export_fails_its_checksum() {
+        let (_repo, handle) = new_handle().await;
+        run(&handle, Command::SetTitle { title: "Signed".to_string() }).await;
+        let export = scratch_dir("checksum").join("doc.automerge");
+        export_command(&handle, &export, None, true, true).unwrap();
+
+        let bytes = std::fs::read(&export).unwrap();
+        verify_checksum(&export, &bytes).unwrap();
+
+        let mut tampered = bytes.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 0xff;
+        std::fs::write(&export, &tampered).unwrap();
+        assert!(verify_checksum(&export, &tampered).is_err());
+
+        let (_other_repo, other) = new_handle().await;
+        assert!(replay_command(&other, &[export], true).is_err());
+        assert!(other.with_document(|doc| doc.get_heads()).is_empty());
+    }
+
+    #[test]
+    fn tree_walks_nested_maps_and_lists() {
+        let doc = mixed_document();
+        assert_eq!(
+            render_tree(&doc, None).unwrap(),
+            concat!(
+                "count: Counter Counter: 3\n",
+                "done: Boolean true\n",
+                "name: Str \"demo\"\n",
+                "notes: Text (2) \"hi\"\n",
+                "settings: Map (2)\n",
+                "  recent: List (2)\n",
+                "    [0]: Int 1\n",
+                "    [1]: Int 2\n",
+                "  theme: Str \"dark\"\n",
+            )
+        );
+        assert_eq!(render_tree(&doc, Some(1)).unwrap().lines().count(), 5);
+
+        let root = automerge::Value::Object(automerge::ObjType::Map);
+        let json = tree_json(&doc, &root, &automerge::ROOT, 0, None).unwrap();
+        assert_eq!(json["value"]["settings"]["value"]["recent"]["value"][1], serde_json::json!({ "type": "Int", "value": 2 }));
+        assert_eq!(json["value"]["notes"], serde_json::json!({ "type": "Text", "value": "hi" }));
+        assert_eq!(json["value"]["count"]["value"], 3);
+
+        let shallow = tree_json(&doc, &root, &automerge::ROOT, 0, Some(1)).unwrap();
+        assert_eq!(shallow["value"]["settings"], serde_json::json!({ "type": "Map", "length": 2 }));
+    }
+
+    #[tokio::test]
+    async fn a_linear_sweep_hits_both_endpoints_and_never_turns_back() {
+        use automerge::ReadDoc;
+
+        let (_repo, handle) = new_handle().await;
+        run(&handle, Command::SetTemp { value: 0, fahrenheit: false }).await;
+        let heads = handle.with_document(|doc| doc.get_heads());
+        let sweep = Sweep { from: 10, to: 20, duration: Duration::from_millis(500), ease: Ease::Linear };
+        animate_temp(&handle, &sweep, 1, false).await.unwrap();
+
+        // The temperature after each change the sweep wrote
+        let written: Vec<i64> = handle.with_document(|doc| {
+            doc.get_changes(&heads)
+                .iter()
+                .map(|change| {
+                    let at = doc.fork_at(&[change.hash()]).unwrap();
+                    let (value, _) = at.get(automerge::ROOT, "temperature").unwrap().unwrap();
+                    value.to_i64().unwrap()
+                })
+                .collect()
+        });
+        assert_eq!(written.first(), Some(&10));
+        assert_eq!(written.last(), Some(&20));
+        assert!(written.windows(2).all(|pair| pair[0] <= pair[1]), "{:?}", written);
+
+        for ease in [Ease::Linear, Ease::In, Ease::Out, Ease::InOut] {
+            assert_eq!(ease.apply(0.0), 0.0);
+            assert_eq!(ease.apply(1.0), 1.0);
+            let curve: Vec<f64> = (0..=20).map(|i| ease.apply(i as f64 / 20.0)).collect();
+            assert!(curve.windows(2).all(|pair| pair[0] <= pair[1]), "{:?}", ease);
+        }
+    }
+
+    #[tokio::test]
+    async fn editing_one_section_leaves_the_other_alone() {
+        let (_repo, handle) = new_handle().await;
+        for name in ["ideas", "log"] {
+            run(&handle, Command::AddSection { name: name.to_string() }).await;
+        }
+        let edit = |text: &str| Command::EditSection { name: "log".to_string(), text: text.to_string() };
+        run(&handle, Command::EditSection { name: "ideas".to_string(), text: "keep me".to_string() }).await;
+        run(&handle, edit("day one")).await;
+        run(&handle, edit("día dos ✓")).await;
+
+        let sections = state_of(&handle).sections;
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections["ideas"].as_str(), "keep me");
+        assert_eq!(sections["log"].as_str(), "día dos ✓");
+    }
+
+    #[tokio::test]
+    async fn bulk_tag_tags_every_matching_todo_once() {
+        let (_repo, handle) = new_handle().await;
+        for text in ["urgent: fix login", "water plants", "not urgent, but soon"] {
+            run(&handle, Command::AddTodo { text: text.to_string() }).await;
+        }
+        let tag = || Command::BulkTag {
+            tag: "urgent".to_string(),
+            pattern: "urgent".to_string(),
+            completed: false,
+            open: false,
+        };
+        run(&handle, tag()).await;
+        run(&handle, tag()).await;
+
+        let tags: Vec<Vec<String>> = state_of(&handle).todos.into_iter().map(|todo| todo.tags).collect();
+        assert_eq!(tags, [vec!["urgent"], vec![], vec!["urgent"]]);
+    }
+
+    #[tokio::test]
+    async fn replacing_multi_byte_text_keeps_every_field_intact() {
+        let (_repo, handle) = new_handle().await;
+        run(&handle, Command::AddSection { name: "log".to_string() }).await;
+        for text in ["naïve café ✓", "crème brûlée", "done"] {
+            run(&handle, Command::SetCode { text: text.to_string() }).await;
+            run(&handle, Command::EditSection { name: "log".to_string(), text: text.to_string() }).await;
+            for path in ["notes", "metadata.title"] {
+                run(&handle, Command::Set { path: path.to_string(), value: text.to_string() }).await;
+            }
+
+            let state = state_of(&handle);
+            assert_eq!(state.code.as_str(), text);
+            assert_eq!(state.sections["log"].as_str(), text);
+            assert_eq!(state.notes.as_str(), text);
+            assert_eq!(state.metadata.title.as_ref().map(|t| t.as_str()), Some(text));
+        }
+    }
+
+    #[tokio::test]
+    async fn append_code_goes_after_multi_byte_code() {
+        let (_repo, handle) = new_handle().await;
+        run(&handle, Command::SetCode { text: "let café = \"☕\";".to_string() }).await;
+        run(&handle, Command::AppendCode { text: "println!(\"{café}\");".to_string() }).await;
+        assert_eq!(state_of(&handle).code.as_str(), "let café = \"☕\";\nprintln!(\"{café}\");");
+    }
+
+    #[tokio::test]
+    async fn set_notes_from_file_splices_after_an_accented_prefix() {
+        let (_repo, handle) = new_handle().await;
+        let notes = |status: &str| format!("crème brûlée: {}\n☕ café", status);
+        run(&handle, Command::SetNotes { text: notes("draft"), max_change_size: None, reject_oversized: false }).await;
+
+        let file = scratch_dir("accented-notes").join("notes.txt");
+        std::fs::write(&file, notes("served")).unwrap();
+        let load = Command::SetNotesFromFile {
+            path: file,
+            encoding: encoding_rs::UTF_8,
+            max_change_size: None,
+            reject_oversized: false,
+        };
+        run(&handle, load).await;
+        assert_eq!(state_of(&handle).notes.as_str(), notes("served"));
+    }
+
+    #[tokio::test]
+    async fn edit_todo_replaces_the_text_instead_of_prepending() {
+        let (_repo, handle) = new_handle().await;
+        run(&handle, Command::AddTodo { text: "old".to_string() }).await;
+        run(&handle, Command::AddTodo { text: "naïve café".to_string() }).await;
+        let ids: Vec<String> = state_of(&handle).todos.iter().map(|t| t.id.as_str().to_string()).collect();
+
+        run(&handle, Command::EditTodo { id: ids[0].clone(), text: "new".to_string() }).await;
+        run(&handle, Command::EditTodo { id: ids[1].clone(), text: "crème brûlée".to_string() }).await;
+        let texts: Vec<String> = state_of(&handle).todos.iter().map(|t| t.text.as_str().to_string()).collect();
+        assert_eq!(texts, ["new", "crème brûlée"]);
+    }
+
+    #[tokio::test]
+    async fn relabelling_replaces_the_title() {
+        // The only test here that touches the environment, so it can't race another
+        std::env::set_var("AUTODASH_CONFIG", scratch_dir("label").join("config.toml"));
+        let (_repo, handle) = new_handle().await;
+        run(&handle, Command::Label { label: "groceries".to_string() }).await;
+        run(&handle, Command::Label { label: "épicerie".to_string() }).await;
+
+        let title = state_of(&handle).metadata.title.map(|t| t.as_str().to_string());
+        assert_eq!(title.as_deref(), Some("épicerie"));
+        let url = format!("automerge:{}", handle.document_id());
+        assert_eq!(Config::resolve_alias("épicerie").unwrap(), Some(url));
+    }
+
+    #[tokio::test]
+    async fn a_partial_import_leaves_the_other_fields_alone() {
+        let (_repo, handle) = new_handle().await;
+        run(&handle, Command::SetNotes { text: "naïve notes".to_string(), max_change_size: None, reject_oversized: false }).await;
+        run(&handle, Command::AddTodo { text: "write tests".to_string() }).await;
+
+        let file = scratch_dir("partial-import").join("doc.json");
+        std::fs::write(&file, r#"{"counter": 7, "metadata": {"title": "Imported ✓"}}"#).unwrap();
+        run(&handle, Command::ImportJson { path: file.clone() }).await;
+        let state = state_of(&handle);
+        assert_eq!(state.counter, 7);
+        assert_eq!(state.metadata.title.map(|t| t.as_str().to_string()).as_deref(), Some("Imported ✓"));
+        assert_eq!(state.notes.as_str(), "naïve notes");
+        let texts: Vec<String> = state.todos.iter().map(|t| t.text.as_str().to_string()).collect();
+        assert_eq!(texts, ["write tests"]);
+
+        // Imported text replaces what was there rather than being prepended to it
+        std::fs::write(&file, r#"{"notes": "crème brûlée", "todos": ["buy milk"]}"#).unwrap();
+        run(&handle, Command::ImportJson { path: file }).await;
+        let state = state_of(&handle);
+        assert_eq!(state.notes.as_str(), "crème brûlée");
+        let texts: Vec<String> = state.todos.iter().map(|t| t.text.as_str().to_string()).collect();
+        assert_eq!(texts, ["buy milk"]);
+    }
+
+    #[tokio::test]
+    async fn overridden_fields_keep_their_stored_shape() {
+        use automerge::{transaction::Transactable, ReadDoc, ScalarValue, Value};
+
+        let (_repo, handle) = new_handle().await;
+        handle.with_document(|doc| {
+            ensure_schema(doc).unwrap();
+            doc.transact(|tx| {
+                tx.put(automerge::ROOT, "notes", "plain notes")?;
+                tx.put(automerge::ROOT, "darkMode", 0)
+            })
+            .unwrap();
+        });
+        let opts = ExecOptions {
+            overrides: TypeOverrides::parse(&["notes=str", "darkMode=int"]).unwrap(),
+            ..options()
+        };
+        let stored = |key: &str| {
+            handle.with_document(|doc| match doc.get(automerge::ROOT, key).unwrap() {
+                Some((Value::Scalar(s), _)) => s.into_owned(),
+                other => panic!("{} is no longer a scalar: {:?}", key, other),
+            })
+        };
+
+        // A command that doesn't touch notes or darkMode leaves them as they were
+        execute_command(&handle, &Command::SetTemp { value: 25, fahrenheit: false }, &opts).await.unwrap();
+        assert_eq!(stored("notes"), ScalarValue::Str("plain notes".into()));
+        assert_eq!(stored("darkMode"), ScalarValue::Int(0));
+
+        // And one that does writes them back in the same shape
+        let add = Command::AddNote { text: "more".to_string(), max_change_size: None, reject_oversized: false };
+        execute_command(&handle, &add, &opts).await.unwrap();
+        execute_command(&handle, &Command::ToggleDark, &opts).await.unwrap();
+        assert_eq!(stored("notes"), ScalarValue::Str("plain notes\nmore".into()));
+        assert_eq!(stored("darkMode"), ScalarValue::Int(1));
+    }
+
+    #[tokio::test]
+    async fn the_schema_is_only_written_by_a_command_that_commits() {
+        let (_repo, handle) = new_handle().await;
+        let quiet = ExecOptions { only_if_changed: true, ..options() };
+        execute_command(&handle, &Command::SetDark { enabled: false }, &quiet).await.unwrap();
+        assert!(handle.with_document(|doc| doc.get_heads()).is_empty());
+
+        let patch = scratch_dir("schema-patch").join("change.automerge");
+        let opts = ExecOptions { as_patch: Some(patch.clone()), ..options() };
+        execute_command(&handle, &Command::SetTemp { value: 21, fahrenheit: false }, &opts).await.unwrap();
+
+        // The schema is a change of its own, and the patch holds only the command's on top of it
+        let changes = handle.with_document(|doc| doc.get_changes(&[]));
+        assert_eq!(changes.len(), 2);
+        let change = automerge::Change::from_bytes(std::fs::read(&patch).unwrap()).unwrap();
+        assert_eq!(change.hash(), changes[1].hash());
+        assert_eq!(change.deps().to_vec(), vec![changes[0].hash()]);
+        assert_eq!(state_of(&handle).temperature, 21);
+    }
+
+    #[tokio::test]
+    async fn increments_on_two_forks_add_up_after_merging() {
+        let (repo, handle) = new_handle().await;
+        run(&handle, Command::SetCounter { value: 3, idempotency_key: None }).await;
+        let fork = repo.create(handle.with_document(|doc| doc.fork())).await.unwrap();
+
+        run(&handle, Command::Increment { idempotency_key: None }).await;
+        run(&fork, Command::Increment { idempotency_key: None }).await;
+        let mut theirs = fork.with_document(|doc| doc.fork());
+        handle.with_document(|doc| doc.merge(&mut theirs)).unwrap();
+
+        assert_eq!(state_of(&handle).counter, 5);
+    }
+}
@@ -0,0 +1,172 @@
+//! Ordered schema migrations keyed on the document's `schemaVersion`.
+//!
+//! Documents written before `schemaVersion` existed count as version 1. Each
+//! migration only creates what's missing, so running one against a document
+//! that already has the field (e.g. one filled in by `ensure_schema`) is harmless.
+
+use crate::Result;
+use automerge::{
+    transaction::{Transactable, Transaction},
+    Automerge, AutomergeError, ObjType, ReadDoc, ScalarValue, Value,
+};
+
+/// The schema version documents are at once every migration has run
+pub const CURRENT_SCHEMA_VERSION: i64 = 6;
+
+/// One step in the schema's history
+pub struct Migration {
+    /// The version this migration upgrades a document to
+    pub to: i64,
+    pub description: &'static str,
+    apply: fn(&mut Transaction<'_>) -> Result<(), AutomergeError>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        to: 2,
+        description: "add the code text field",
+        apply: add_code,
+    },
+    Migration {
+        to: 3,
+        description: "add the settings map",
+        apply: add_settings,
+    },
+    Migration {
+        to: 4,
+        description: "add the attachments list",
+        apply: add_attachments,
+    },
+    Migration {
+        to: 5,
+        description: "store the counter as an Automerge Counter",
+        apply: counter_to_crdt,
+    },
+    Migration {
+        to: 6,
+        description: "add the notes sections map",
+        apply: add_sections,
+    },
+];
+
+fn put_object_if_missing(
+    tx: &mut Transaction<'_>,
+    key: &str,
+    obj_type: ObjType,
+) -> Result<(), AutomergeError> {
+    if tx.get(automerge::ROOT, key)?.is_none() {
+        tx.put_object(automerge::ROOT, key, obj_type)?;
+    }
+    Ok(())
+}
+
+fn add_code(tx: &mut Transaction<'_>) -> Result<(), AutomergeError> {
+    put_object_if_missing(tx, "code", ObjType::Text)
+}
+
+fn add_settings(tx: &mut Transaction<'_>) -> Result<(), AutomergeError> {
+    put_object_if_missing(tx, "settings", ObjType::Map)
+}
+
+fn add_attachments(tx: &mut Transaction<'_>) -> Result<(), AutomergeError> {
+    put_object_if_missing(tx, "attachments", ObjType::List)
+}
+
+fn add_sections(tx: &mut Transaction<'_>) -> Result<(), AutomergeError> {
+    put_object_if_missing(tx, "sections", ObjType::Map)
+}
+
+fn counter_to_crdt(tx: &mut Transaction<'_>) -> Result<(), AutomergeError> {
+    let value = match tx.get(automerge::ROOT, "counter")? {
+        Some((Value::Scalar(s), _)) => match s.as_ref() {
+            ScalarValue::Counter(_) => return Ok(()),
+            ScalarValue::Int(i) => *i,
+            ScalarValue::Uint(u) => *u as i64,
+            _ => 0,
+        },
+        _ => 0,
+    };
+    tx.put(automerge::ROOT, "counter", ScalarValue::counter(value))?;
+    Ok(())
+}
+
+/// The document's schema version; documents without one are version 1
+pub fn schema_version<D: ReadDoc>(doc: &D) -> Result<i64> {
+    match doc.get(automerge::ROOT, "schemaVersion")? {
+        Some((Value::Scalar(s), _)) => match s.as_ref() {
+            ScalarValue::Int(i) => Ok(*i),
+            ScalarValue::Uint(u) => Ok(*u as i64),
+            _ => Ok(1),
+        },
+        _ => Ok(1),
+    }
+}
+
+/// Run every migration newer than the document's version, in order and in a
+/// single transaction, then bump `schemaVersion`. Returns the migrations that ran.
+pub fn migrate(doc: &mut Automerge) -> Result<Vec<&'static Migration>> {
+    let from = schema_version(doc)?;
+    let pending: Vec<&'static Migration> = MIGRATIONS.iter().filter(|m| m.to > from).collect();
+    if pending.is_empty() {
+        return Ok(pending);
+    }
+
+    doc.transact(|tx| {
+        for migration in &pending {
+            (migration.apply)(tx)?;
+        }
+        tx.put(automerge::ROOT, "schemaVersion", CURRENT_SCHEMA_VERSION)?;
+        Ok::<_, AutomergeError>(())
+    })
+    .map_err(|failure| failure.error)?;
+
+    Ok(pending)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A document as the first release wrote it: no code, settings,
+    /// attachments or sections, and a plain integer counter
+    fn v1_document() -> Automerge {
+        let mut doc = Automerge::new();
+        doc.transact(|tx| {
+            tx.put(automerge::ROOT, "counter", 7)?;
+            tx.put(automerge::ROOT, "temperature", 20)?;
+            let notes = tx.put_object(automerge::ROOT, "notes", ObjType::Text)?;
+            tx.splice_text(&notes, 0, 0, "old notes")?;
+            tx.put_object(automerge::ROOT, "todos", ObjType::List)?;
+            Ok::<_, AutomergeError>(())
+        })
+        .unwrap();
+        doc
+    }
+
+    #[test]
+    fn a_v1_document_migrates_to_the_current_version() {
+        let mut doc = v1_document();
+        assert_eq!(schema_version(&doc).unwrap(), 1);
+
+        let ran: Vec<i64> = migrate(&mut doc).unwrap().iter().map(|m| m.to).collect();
+        assert_eq!(ran, (2..=CURRENT_SCHEMA_VERSION).collect::<Vec<_>>());
+        assert_eq!(schema_version(&doc).unwrap(), CURRENT_SCHEMA_VERSION);
+
+        for (key, obj_type) in [
+            ("code", ObjType::Text),
+            ("settings", ObjType::Map),
+            ("attachments", ObjType::List),
+            ("sections", ObjType::Map),
+        ] {
+            let (value, _) = doc.get(automerge::ROOT, key).unwrap().unwrap();
+            assert_eq!(value, Value::Object(obj_type), "{}", key);
+        }
+        let (counter, _) = doc.get(automerge::ROOT, "counter").unwrap().unwrap();
+        assert_eq!(counter, Value::counter(7));
+        let (_, notes) = doc.get(automerge::ROOT, "notes").unwrap().unwrap();
+        assert_eq!(doc.text(&notes).unwrap(), "old notes");
+
+        // Already current, so nothing runs
+        assert!(migrate(&mut doc).unwrap().is_empty());
+    }
+}
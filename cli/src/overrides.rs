@@ -0,0 +1,326 @@
+//! Runtime overrides for fields that clients store in different shapes.
+//!
+//! JavaScript clients don't agree on how to store a few fields: notes may be a
+//! plain string or Text, the counter an int or a Counter, darkMode a boolean
+//! or 0/1. The derived hydrate expects one shape per field and fails on the
+//! others, so `--as notes=str,darkMode=int` tells [`hydrate_with_overrides`]
+//! how a particular document stores them. Fields without an override hydrate
+//! exactly as they would with a plain `hydrate`.
+//!
+//! [`reconcile_with_overrides`] writes overridden fields back in the same shape,
+//! and only when a command changed them, so reading a document with `--as`
+//! never converts its fields to the CLI's usual shapes (Text, Int, Boolean).
+
+use crate::{
+    hydrate_or_default, hydrate_string_map, hydrate_text_map, reconcile_diff, AutodashError, Doc,
+    Result,
+};
+use automerge::{transaction::Transactable, ObjType, ReadDoc, ScalarValue, Value};
+
+/// How a field is stored in the document
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoredAs {
+    Text,
+    Str,
+    Int,
+    Counter,
+    Bool,
+}
+
+impl StoredAs {
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "text" => Some(StoredAs::Text),
+            "str" | "string" => Some(StoredAs::Str),
+            "int" => Some(StoredAs::Int),
+            "counter" => Some(StoredAs::Counter),
+            "bool" | "boolean" => Some(StoredAs::Bool),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            StoredAs::Text => "text",
+            StoredAs::Str => "str",
+            StoredAs::Int => "int",
+            StoredAs::Counter => "counter",
+            StoredAs::Bool => "bool",
+        }
+    }
+}
+
+/// The fields that can be overridden, and the shapes each can be read from
+const OVERRIDABLE: &[(&str, &[StoredAs], &str)] = &[
+    ("counter", &[StoredAs::Int, StoredAs::Counter], "int or counter"),
+    ("temperature", &[StoredAs::Int, StoredAs::Counter], "int or counter"),
+    ("darkMode", &[StoredAs::Bool, StoredAs::Int], "bool or int"),
+    ("notes", &[StoredAs::Text, StoredAs::Str], "text or str"),
+    ("code", &[StoredAs::Text, StoredAs::Str], "text or str"),
+];
+
+/// Per-field storage shapes, parsed from `field=type` pairs
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TypeOverrides {
+    fields: Vec<(&'static str, StoredAs)>,
+}
+
+impl TypeOverrides {
+    /// Parse `field=type` pairs such as `notes=str` or `darkMode=int`
+    pub fn parse<S: AsRef<str>>(pairs: &[S]) -> Result<Self> {
+        let mut overrides = TypeOverrides::default();
+        for pair in pairs {
+            let pair = pair.as_ref().trim();
+            let (field, ty) = pair.split_once('=').ok_or_else(|| AutodashError::InvalidValue {
+                path: "--as".to_string(),
+                expected: "field=type",
+                value: pair.to_string(),
+            })?;
+            let (field, ty) = (field.trim(), ty.trim());
+
+            let &(name, allowed, expected) = OVERRIDABLE
+                .iter()
+                .find(|(name, ..)| name.eq_ignore_ascii_case(field))
+                .ok_or_else(|| AutodashError::InvalidValue {
+                    path: "--as".to_string(),
+                    expected: "one of counter, temperature, darkMode, notes, code",
+                    value: field.to_string(),
+                })?;
+            let stored = StoredAs::parse(ty)
+                .filter(|stored| allowed.contains(stored))
+                .ok_or_else(|| AutodashError::InvalidValue {
+                    path: format!("--as {}", name),
+                    expected,
+                    value: ty.to_string(),
+                })?;
+
+            if overrides.get(name).is_some() {
+                return Err(AutodashError::DuplicateField(name.to_string()));
+            }
+            overrides.fields.push((name, stored));
+        }
+        Ok(overrides)
+    }
+
+    pub fn get(&self, field: &str) -> Option<StoredAs> {
+        self.fields.iter().find(|(name, _)| *name == field).map(|(_, stored)| *stored)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+fn mismatch(field: &str, stored: StoredAs, found: &Value) -> AutodashError {
+    AutodashError::Schema {
+        path: field.to_string(),
+        problem: format!("should be stored as {}, found {}", stored.name(), found),
+    }
+}
+
+fn read_int<D: ReadDoc>(doc: &D, field: &str, stored: StoredAs) -> Result<i64> {
+    let Some((value, _)) = doc.get(automerge::ROOT, field)? else {
+        return Ok(0);
+    };
+    match (&value, stored) {
+        (Value::Scalar(s), StoredAs::Int) => match s.as_ref() {
+            ScalarValue::Int(i) => return Ok(*i),
+            ScalarValue::Uint(u) => return Ok(*u as i64),
+            _ => {}
+        },
+        (Value::Scalar(s), StoredAs::Counter) => {
+            if let ScalarValue::Counter(_) = s.as_ref() {
+                return Ok(s.to_i64().unwrap_or_default());
+            }
+        }
+        _ => {}
+    }
+    Err(mismatch(field, stored, &value))
+}
+
+fn read_bool<D: ReadDoc>(doc: &D, field: &str, stored: StoredAs) -> Result<bool> {
+    let Some((value, _)) = doc.get(automerge::ROOT, field)? else {
+        return Ok(false);
+    };
+    if let Value::Scalar(s) = &value {
+        match (s.as_ref(), stored) {
+            (ScalarValue::Boolean(b), StoredAs::Bool) => return Ok(*b),
+            (ScalarValue::Int(i), StoredAs::Int) => return Ok(*i != 0),
+            (ScalarValue::Uint(u), StoredAs::Int) => return Ok(*u != 0),
+            _ => {}
+        }
+    }
+    Err(mismatch(field, stored, &value))
+}
+
+fn read_text<D: ReadDoc>(doc: &D, field: &str, stored: StoredAs) -> Result<autosurgeon::Text> {
+    let Some((value, _)) = doc.get(automerge::ROOT, field)? else {
+        return Ok(autosurgeon::Text::with_value(""));
+    };
+    match (&value, stored) {
+        (Value::Object(ObjType::Text), StoredAs::Text) => {
+            Ok(autosurgeon::hydrate_prop(doc, automerge::ROOT, field)?)
+        }
+        (Value::Scalar(s), StoredAs::Str) => match s.to_str() {
+            Some(s) => Ok(autosurgeon::Text::with_value(s)),
+            None => Err(mismatch(field, stored, &value)),
+        },
+        _ => Err(mismatch(field, stored, &value)),
+    }
+}
+
+/// Hydrate a [`Doc`], reading overridden fields in the shape `overrides` gives
+pub fn hydrate_with_overrides<D: ReadDoc>(doc: &D, overrides: &TypeOverrides) -> Result<Doc> {
+    if overrides.is_empty() {
+        return Ok(autosurgeon::hydrate(doc)?);
+    }
+
+    let root = automerge::ROOT;
+    let int = |field: &str| -> Result<i64> {
+        match overrides.get(field) {
+            Some(stored) => read_int(doc, field, stored),
+            None => Ok(autosurgeon::hydrate_prop(doc, &root, field)?),
+        }
+    };
+    let text = |field: &str| -> Result<autosurgeon::Text> {
+        match overrides.get(field) {
+            Some(stored) => read_text(doc, field, stored),
+            None => Ok(autosurgeon::hydrate_prop(doc, &root, field)?),
+        }
+    };
+    let dark_mode = match overrides.get("darkMode") {
+        Some(stored) => read_bool(doc, "darkMode", stored)?,
+        None => autosurgeon::hydrate_prop(doc, &root, "darkMode")?,
+    };
+
+    let counter = match overrides.get("counter") {
+        Some(stored) => read_int(doc, "counter", stored)?,
+        None => crate::hydrate_counter(doc, &root, "counter".into())?,
+    };
+
+    Ok(Doc {
+        counter,
+        temperature: int("temperature")?,
+        darkMode: dark_mode,
+        notes: text("notes")?,
+        sections: hydrate_text_map(doc, &root, "sections".into())?,
+        code: text("code")?,
+        tags: autosurgeon::hydrate_prop(doc, &root, "tags")?,
+        todos: autosurgeon::hydrate_prop(doc, &root, "todos")?,
+        metadata: autosurgeon::hydrate_prop(doc, &root, "metadata")?,
+        settings: hydrate_string_map(doc, &root, "settings".into())?,
+        attachments: hydrate_or_default(doc, &root, "attachments".into())?,
+        schemaVersion: autosurgeon::hydrate_prop(doc, &root, "schemaVersion")?,
+    })
+}
+
+/// Reconcile `desired` over `current`, keeping overridden fields in the shape
+/// `overrides` says they're stored in.
+///
+/// Only the fields that changed are written (see [`reconcile_diff`]); an
+/// overridden one is put back as the same scalar or counter it was read from.
+pub fn reconcile_with_overrides<D: autosurgeon::Doc + Transactable>(
+    tx: &mut D,
+    current: &Doc,
+    desired: &Doc,
+    overrides: &TypeOverrides,
+    max_splice_bytes: Option<usize>,
+) -> Result<()> {
+    // Hide overridden changes from the regular reconcile, which would reshape them
+    let mut regular = desired.clone();
+    for &(field, stored) in &overrides.fields {
+        match field {
+            "counter" => regular.counter = current.counter,
+            "temperature" => regular.temperature = current.temperature,
+            "darkMode" => regular.darkMode = current.darkMode,
+            "notes" if stored == StoredAs::Str => regular.notes = current.notes.clone(),
+            "code" if stored == StoredAs::Str => regular.code = current.code.clone(),
+            _ => {}
+        }
+    }
+    reconcile_diff(tx, current, &regular, max_splice_bytes)?;
+    write_overridden(tx, current, desired, overrides)
+}
+
+/// Put each changed overridden field back in its stored shape
+fn write_overridden<T: Transactable>(
+    tx: &mut T,
+    current: &Doc,
+    desired: &Doc,
+    overrides: &TypeOverrides,
+) -> Result<()> {
+    let root = automerge::ROOT;
+    for &(field, stored) in &overrides.fields {
+        match field {
+            "counter" | "temperature" => {
+                let (from, to) = match field {
+                    "counter" => (current.counter, desired.counter),
+                    _ => (current.temperature, desired.temperature),
+                };
+                if from == to {
+                    continue;
+                }
+                match stored {
+                    StoredAs::Counter if tx.get(&root, field)?.is_some() => {
+                        tx.increment(&root, field, to - from)?
+                    }
+                    StoredAs::Counter => tx.put(&root, field, ScalarValue::counter(to))?,
+                    _ => tx.put(&root, field, to)?,
+                }
+            }
+            "darkMode" if current.darkMode != desired.darkMode => match stored {
+                StoredAs::Int => tx.put(&root, field, i64::from(desired.darkMode))?,
+                _ => tx.put(&root, field, desired.darkMode)?,
+            },
+            "notes" | "code" if stored == StoredAs::Str => {
+                let (from, to) = match field {
+                    "notes" => (&current.notes, &desired.notes),
+                    _ => (&current.code, &desired.code),
+                };
+                if from.as_str() != to.as_str() {
+                    tx.put(&root, field, to.as_str())?;
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use automerge::{transaction::Transactable, Automerge};
+
+    /// A schema-complete document with darkMode written as 0/1, as some JavaScript clients do
+    fn dark_mode_as_int(value: i64) -> Automerge {
+        let mut doc = Automerge::new();
+        crate::ensure_schema(&mut doc).unwrap();
+        doc.transact(|tx| tx.put(automerge::ROOT, "darkMode", value)).unwrap();
+        doc
+    }
+
+    #[test]
+    fn an_int_dark_mode_hydrates_only_with_an_override() {
+        let doc = dark_mode_as_int(1);
+        assert!(hydrate_with_overrides(&doc, &TypeOverrides::default()).is_err());
+        let as_bool = TypeOverrides::parse(&["darkMode=bool"]).unwrap();
+        assert!(hydrate_with_overrides(&doc, &as_bool).is_err());
+
+        let as_int = TypeOverrides::parse(&["darkMode=int"]).unwrap();
+        assert!(hydrate_with_overrides(&doc, &as_int).unwrap().darkMode);
+        assert!(!hydrate_with_overrides(&dark_mode_as_int(0), &as_int).unwrap().darkMode);
+    }
+
+    #[test]
+    fn parse_rejects_unsupported_shapes_and_repeats() {
+        assert!(TypeOverrides::parse(&["darkMode=str"]).is_err());
+        assert!(TypeOverrides::parse(&["tags=str"]).is_err());
+        assert!(TypeOverrides::parse(&["notes"]).is_err());
+        assert!(matches!(
+            TypeOverrides::parse(&["notes=str", "NOTES=text"]),
+            Err(AutodashError::DuplicateField(_))
+        ));
+        assert_eq!(TypeOverrides::parse(&["DarkMode = INT"]).unwrap().get("darkMode"), Some(StoredAs::Int));
+    }
+}
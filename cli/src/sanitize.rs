@@ -0,0 +1,81 @@
+//! Screening text typed at the shell before it's written to the shared document.
+//!
+//! Control characters other than newline and tab don't render in the dashboard
+//! box or the TUI, and once synced they scramble the display for every peer.
+//! Arguments have to be valid UTF-8 to reach clap at all, so lone surrogates
+//! can't get this far; control characters are what's left to check.
+
+use crate::{AutodashError, Result};
+
+/// What `--strict-utf8` does with control characters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ControlChars {
+    /// Refuse the command
+    Reject,
+    /// Remove them and carry on
+    Strip,
+}
+
+fn is_disallowed(c: char) -> bool {
+    c.is_control() && c != '\n' && c != '\t'
+}
+
+/// Screen `text`, the value being written to `path`, under `policy`.
+///
+/// Returns the text to write and the characters that were stripped from it.
+pub fn screen_text(path: &str, text: &str, policy: ControlChars) -> Result<(String, Vec<char>)> {
+    let found: Vec<char> = text.chars().filter(|&c| is_disallowed(c)).collect();
+    if found.is_empty() {
+        return Ok((text.to_string(), found));
+    }
+    match policy {
+        ControlChars::Reject => Err(AutodashError::ControlCharacters {
+            path: path.to_string(),
+            found: describe_chars(&found),
+        }),
+        ControlChars::Strip => {
+            let kept = text.chars().filter(|&c| !is_disallowed(c)).collect();
+            Ok((kept, found))
+        }
+    }
+}
+
+/// Characters as a `U+0000, U+001B` list
+pub fn describe_chars(chars: &[char]) -> String {
+    chars
+        .iter()
+        .map(|c| format!("U+{:04X}", *c as u32))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_nul_byte_is_rejected_or_stripped_per_the_policy() {
+        let text = "before\0after\n\tindented";
+        match screen_text("notes", text, ControlChars::Reject) {
+            Err(AutodashError::ControlCharacters { path, found }) => {
+                assert_eq!(path, "notes");
+                assert_eq!(found, "U+0000");
+            }
+            other => panic!("expected the NUL to be rejected, got {:?}", other),
+        }
+
+        let (kept, stripped) = screen_text("notes", text, ControlChars::Strip).unwrap();
+        assert_eq!(kept, "beforeafter\n\tindented");
+        assert_eq!(stripped, vec!['\0']);
+    }
+
+    #[test]
+    fn clean_text_passes_either_policy() {
+        for policy in [ControlChars::Reject, ControlChars::Strip] {
+            let (kept, stripped) = screen_text("notes", "héllo\nwörld", policy).unwrap();
+            assert_eq!(kept, "héllo\nwörld");
+            assert!(stripped.is_empty());
+        }
+        assert_eq!(describe_chars(&['\0', '\u{1b}']), "U+0000, U+001B");
+    }
+}
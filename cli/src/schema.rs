@@ -0,0 +1,228 @@
+//! Strict schema checks for documents.
+//!
+//! The regular hydrate helpers are lenient: a missing or wrong-typed field
+//! quietly becomes an empty string, `None` or an empty list. That keeps the
+//! CLI working against older documents but can hide schema drift, so
+//! [`hydrate_strict`] first checks that every required field is present with
+//! the expected type.
+//!
+//! Required: `counter`, `temperature`, `darkMode`, `notes`, `code`, `tags`,
+//! `todos`, `metadata`, `metadata.createdAt` and `metadata.lastModified`.
+//! Optional: `settings`, `attachments`, `sections`, `metadata.title`,
+//! `metadata.appliedKeys` and `metadata.locks`, which older documents may not have.
+//!
+//! Documents created by minimal clients can be missing top-level fields
+//! altogether, which a plain hydrate rejects. [`ensure_schema`] fills those in
+//! with defaults before the first write.
+
+use crate::{hydrate_or_default, AutodashError, Doc, Result};
+use automerge::{ObjType, ReadDoc, ScalarValue, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Number,
+    Boolean,
+    Timestamp,
+    Text,
+    List,
+    Map,
+}
+
+impl Kind {
+    fn describe(self) -> &'static str {
+        match self {
+            Kind::Number => "a number",
+            Kind::Boolean => "a boolean",
+            Kind::Timestamp => "a timestamp",
+            Kind::Text => "text",
+            Kind::List => "a list",
+            Kind::Map => "a map",
+        }
+    }
+
+    fn matches(self, value: &Value) -> bool {
+        match (self, value) {
+            (Kind::Number, Value::Scalar(s)) => matches!(
+                s.as_ref(),
+                ScalarValue::Int(_) | ScalarValue::Uint(_) | ScalarValue::F64(_) | ScalarValue::Counter(_)
+            ),
+            (Kind::Boolean, Value::Scalar(s)) => matches!(s.as_ref(), ScalarValue::Boolean(_)),
+            (Kind::Timestamp, Value::Scalar(s)) => matches!(
+                s.as_ref(),
+                ScalarValue::Timestamp(_) | ScalarValue::Int(_) | ScalarValue::Uint(_) | ScalarValue::F64(_)
+            ),
+            (Kind::Text, Value::Object(ObjType::Text)) => true,
+            (Kind::List, Value::Object(ObjType::List)) => true,
+            (Kind::Map, Value::Object(ObjType::Map)) => true,
+            _ => false,
+        }
+    }
+}
+
+const REQUIRED_ROOT_FIELDS: &[(&str, Kind)] = &[
+    ("counter", Kind::Number),
+    ("temperature", Kind::Number),
+    ("darkMode", Kind::Boolean),
+    ("notes", Kind::Text),
+    ("code", Kind::Text),
+    ("tags", Kind::List),
+    ("todos", Kind::List),
+    ("metadata", Kind::Map),
+];
+
+/// Every top-level key a fully initialised document has
+const ROOT_FIELDS: &[&str] = &[
+    "counter",
+    "temperature",
+    "darkMode",
+    "notes",
+    "code",
+    "tags",
+    "todos",
+    "metadata",
+    "settings",
+    "attachments",
+    "sections",
+];
+
+const REQUIRED_METADATA_FIELDS: &[(&str, Kind)] = &[
+    ("createdAt", Kind::Timestamp),
+    ("lastModified", Kind::Timestamp),
+];
+
+fn check_fields<D: ReadDoc>(
+    doc: &D,
+    obj: &automerge::ObjId,
+    prefix: &str,
+    fields: &[(&str, Kind)],
+) -> Result<()> {
+    for (name, kind) in fields {
+        let path = format!("{}{}", prefix, name);
+        match doc.get(obj, *name)? {
+            None => {
+                return Err(AutodashError::Schema {
+                    path,
+                    problem: "is missing".to_string(),
+                })
+            }
+            Some((value, _)) if !kind.matches(&value) => {
+                return Err(AutodashError::Schema {
+                    path,
+                    problem: format!("should be {}, found {}", kind.describe(), value),
+                })
+            }
+            Some(_) => {}
+        }
+    }
+    Ok(())
+}
+
+/// Check that every required field is present with the expected type
+pub fn check_schema<D: ReadDoc>(doc: &D) -> Result<()> {
+    check_fields(doc, &automerge::ROOT, "", REQUIRED_ROOT_FIELDS)?;
+    if let Some((_, metadata)) = doc.get(automerge::ROOT, "metadata")? {
+        check_fields(doc, &metadata, "metadata.", REQUIRED_METADATA_FIELDS)?;
+    }
+    Ok(())
+}
+
+/// Hydrate a [`Doc`], failing instead of defaulting when a required field is absent
+pub fn hydrate_strict<D: ReadDoc>(doc: &D) -> Result<Doc> {
+    check_schema(doc)?;
+    Ok(autosurgeon::hydrate(doc)?)
+}
+
+/// Top-level keys the document doesn't have yet
+pub fn missing_fields<D: ReadDoc>(doc: &D) -> Vec<&'static str> {
+    ROOT_FIELDS
+        .iter()
+        .copied()
+        .filter(|name| doc.get(automerge::ROOT, *name).ok().flatten().is_none())
+        .collect()
+}
+
+/// Hydrate a [`Doc`], treating every missing top-level field as its default
+fn hydrate_lenient<D: ReadDoc>(doc: &D) -> Result<Doc> {
+    let root = automerge::ROOT;
+    Ok(Doc {
+        counter: crate::hydrate_counter(doc, &root, "counter".into())?,
+        temperature: hydrate_or_default(doc, &root, "temperature".into())?,
+        darkMode: hydrate_or_default(doc, &root, "darkMode".into())?,
+        notes: hydrate_or_default(doc, &root, "notes".into())?,
+        sections: crate::hydrate_text_map(doc, &root, "sections".into())?,
+        code: hydrate_or_default(doc, &root, "code".into())?,
+        tags: hydrate_or_default(doc, &root, "tags".into())?,
+        todos: hydrate_or_default(doc, &root, "todos".into())?,
+        metadata: hydrate_or_default(doc, &root, "metadata".into())?,
+        settings: crate::hydrate_string_map(doc, &root, "settings".into())?,
+        attachments: hydrate_or_default(doc, &root, "attachments".into())?,
+        schemaVersion: hydrate_or_default(doc, &root, "schemaVersion".into())?,
+    })
+}
+
+/// Make sure every top-level field exists with the right type.
+///
+/// Missing fields are written with their defaults, merged with whatever the
+/// document already has, in one transaction. Returns whether anything was added;
+/// a complete document is left untouched.
+pub fn ensure_schema(doc: &mut automerge::Automerge) -> Result<bool> {
+    if missing_fields(doc).is_empty() {
+        return Ok(false);
+    }
+
+    let mut state = hydrate_lenient(doc)?;
+    if state.metadata.createdAt.is_none() {
+        state.metadata.createdAt = Some(chrono::Utc::now().timestamp_millis());
+    }
+    doc.transact(|tx| {
+        autosurgeon::reconcile(tx, &state)?;
+        crate::write_counter(tx, state.counter, state.counter)?;
+        Ok::<_, autosurgeon::ReconcileError>(())
+    })
+    .map_err(|failure| AutodashError::Reconcile(failure.error))?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use automerge::{transaction::Transactable, Automerge};
+
+    fn doc_without(field: &str) -> Automerge {
+        let mut doc = Automerge::new();
+        ensure_schema(&mut doc).unwrap();
+        doc.transact(|tx| tx.delete(automerge::ROOT, field)).unwrap();
+        doc
+    }
+
+    #[test]
+    fn strict_hydrate_rejects_a_missing_field() {
+        let doc = doc_without("code");
+        match hydrate_strict(&doc) {
+            Err(AutodashError::Schema { path, problem }) => {
+                assert_eq!(path, "code");
+                assert_eq!(problem, "is missing");
+            }
+            other => panic!("expected a schema error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn lenient_hydrate_fills_in_a_missing_field() {
+        let mut doc = doc_without("code");
+        assert_eq!(missing_fields(&doc), vec!["code"]);
+        assert!(ensure_schema(&mut doc).unwrap());
+        let state = hydrate_strict(&doc).unwrap();
+        assert_eq!(state.code.as_str(), "");
+        assert!(!ensure_schema(&mut doc).unwrap());
+    }
+
+    #[test]
+    fn ensure_schema_initialises_an_empty_document() {
+        let mut doc = Automerge::new();
+        assert_eq!(missing_fields(&doc).len(), ROOT_FIELDS.len());
+        assert!(ensure_schema(&mut doc).unwrap());
+        assert!(missing_fields(&doc).is_empty());
+        check_schema(&doc).unwrap();
+    }
+}
@@ -0,0 +1,143 @@
+//! Document-wide text search across the Autodash text fields.
+
+use crate::{AutodashError, Doc, Result};
+use regex::{Regex, RegexBuilder};
+
+/// Characters of context kept on each side of a match in its snippet
+const SNIPPET_CONTEXT: usize = 30;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SearchOptions {
+    pub case_sensitive: bool,
+    /// Treat the term as a regular expression instead of a plain substring
+    pub regex: bool,
+}
+
+/// One match: where it was found and the surrounding text
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct Match {
+    /// e.g. `notes:3` (line 3), `todos[<id>]`, `tags`, `metadata.title`
+    pub field: String,
+    pub snippet: String,
+}
+
+fn build_pattern(term: &str, options: SearchOptions) -> Result<Regex> {
+    let pattern = if options.regex {
+        term.to_string()
+    } else {
+        regex::escape(term)
+    };
+    RegexBuilder::new(&pattern)
+        .case_insensitive(!options.case_sensitive)
+        .build()
+        .map_err(|_| AutodashError::InvalidValue {
+            path: "search".to_string(),
+            expected: "a valid regular expression",
+            value: term.to_string(),
+        })
+}
+
+/// `text` around the match at `start..end`, with ellipses where it was cut
+fn snippet(text: &str, start: usize, end: usize) -> String {
+    let before: Vec<char> = text[..start].chars().collect();
+    let after: Vec<char> = text[end..].chars().collect();
+    let lead = before.len().saturating_sub(SNIPPET_CONTEXT);
+    let tail = after.len().min(SNIPPET_CONTEXT);
+
+    let mut out = String::new();
+    if lead > 0 {
+        out.push('…');
+    }
+    out.extend(&before[lead..]);
+    out.push_str(&text[start..end]);
+    out.extend(&after[..tail]);
+    if tail < after.len() {
+        out.push('…');
+    }
+    out
+}
+
+fn search_lines(pattern: &Regex, field: &str, text: &str, matches: &mut Vec<Match>) {
+    for (number, line) in text.lines().enumerate() {
+        for found in pattern.find_iter(line) {
+            matches.push(Match {
+                field: format!("{}:{}", field, number + 1),
+                snippet: snippet(line, found.start(), found.end()),
+            });
+        }
+    }
+}
+
+fn search_value(pattern: &Regex, field: String, text: &str, matches: &mut Vec<Match>) {
+    if let Some(found) = pattern.find(text) {
+        matches.push(Match {
+            field,
+            snippet: snippet(text, found.start(), found.end()),
+        });
+    }
+}
+
+/// Search notes, code, todo texts, tags and the title for `term`
+pub fn search_doc(doc: &Doc, term: &str, options: SearchOptions) -> Result<Vec<Match>> {
+    let pattern = build_pattern(term, options)?;
+    let mut matches = Vec::new();
+
+    search_lines(&pattern, "notes", doc.notes.as_str(), &mut matches);
+    search_lines(&pattern, "code", doc.code.as_str(), &mut matches);
+    for todo in &doc.todos {
+        search_value(
+            &pattern,
+            format!("todos[{}]", todo.id.as_str()),
+            todo.text.as_str(),
+            &mut matches,
+        );
+    }
+    for tag in &doc.tags {
+        search_value(&pattern, "tags".to_string(), tag, &mut matches);
+    }
+    if let Some(title) = &doc.metadata.title {
+        search_value(&pattern, "metadata.title".to_string(), title.as_str(), &mut matches);
+    }
+
+    Ok(matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TodoItem;
+
+    fn doc_with(notes: &str, todos: &[&str]) -> Doc {
+        let mut doc = automerge::Automerge::new();
+        crate::ensure_schema(&mut doc).unwrap();
+        let mut state: Doc = autosurgeon::hydrate(&doc).unwrap();
+        state.notes = autosurgeon::Text::with_value(notes);
+        state.todos = todos.iter().map(|text| TodoItem::new(text, false)).collect();
+        state
+    }
+
+    #[test]
+    fn matches_in_notes_report_the_line() {
+        let state = doc_with("shopping\nbuy Milk and eggs", &[]);
+        let matches = search_doc(&state, "milk", SearchOptions::default()).unwrap();
+        assert_eq!(
+            matches,
+            vec![Match { field: "notes:2".to_string(), snippet: "buy Milk and eggs".to_string() }]
+        );
+
+        let strict = SearchOptions { case_sensitive: true, ..Default::default() };
+        assert!(search_doc(&state, "milk", strict).unwrap().is_empty());
+    }
+
+    #[test]
+    fn matches_in_todo_text_report_the_todo() {
+        let state = doc_with("", &["water plants", "call the plumber"]);
+        let matches = search_doc(&state, "plum", SearchOptions::default()).unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].field, format!("todos[{}]", state.todos[1].id.as_str()));
+        assert_eq!(matches[0].snippet, "call the plumber");
+
+        let regex = SearchOptions { regex: true, ..Default::default() };
+        assert_eq!(search_doc(&state, "^(water|call) ", regex).unwrap().len(), 2);
+    }
+}
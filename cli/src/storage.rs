@@ -0,0 +1,99 @@
+//! Read-only views of samod's filesystem storage (`./autodash-data/` by default).
+//!
+//! The filesystem backend stores each chunk of a document under a directory
+//! named after the document id, split after its first two characters to keep
+//! directories small: `<dir>/<id[..2]>/<id[2..]>/<kind>/<hash>`.
+
+use crate::Result;
+use automerge::Automerge;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// One document's files in local storage
+#[derive(Debug, Clone)]
+pub struct StoredDocument {
+    pub id: String,
+    /// Directory holding all of this document's chunks
+    pub dir: PathBuf,
+    pub files: Vec<PathBuf>,
+    /// Total size of the chunk files in bytes
+    pub size: u64,
+    /// Most recent modification time of any chunk
+    pub modified: Option<SystemTime>,
+}
+
+impl StoredDocument {
+    /// Load the document by applying every stored chunk
+    pub fn load(&self) -> Result<Automerge> {
+        let mut doc = Automerge::new();
+        for file in &self.files {
+            doc.load_incremental(&std::fs::read(file)?)?;
+        }
+        Ok(doc)
+    }
+}
+
+/// Recursively list every file under `dir`
+pub fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Group the files under `dir` by document. A missing directory has no documents.
+pub fn list_stored_documents(dir: &Path) -> Result<Vec<StoredDocument>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut files = Vec::new();
+    collect_files(dir, &mut files)?;
+
+    let mut documents: Vec<StoredDocument> = Vec::new();
+    for file in files {
+        let Ok(relative) = file.strip_prefix(dir) else {
+            continue;
+        };
+        let parts: Vec<String> = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        // Files directly in the storage root (e.g. probes) don't belong to a document
+        if parts.len() < 2 {
+            continue;
+        }
+        // Re-join the two-character prefix directory with the rest of the id
+        let (id, depth) = if parts[0].len() == 2 && parts.len() > 2 {
+            (format!("{}{}", parts[0], parts[1]), 2)
+        } else {
+            (parts[0].clone(), 1)
+        };
+
+        let metadata = std::fs::metadata(&file)?;
+        let index = match documents.iter().position(|d| d.id == id) {
+            Some(index) => index,
+            None => {
+                documents.push(StoredDocument {
+                    id,
+                    dir: parts[..depth].iter().fold(dir.to_path_buf(), |p, c| p.join(c)),
+                    files: Vec::new(),
+                    size: 0,
+                    modified: None,
+                });
+                documents.len() - 1
+            }
+        };
+        let document = &mut documents[index];
+        document.size += metadata.len();
+        document.modified = document.modified.max(metadata.modified().ok());
+        document.files.push(file);
+    }
+
+    documents.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(documents)
+}
@@ -0,0 +1,32 @@
+//! Full-screen terminal setup and teardown shared by the TUI editor and `watch --render tui`.
+
+use crossterm::{
+    event::{DisableMouseCapture, EnableMouseCapture},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use ratatui::{backend::CrosstermBackend, Terminal};
+use std::io::{self, Stdout};
+
+pub type Tui = Terminal<CrosstermBackend<Stdout>>;
+
+/// Switch to raw mode on the alternate screen, capturing the mouse if asked
+pub fn setup_terminal(mouse: bool) -> io::Result<Tui> {
+    enable_raw_mode()?;
+    let mut stdout = io::stdout();
+    execute!(stdout, EnterAlternateScreen)?;
+    if mouse {
+        execute!(stdout, EnableMouseCapture)?;
+    }
+    Terminal::new(CrosstermBackend::new(stdout))
+}
+
+/// Undo [`setup_terminal`]; `mouse` must match what it was called with
+pub fn restore_terminal(terminal: &mut Tui, mouse: bool) -> io::Result<()> {
+    disable_raw_mode()?;
+    if mouse {
+        execute!(terminal.backend_mut(), DisableMouseCapture)?;
+    }
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()
+}
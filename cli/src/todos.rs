@@ -0,0 +1,166 @@
+//! Todo ids, markdown checklists and looking todos up by id.
+
+use crate::TodoItem;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Process-wide counter so ids minted in the same millisecond stay unique
+static ID_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// A fresh `<millis>-<counter>` id, as used for todos and attachments
+pub fn new_id() -> String {
+    let counter = ID_COUNTER.fetch_add(1, Ordering::SeqCst);
+    format!("{}-{}", chrono::Utc::now().timestamp_millis(), counter)
+}
+
+impl TodoItem {
+    /// A new todo with a freshly generated id
+    pub fn new(text: &str, completed: bool) -> Self {
+        Self {
+            id: autosurgeon::Text::with_value(new_id()),
+            text: autosurgeon::Text::with_value(text),
+            completed,
+            tags: Vec::new(),
+        }
+    }
+
+    /// The todo's tags as ` #tag` suffixes, or nothing when it has none
+    pub fn tag_suffix(&self) -> String {
+        self.tags.iter().map(|tag| format!(" #{}", tag)).collect()
+    }
+}
+
+/// Render todos as a markdown checklist that [`parse_checklist`] reads back,
+/// followed by a `completed/total` summary line.
+pub fn render_checklist(todos: &[TodoItem]) -> String {
+    let mut out = String::new();
+    for todo in todos {
+        let mark = if todo.completed { "x" } else { " " };
+        out.push_str(&format!("- [{}] {}\n", mark, todo.text.as_str()));
+    }
+    let completed = todos.iter().filter(|todo| todo.completed).count();
+    out.push_str(&format!("\n{}/{} completed\n", completed, todos.len()));
+    out
+}
+
+/// Parse markdown checklist lines (`- [ ] task`, `- [x] done`) into
+/// `(text, completed)` pairs, skipping anything that isn't a checklist item.
+pub fn parse_checklist(input: &str) -> Vec<(String, bool)> {
+    input
+        .lines()
+        .filter_map(|line| {
+            let rest = line.trim_start();
+            let rest = rest
+                .strip_prefix("- ")
+                .or_else(|| rest.strip_prefix("* "))
+                .or_else(|| rest.strip_prefix("+ "))?;
+            let (completed, text) = if let Some(text) = rest.strip_prefix("[ ]") {
+                (false, text)
+            } else if let Some(text) = rest
+                .strip_prefix("[x]")
+                .or_else(|| rest.strip_prefix("[X]"))
+            {
+                (true, text)
+            } else {
+                return None;
+            };
+            let text = text.trim();
+            (!text.is_empty()).then(|| (text.to_string(), completed))
+        })
+        .collect()
+}
+
+/// Index of the first todo whose id starts with `id_prefix`.
+///
+/// Indices into `todos` shift when items are inserted or removed concurrently,
+/// so call this on the state hydrated for the mutation that uses the index,
+/// never keep an index from an earlier read.
+pub fn find_todo(todos: &[TodoItem], id_prefix: &str) -> Option<usize> {
+    todos.iter().position(|todo| todo.id.as_str().starts_with(id_prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use automerge::{transaction::Transactable, AutoCommit, ObjType, ReadDoc};
+    use autosurgeon::{Hydrate, Reconcile};
+
+    #[derive(Debug, Reconcile, Hydrate)]
+    struct Todos {
+        todos: Vec<TodoItem>,
+    }
+
+    /// Insert a todo at the front of the list with raw ops, as another client would
+    fn insert_front(doc: &mut AutoCommit, text: &str) {
+        let (_, list) = doc.get(automerge::ROOT, "todos").unwrap().unwrap();
+        let item = doc.insert_object(&list, 0, ObjType::Map).unwrap();
+        for (key, value) in [("id", new_id()), ("text", text.to_string())] {
+            let obj = doc.put_object(&item, key, ObjType::Text).unwrap();
+            doc.splice_text(&obj, 0, 0, &value).unwrap();
+        }
+        doc.put(&item, "completed", false).unwrap();
+    }
+
+    #[test]
+    fn edit_lands_on_the_right_todo_after_a_concurrent_insert() {
+        let mut doc = AutoCommit::new();
+        let initial = Todos {
+            todos: vec![TodoItem::new("first", false), TodoItem::new("second", false)],
+        };
+        let second_id = initial.todos[1].id.as_str().to_string();
+        autosurgeon::reconcile(&mut doc, &initial).unwrap();
+
+        let mut peer = doc.fork();
+        insert_front(&mut peer, "inserted");
+
+        let mut state: Todos = autosurgeon::hydrate(&doc).unwrap();
+        let index = find_todo(&state.todos, &second_id).unwrap();
+        state.todos[index].completed = true;
+        autosurgeon::reconcile(&mut doc, &state).unwrap();
+
+        doc.merge(&mut peer).unwrap();
+        let merged: Todos = autosurgeon::hydrate(&doc).unwrap();
+        let texts: Vec<_> = merged.todos.iter().map(|t| (t.text.as_str(), t.completed)).collect();
+        assert_eq!(texts, vec![("inserted", false), ("first", false), ("second", true)]);
+    }
+
+    #[test]
+    fn parse_checklist_reads_mixed_completion_states() {
+        let input = "- [ ] write docs\n- [x] ship it\n* [X] tag release\nnot a todo\n- [ ]   \n";
+        assert_eq!(
+            parse_checklist(input),
+            vec![
+                ("write docs".to_string(), false),
+                ("ship it".to_string(), true),
+                ("tag release".to_string(), true),
+            ]
+        );
+    }
+
+    #[test]
+    fn render_checklist_round_trips_through_parse_checklist() {
+        let todos = vec![
+            TodoItem::new("open item", false),
+            TodoItem::new("done item", true),
+            TodoItem::new("another open one", false),
+        ];
+        let markdown = render_checklist(&todos);
+        assert_eq!(
+            markdown,
+            "- [ ] open item\n- [x] done item\n- [ ] another open one\n\n1/3 completed\n"
+        );
+        let parsed = parse_checklist(&markdown);
+        let original: Vec<_> = todos
+            .iter()
+            .map(|todo| (todo.text.as_str().to_string(), todo.completed))
+            .collect();
+        assert_eq!(parsed, original);
+    }
+
+    #[test]
+    fn find_todo_matches_an_id_prefix() {
+        let todos = vec![TodoItem::new("a", false), TodoItem::new("b", false)];
+        let id = todos[1].id.as_str();
+        assert_eq!(find_todo(&todos, id), Some(1));
+        assert_eq!(find_todo(&todos, "no-such-id"), None);
+    }
+}
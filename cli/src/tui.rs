@@ -1,29 +1,22 @@
 use anyhow::{Context, Result};
 
 use automerge_cli::*;
-use autosurgeon::{hydrate, reconcile};
+use autosurgeon::hydrate;
 use chrono::Utc;
 use clap::Parser;
-use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyModifiers},
-    execute,
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
-};
+use crossterm::event::{self, Event, KeyCode, KeyModifiers};
 use futures_util::StreamExt;
 use ratatui::{
-    backend::CrosstermBackend,
     layout::{Constraint, Direction, Layout},
     style::{Color, Modifier, Style},
     text::{Line, Span},
-    widgets::{Block, Borders, Paragraph},
-    Terminal,
+    widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph, Wrap},
 };
-use std::convert::Infallible;
-use std::io;
-use std::time::Duration;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 use tokio::time::sleep;
-use tokio_tungstenite::{connect_async, tungstenite::Message};
 use tui_textarea::{Input, TextArea};
 
 #[derive(Parser, Debug)]
@@ -36,65 +29,524 @@ struct Cli {
     /// Enable verbose logging
     #[arg(short, long)]
     verbose: bool,
+
+    /// Don't reconnect if the sync server closes the connection
+    #[arg(long)]
+    no_reconnect_on_eof: bool,
+
+    /// Token for sync servers that require authentication
+    /// (defaults to $AUTODASH_AUTH_TOKEN, then `auth_token` in the config file)
+    #[arg(long, value_name = "TOKEN")]
+    auth_token: Option<String>,
+
+    /// A friendly name for this client, sent to the sync server when connecting
+    #[arg(long, value_name = "NAME")]
+    peer_name: Option<String>,
+
+    /// Seconds between local version snapshots (F2 to browse, 0 to disable)
+    #[arg(long, value_name = "SECS", default_value_t = 60)]
+    snapshot_interval: u64,
+
+    /// Which text field to edit
+    #[arg(long, value_enum, default_value_t = EditField::Notes)]
+    field: EditField,
+
+    /// Carry indentation over to new lines when editing code
+    #[arg(long)]
+    auto_indent: bool,
+
+    /// Spaces added after an opening bracket when auto-indenting
+    #[arg(long, value_name = "N", default_value_t = 4)]
+    indent_width: usize,
+
+    /// Color theme [default: dark]. Overrides `theme` in the config file
+    #[arg(long, value_enum)]
+    theme: Option<ThemeName>,
+
+    /// Poll for document changes every MS milliseconds instead of waiting for
+    /// change events (0 = events). Polling costs a little CPU per tick but works
+    /// where change events aren't delivered
+    #[arg(long, value_name = "MS", default_value_t = 0)]
+    poll_changes: u64,
+
+    /// Don't capture the mouse. Your terminal's own text selection and copy then
+    /// work in the editor, but the mouse wheel no longer scrolls it
+    #[arg(long)]
+    no_mouse: bool,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ThemeName {
+    Dark,
+    Light,
+    HighContrast,
+}
+
+/// Every style the TUI draws with, picked once at startup
+#[derive(Debug, Clone, Copy)]
+struct Theme {
+    editor_border: Style,
+    cursor: Style,
+    status_label: Style,
+    status_text: Style,
+    collaborators: Style,
+    status_border: Style,
+    popup_border: Style,
+    timestamp: Style,
+    heads: Style,
+    selection: Style,
+    // Text drawn on top of a collaborator's cursor color
+    remote_cursor_fg: Color,
+}
+
+impl Theme {
+    fn new(name: ThemeName) -> Self {
+        let reversed = Style::default().add_modifier(Modifier::REVERSED);
+        match name {
+            ThemeName::Dark => Self {
+                editor_border: Style::default(),
+                cursor: reversed,
+                status_label: Style::default().fg(Color::Gray).add_modifier(Modifier::BOLD),
+                status_text: Style::default().fg(Color::Green),
+                collaborators: Style::default().fg(Color::Magenta),
+                status_border: Style::default().fg(Color::DarkGray),
+                popup_border: Style::default().fg(Color::Yellow),
+                timestamp: Style::default().fg(Color::Green),
+                heads: Style::default().fg(Color::DarkGray),
+                selection: reversed,
+                remote_cursor_fg: Color::Black,
+            },
+            ThemeName::Light => Self {
+                editor_border: Style::default().fg(Color::Gray),
+                cursor: reversed,
+                status_label: Style::default().fg(Color::DarkGray).add_modifier(Modifier::BOLD),
+                status_text: Style::default().fg(Color::Blue),
+                collaborators: Style::default().fg(Color::Magenta),
+                status_border: Style::default().fg(Color::Gray),
+                popup_border: Style::default().fg(Color::Blue),
+                timestamp: Style::default().fg(Color::Blue),
+                heads: Style::default().fg(Color::Gray),
+                selection: reversed,
+                remote_cursor_fg: Color::Black,
+            },
+            ThemeName::HighContrast => Self {
+                editor_border: Style::default().fg(Color::White).add_modifier(Modifier::BOLD),
+                cursor: Style::default().fg(Color::Black).bg(Color::Yellow),
+                status_label: Style::default().fg(Color::White).add_modifier(Modifier::BOLD),
+                status_text: Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD),
+                collaborators: Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD),
+                status_border: Style::default().fg(Color::White),
+                popup_border: Style::default().fg(Color::White).add_modifier(Modifier::BOLD),
+                timestamp: Style::default().fg(Color::Yellow),
+                heads: Style::default().fg(Color::White),
+                selection: Style::default().fg(Color::Black).bg(Color::Yellow),
+                remote_cursor_fg: Color::Black,
+            },
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum EditField {
+    Notes,
+    Code,
 }
 
+impl EditField {
+    fn title(self) -> &'static str {
+        match self {
+            EditField::Notes => "Notes",
+            EditField::Code => "Code",
+        }
+    }
+
+    fn text(self, state: &Doc) -> String {
+        match self {
+            EditField::Notes => state.notes.as_str().to_string(),
+            EditField::Code => state.code.as_str().to_string(),
+        }
+    }
+
+    fn text_field(self) -> TextField {
+        match self {
+            EditField::Notes => TextField::Notes,
+            EditField::Code => TextField::Code,
+        }
+    }
+}
+
+// Cursor presence is broadcast as ephemeral messages: `autodash-cursor:<session>:<offset>`
+const CURSOR_PREFIX: &str = "autodash-cursor:";
+const REMOTE_CURSOR_TTL: Duration = Duration::from_secs(10);
+const REMOTE_CURSOR_COLORS: [Color; 5] = [
+    Color::Magenta,
+    Color::Cyan,
+    Color::Yellow,
+    Color::Blue,
+    Color::LightRed,
+];
+
 enum AppEvent {
     Input(Event),
     Tick,
+    RemoteChange,
+    Presence(Vec<u8>),
+    Connection(ConnEvent),
+    Snapshot,
+}
+
+struct RemoteCursor {
+    offset: usize,
+    color: Color,
+    last_seen: Instant,
+}
+
+/// Document heads recorded during this session; kept locally, never written to the doc
+struct Snapshot {
+    taken_at: chrono::DateTime<chrono::Local>,
+    heads: Vec<automerge::ChangeHash>,
+}
+
+// How often the edited text is written to the local backup file
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Where the plain-text backup of a document's field lives:
+/// `~/.local/state/autodash/backups/<doc id>-<field>.txt`
+fn backup_path(doc_id: &str, field: EditField) -> std::path::PathBuf {
+    let dir = match std::env::var_os("HOME") {
+        Some(home) => std::path::PathBuf::from(home).join(".local/state/autodash/backups"),
+        None => std::env::temp_dir().join("autodash-backups"),
+    };
+    dir.join(format!("{}-{}.txt", doc_id, field.title().to_lowercase()))
+}
+
+/// The backup's text, if it was written after the document last changed and differs from it
+fn newer_backup(path: &std::path::Path, state: &Doc, text: &str) -> Option<String> {
+    let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+    let modified_ms = modified
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?
+        .as_millis() as i64;
+    if modified_ms <= state.metadata.lastModified.unwrap_or(0) {
+        return None;
+    }
+    let backup = std::fs::read_to_string(path).ok()?;
+    (backup != text).then_some(backup)
+}
+
+struct VersionsPopup {
+    list_state: ListState,
+    // Read-only text at the selected snapshot, once one has been opened
+    preview: Option<String>,
 }
 
 struct App<'a> {
     textarea: TextArea<'a>,
     doc_handle: samod::DocHandle,
+    // Splices edits straight into the field's Text, caching its object id
+    autodash: Autodash,
+    field: EditField,
+    // Indent width for auto-indentation, or None when disabled
+    auto_indent: Option<usize>,
     status_message: String,
     last_known_text: String,
     should_quit: bool,
+    session_id: String,
+    remote_cursors: HashMap<String, RemoteCursor>,
+    last_broadcast_offset: Option<usize>,
+    // First visible row of the editor, mirroring the textarea's own scrolling
+    scroll_top: Cell<usize>,
+    snapshots: Vec<Snapshot>,
+    versions: Option<VersionsPopup>,
+    theme: Theme,
+    backup_path: std::path::PathBuf,
+    // Text as of the last backup write, to skip rewriting an unchanged file
+    backed_up_text: Option<String>,
+    last_backup: Instant,
+    // A newer local backup found at startup, waiting for the user to recover or discard it
+    recovery: Option<String>,
+}
+
+/// A styled editor for the given text
+fn new_textarea<'a>(text: &str, field: EditField, theme: &Theme) -> TextArea<'a> {
+    let lines: Vec<String> = if text.is_empty() {
+        vec![String::new()]
+    } else {
+        text.lines().map(|s| s.to_string()).collect()
+    };
+
+    let mut textarea = TextArea::new(lines);
+    textarea.set_block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(format!(
+                "{} (Ctrl+Q to quit, Ctrl+S to sync status, F2 versions)",
+                field.title()
+            ))
+            .border_style(theme.editor_border),
+    );
+    textarea.set_cursor_line_style(Style::default());
+    textarea.set_cursor_style(theme.cursor);
+    textarea
+}
+
+/// Indentation for the line following `line` when Enter is pressed at `col`:
+/// the line's leading whitespace, plus one level after an opening bracket.
+fn next_line_indent(line: &str, col: usize, indent_width: usize) -> String {
+    let mut indent: String = line.chars().take_while(|c| c.is_whitespace()).collect();
+    let before_cursor: String = line.chars().take(col).collect();
+    if before_cursor.trim_end().ends_with(['{', '(', '[']) {
+        indent.push_str(&" ".repeat(indent_width));
+    }
+    indent
 }
 
 impl<'a> App<'a> {
-    fn new(doc_handle: samod::DocHandle) -> Result<Self> {
+    fn new(
+        doc_handle: samod::DocHandle,
+        field: EditField,
+        auto_indent: Option<usize>,
+        theme: Theme,
+        backup_path: std::path::PathBuf,
+    ) -> Result<Self> {
         // Load initial text from document
-        let initial_text = doc_handle.with_document(|doc| -> Result<String> {
+        let (initial_text, recovery) = doc_handle.with_document(|doc| -> Result<_> {
             let state: Doc = hydrate(doc)?;
-            Ok(state.notes.as_str().to_string())
+            let text = field.text(&state);
+            let recovery = newer_backup(&backup_path, &state, &text);
+            Ok((text, recovery))
         })?;
 
-        let lines: Vec<String> = if initial_text.is_empty() {
-            vec![String::new()]
+        let textarea = new_textarea(&initial_text, field, &theme);
+        let status_message = if recovery.is_some() {
+            "Found a local backup newer than the document".to_string()
         } else {
-            initial_text.lines().map(|s| s.to_string()).collect()
+            "Connected. Every keystroke syncs!".to_string()
         };
 
-        let mut textarea = TextArea::new(lines);
-        textarea.set_block(
-            Block::default()
-                .borders(Borders::ALL)
-                .title("Notes (Ctrl+Q to quit, Ctrl+S to sync status)"),
-        );
-        textarea.set_cursor_line_style(Style::default());
-        textarea
-            .set_cursor_style(Style::default().add_modifier(Modifier::REVERSED));
-
         Ok(Self {
             textarea,
+            autodash: Autodash::new(doc_handle.clone()),
             doc_handle,
-            status_message: "Connected. Every keystroke syncs!".to_string(),
+            field,
+            auto_indent,
+            status_message,
             last_known_text: initial_text,
             should_quit: false,
+            session_id: format!(
+                "{}-{}",
+                std::process::id(),
+                Utc::now().timestamp_millis()
+            ),
+            remote_cursors: HashMap::new(),
+            last_broadcast_offset: None,
+            scroll_top: Cell::new(0),
+            snapshots: Vec::new(),
+            versions: None,
+            theme,
+            backup_path,
+            backed_up_text: None,
+            last_backup: Instant::now(),
+            recovery,
+        })
+    }
+
+    /// Write the edited text to the local backup file, unless it hasn't changed
+    fn save_backup(&mut self) -> Result<()> {
+        // Don't overwrite a backup the user hasn't decided about yet
+        if self.recovery.is_some() {
+            return Ok(());
+        }
+        self.last_backup = Instant::now();
+        let text = self.textarea.lines().join("\n");
+        if self.backed_up_text.as_deref() == Some(text.as_str()) {
+            return Ok(());
+        }
+        if let Some(dir) = self.backup_path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        std::fs::write(&self.backup_path, &text)
+            .with_context(|| format!("Failed to write backup {}", self.backup_path.display()))?;
+        self.backed_up_text = Some(text);
+        Ok(())
+    }
+
+    fn handle_recovery_key(&mut self, code: KeyCode) -> Result<()> {
+        match code {
+            KeyCode::Char('y') | KeyCode::Enter => {
+                let Some(backup) = self.recovery.take() else {
+                    return Ok(());
+                };
+                let old_text = self.textarea.lines().join("\n");
+                self.textarea = new_textarea(&backup, self.field, &self.theme);
+                self.apply_text_diff(&old_text, &backup)?;
+                self.last_known_text = backup;
+                self.status_message = "Recovered local backup".to_string();
+            }
+            KeyCode::Char('n') | KeyCode::Esc => {
+                self.recovery = None;
+                self.status_message = "Discarded local backup".to_string();
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Record the current heads, skipping snapshots where nothing changed
+    fn record_snapshot(&mut self) {
+        let heads = self.doc_handle.with_document(|doc| doc.get_heads());
+        if self.snapshots.last().is_some_and(|last| last.heads == heads) {
+            return;
+        }
+        self.snapshots.push(Snapshot {
+            taken_at: chrono::Local::now(),
+            heads,
+        });
+    }
+
+    fn open_versions(&mut self) {
+        if self.snapshots.is_empty() {
+            self.status_message = "No snapshots recorded yet".to_string();
+            return;
+        }
+        // Newest first, matching the list order
+        let mut list_state = ListState::default();
+        list_state.select(Some(0));
+        self.versions = Some(VersionsPopup {
+            list_state,
+            preview: None,
+        });
+    }
+
+    /// The edited field as it was at the given snapshot, read from a fork at its heads
+    fn text_at(&self, snapshot: &Snapshot) -> Result<String> {
+        self.doc_handle.with_document(|doc| -> Result<String> {
+            let forked = doc
+                .fork_at(&snapshot.heads)
+                .map_err(|e| anyhow::anyhow!("Failed to read snapshot: {:?}", e))?;
+            let state: Doc = hydrate(&forked)?;
+            Ok(self.field.text(&state))
         })
     }
 
+    fn handle_versions_key(&mut self, code: KeyCode) -> Result<()> {
+        let Some(popup) = self.versions.as_mut() else {
+            return Ok(());
+        };
+
+        // Esc backs out of a preview first, then closes the popup
+        if popup.preview.is_some() {
+            if matches!(code, KeyCode::Esc | KeyCode::Enter | KeyCode::F(2)) {
+                popup.preview = None;
+            }
+            return Ok(());
+        }
+
+        let selected = popup.list_state.selected().unwrap_or(0);
+        match code {
+            KeyCode::Esc | KeyCode::F(2) => self.versions = None,
+            KeyCode::Up => popup.list_state.select(Some(selected.saturating_sub(1))),
+            KeyCode::Down => {
+                let last = self.snapshots.len().saturating_sub(1);
+                popup.list_state.select(Some((selected + 1).min(last)));
+            }
+            KeyCode::Enter => {
+                let index = self.snapshots.len() - 1 - selected;
+                let text = self.text_at(&self.snapshots[index])?;
+                if let Some(popup) = self.versions.as_mut() {
+                    popup.preview = Some(text);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Character offset of the local cursor within the whole edited text
+    fn cursor_offset(&self) -> usize {
+        let (row, col) = self.textarea.cursor();
+        let preceding: usize = self
+            .textarea
+            .lines()
+            .iter()
+            .take(row)
+            .map(|line| line.chars().count() + 1)
+            .sum();
+        preceding + col
+    }
+
+    fn broadcast_cursor(&mut self, force: bool) {
+        let offset = self.cursor_offset();
+        if force || self.last_broadcast_offset != Some(offset) {
+            let message = format!("{}{}:{}", CURSOR_PREFIX, self.session_id, offset);
+            self.doc_handle.broadcast(message.into_bytes());
+            self.last_broadcast_offset = Some(offset);
+        }
+    }
+
+    fn handle_presence(&mut self, message: &[u8]) {
+        // Ignore anything that isn't one of our cursor messages (e.g. browser presence)
+        let Ok(text) = std::str::from_utf8(message) else {
+            return;
+        };
+        let Some((session, offset)) = text
+            .strip_prefix(CURSOR_PREFIX)
+            .and_then(|rest| rest.rsplit_once(':'))
+        else {
+            return;
+        };
+        let Ok(offset) = offset.parse::<usize>() else {
+            return;
+        };
+        if session == self.session_id {
+            return;
+        }
+
+        let hash = session
+            .bytes()
+            .fold(0usize, |acc, b| acc.wrapping_mul(31).wrapping_add(b as usize));
+        let color = REMOTE_CURSOR_COLORS[hash % REMOTE_CURSOR_COLORS.len()];
+        self.remote_cursors.insert(
+            session.to_string(),
+            RemoteCursor {
+                offset,
+                color,
+                last_seen: Instant::now(),
+            },
+        );
+    }
+
+    fn prune_remote_cursors(&mut self) {
+        self.remote_cursors
+            .retain(|_, cursor| cursor.last_seen.elapsed() < REMOTE_CURSOR_TTL);
+    }
+
     fn handle_input(&mut self, event: Event) -> Result<()> {
         match event {
             Event::Key(key) => {
+                // Popups capture all keys while they're open
+                if self.recovery.is_some() {
+                    return self.handle_recovery_key(key.code);
+                }
+                if self.versions.is_some() {
+                    return self.handle_versions_key(key.code);
+                }
+                if key.code == KeyCode::F(2) {
+                    self.open_versions();
+                    return Ok(());
+                }
+
                 if key.modifiers.contains(KeyModifiers::CONTROL) {
                     match key.code {
                         KeyCode::Char('q') => {
+                            self.save_backup()?;
                             self.should_quit = true;
                             return Ok(());
                         }
                         KeyCode::Char('s') => {
-                            self.status_message = format!("Synced at {}", chrono::Local::now().format("%H:%M:%S"));
+                            self.save_backup()?;
+                            self.status_message = format!("Saved at {}", chrono::Local::now().format("%H:%M:%S"));
                             return Ok(());
                         }
                         _ => {}
@@ -104,8 +556,25 @@ impl<'a> App<'a> {
                 // Capture text before the edit
                 let old_text = self.textarea.lines().join("\n");
 
+                // Work out the new line's indent before the textarea splits the line
+                let indent = match self.auto_indent {
+                    Some(width)
+                        if self.field == EditField::Code
+                            && key.code == KeyCode::Enter
+                            && key.modifiers.is_empty() =>
+                    {
+                        let (row, col) = self.textarea.cursor();
+                        let line = &self.textarea.lines()[row];
+                        Some(next_line_indent(line, col, width))
+                    }
+                    _ => None,
+                };
+
                 // Apply the input to the textarea
                 self.textarea.input(Input::from(event));
+                if let Some(indent) = indent.filter(|indent| !indent.is_empty()) {
+                    self.textarea.insert_str(indent);
+                }
 
                 // Get new text after the edit
                 let new_text = self.textarea.lines().join("\n");
@@ -115,6 +584,13 @@ impl<'a> App<'a> {
                     self.apply_text_diff(&old_text, &new_text)?;
                     self.last_known_text = new_text;
                 }
+
+                self.broadcast_cursor(false);
+            }
+            // Only delivered with mouse capture on; the wheel scrolls the editor
+            Event::Mouse(_) if self.versions.is_none() && self.recovery.is_none() => {
+                self.textarea.input(Input::from(event));
+                self.broadcast_cursor(false);
             }
             _ => {}
         }
@@ -122,55 +598,25 @@ impl<'a> App<'a> {
     }
 
     fn apply_text_diff(&mut self, old_text: &str, new_text: &str) -> Result<()> {
-        // Simple diff algorithm: find common prefix and suffix
-        let old_chars: Vec<char> = old_text.chars().collect();
-        let new_chars: Vec<char> = new_text.chars().collect();
-
-        // Find common prefix length
-        let mut prefix_len = 0;
-        while prefix_len < old_chars.len()
-            && prefix_len < new_chars.len()
-            && old_chars[prefix_len] == new_chars[prefix_len]
-        {
-            prefix_len += 1;
-        }
-
-        // Find common suffix length (but don't overlap with prefix)
-        let mut suffix_len = 0;
-        while suffix_len < (old_chars.len() - prefix_len)
-            && suffix_len < (new_chars.len() - prefix_len)
-            && old_chars[old_chars.len() - 1 - suffix_len] == new_chars[new_chars.len() - 1 - suffix_len]
-        {
-            suffix_len += 1;
-        }
-
-        // Calculate the deletion and insertion
-        let delete_start = prefix_len;
-        let delete_count = old_chars.len() - prefix_len - suffix_len;
-        let insert_text: String = new_chars[prefix_len..new_chars.len() - suffix_len].iter().collect();
+        // Only the span between the common prefix and suffix changed
+        let plan = splice_plan(old_text, new_text);
 
         // Apply to Automerge document
-        self.doc_handle.with_document(|doc| -> Result<()> {
-            let mut state: Doc = hydrate(doc)?;
-
-            // Use autosurgeon::Text splice API
-            // First delete, then insert at the same position
-            state.notes.splice(delete_start, delete_count as isize, &insert_text);
-
-            state.metadata.lastModified = Some(Utc::now().timestamp_millis());
-
-            doc.transact(|tx| reconcile(tx, &state))
-                .map_err(|e| anyhow::anyhow!("Failed to reconcile: {:?}", e))?;
-            Ok(())
-        })?;
+        self.autodash.splice_text(
+            self.field.text_field(),
+            plan.start,
+            plan.delete as isize,
+            &plan.insert,
+        )?;
 
         Ok(())
     }
 
     fn apply_remote_changes(&mut self) -> Result<()> {
+        let field = self.field;
         let remote_text = self.doc_handle.with_document(|doc| -> Result<String> {
             let state: Doc = hydrate(doc)?;
-            Ok(state.notes.as_str().to_string())
+            Ok(field.text(&state))
         })?;
 
         if remote_text != self.last_known_text {
@@ -178,21 +624,8 @@ impl<'a> App<'a> {
             let cursor = self.textarea.cursor();
 
             // Update textarea content
-            let lines: Vec<String> = if remote_text.is_empty() {
-                vec![String::new()]
-            } else {
-                remote_text.lines().map(|s| s.to_string()).collect()
-            };
-
-            self.textarea = TextArea::new(lines);
-            self.textarea.set_block(
-                Block::default()
-                    .borders(Borders::ALL)
-                    .title("Notes (Ctrl+Q to quit, Ctrl+S to sync status)"),
-            );
-            self.textarea.set_cursor_line_style(Style::default());
-            self.textarea
-                .set_cursor_style(Style::default().add_modifier(Modifier::REVERSED));
+            self.textarea = new_textarea(&remote_text, self.field, &self.theme);
+            self.scroll_top.set(0);
 
             // Try to restore cursor position (clamped to valid range)
             let new_line_count = self.textarea.lines().len();
@@ -221,6 +654,133 @@ impl<'a> App<'a> {
     }
 }
 
+/// Map a character offset in the text to a (row, column), clamping offsets past the end.
+fn offset_to_position(lines: &[String], offset: usize) -> (usize, usize) {
+    let mut remaining = offset;
+    for (row, line) in lines.iter().enumerate() {
+        let len = line.chars().count();
+        if remaining <= len {
+            return (row, remaining);
+        }
+        remaining -= len + 1;
+    }
+    let last = lines.len().saturating_sub(1);
+    (last, lines.get(last).map_or(0, |line| line.chars().count()))
+}
+
+fn render_remote_cursors(f: &mut ratatui::Frame, app: &App, area: ratatui::layout::Rect) {
+    // Inside the editor's borders
+    let inner_x = area.x + 1;
+    let inner_y = area.y + 1;
+    let width = area.width.saturating_sub(2) as usize;
+    let height = area.height.saturating_sub(2) as usize;
+    if width == 0 || height == 0 {
+        return;
+    }
+
+    // Track the textarea's vertical scroll: it only scrolls enough to keep the cursor visible
+    let (cursor_row, _) = app.textarea.cursor();
+    let mut top = app.scroll_top.get();
+    if cursor_row < top {
+        top = cursor_row;
+    } else if cursor_row >= top + height {
+        top = cursor_row + 1 - height;
+    }
+    app.scroll_top.set(top);
+
+    let lines = app.textarea.lines();
+    for cursor in app.remote_cursors.values() {
+        let (row, col) = offset_to_position(lines, cursor.offset);
+        if row < top || row >= top + height || col >= width {
+            continue;
+        }
+        let position = (inner_x + col as u16, inner_y + (row - top) as u16);
+        if let Some(cell) = f.buffer_mut().cell_mut(position) {
+            cell.set_style(Style::default().fg(app.theme.remote_cursor_fg).bg(cursor.color));
+        }
+    }
+}
+
+/// A rectangle of the given percentage size, centered within `area`
+fn centered_rect(percent_x: u16, percent_y: u16, area: ratatui::layout::Rect) -> ratatui::layout::Rect {
+    let width = area.width * percent_x / 100;
+    let height = area.height * percent_y / 100;
+    ratatui::layout::Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    }
+}
+
+fn render_versions(f: &mut ratatui::Frame, app: &App, popup: &VersionsPopup) {
+    let area = centered_rect(70, 70, f.area());
+    f.render_widget(Clear, area);
+
+    if let Some(preview) = &popup.preview {
+        let selected = popup.list_state.selected().unwrap_or(0);
+        let snapshot = &app.snapshots[app.snapshots.len() - 1 - selected];
+        let paragraph = Paragraph::new(preview.as_str())
+            .wrap(Wrap { trim: false })
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(format!(
+                        "{} at {} (read-only, Esc to go back)",
+                        app.field.title(),
+                        snapshot.taken_at.format("%H:%M:%S")
+                    ))
+                    .border_style(app.theme.popup_border),
+            );
+        f.render_widget(paragraph, area);
+        return;
+    }
+
+    let items: Vec<ListItem> = app
+        .snapshots
+        .iter()
+        .rev()
+        .map(|snapshot| {
+            ListItem::new(Line::from(vec![
+                Span::styled(
+                    snapshot.taken_at.format("%H:%M:%S").to_string(),
+                    app.theme.timestamp,
+                ),
+                Span::raw("  "),
+                Span::styled(format_heads(&snapshot.heads), app.theme.heads),
+            ]))
+        })
+        .collect();
+
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Versions (Enter to preview, Esc to close)")
+                .border_style(app.theme.popup_border),
+        )
+        .highlight_style(app.theme.selection);
+
+    // The list state is only mutated for rendering offsets, so draw from a copy
+    let mut list_state = popup.list_state.clone();
+    f.render_stateful_widget(list, area, &mut list_state);
+}
+
+fn render_recovery(f: &mut ratatui::Frame, app: &App, backup: &str) {
+    let area = centered_rect(70, 70, f.area());
+    f.render_widget(Clear, area);
+    let paragraph = Paragraph::new(backup).wrap(Wrap { trim: false }).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(format!(
+                "Recover unsynced {} from local backup? (y: apply, n: discard)",
+                app.field.title().to_lowercase()
+            ))
+            .border_style(app.theme.popup_border),
+    );
+    f.render_widget(paragraph, area);
+}
+
 fn ui(f: &mut ratatui::Frame, app: &App) {
     let chunks = Layout::default()
         .direction(Direction::Vertical)
@@ -230,31 +790,53 @@ fn ui(f: &mut ratatui::Frame, app: &App) {
     // Render textarea
     #[allow(deprecated)]
     f.render_widget(app.textarea.widget(), chunks[0]);
+    render_remote_cursors(f, app, chunks[0]);
 
     // Render status bar
     let status_text = vec![Line::from(vec![
+        Span::styled("Status: ", app.theme.status_label),
+        Span::styled(&app.status_message, app.theme.status_text),
         Span::styled(
-            "Status: ",
-            Style::default()
-                .fg(Color::Gray)
-                .add_modifier(Modifier::BOLD),
+            match app.remote_cursors.len() {
+                0 => String::new(),
+                1 => "  (1 other editor)".to_string(),
+                n => format!("  ({} other editors)", n),
+            },
+            app.theme.collaborators,
         ),
-        Span::styled(&app.status_message, Style::default().fg(Color::Green)),
     ])];
 
     let status = Paragraph::new(status_text).block(
         Block::default()
             .borders(Borders::ALL)
             .title("Status")
-            .border_style(Style::default().fg(Color::DarkGray)),
+            .border_style(app.theme.status_border),
     );
     f.render_widget(status, chunks[1]);
+
+    if let Some(popup) = &app.versions {
+        render_versions(f, app, popup);
+    }
+    if let Some(backup) = &app.recovery {
+        render_recovery(f, app, backup);
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
 
+    // Pick the theme up front so a bad config value fails before the terminal is taken over
+    let config = Config::load()?;
+    let theme_name = match cli.theme {
+        Some(name) => name,
+        None => match &config.theme {
+            Some(name) => <ThemeName as clap::ValueEnum>::from_str(name, true)
+                .map_err(|_| anyhow::anyhow!("Unknown theme '{}' in config file", name))?,
+            None => ThemeName::Dark,
+        },
+    };
+
     // Set up tracing
     let filter = if cli.verbose {
         "samod=debug,automerge_cli=debug"
@@ -283,75 +865,22 @@ async fn main() -> Result<()> {
     // Create a repo with filesystem storage
     let repo = samod::Repo::build_tokio()
         .with_storage(samod::storage::TokioFilesystemStorage::new(
-            "./autodash-data/",
+            DEFAULT_STORAGE_DIR,
         ))
         .load()
         .await;
 
-    tracing::debug!("Connecting to sync server");
-
-    // Connect to WebSocket server
-    let (ws_stream, _) = connect_async("ws://localhost:3030")
-        .await
-        .context("Failed to connect to WebSocket server")?;
-
-    tracing::debug!("WebSocket connected");
-
-    let (ws_sink, ws_stream) = ws_stream.split();
-
-    // Create channels to bridge WebSocket and samod
-    let (to_samod_tx, to_samod_rx) = futures::channel::mpsc::unbounded::<Vec<u8>>();
-    let (from_samod_tx, from_samod_rx) = futures::channel::mpsc::unbounded::<Vec<u8>>();
-
-    // Forward WebSocket messages to samod
-    let ws_to_samod = async move {
-        let mut stream = ws_stream;
-        while let Some(msg) = stream.next().await {
-            match msg {
-                Ok(Message::Binary(data)) => {
-                    if to_samod_tx.unbounded_send(data).is_err() {
-                        break;
-                    }
-                }
-                Ok(Message::Close(_)) => break,
-                Ok(_) => {} // Ignore text/ping/pong
-                Err(e) => {
-                    tracing::warn!("WebSocket error: {}", e);
-                    break;
-                }
-            }
-        }
-    };
-
-    // Forward samod messages to WebSocket
-    let samod_to_ws = async move {
-        use futures_util::SinkExt;
-        let mut rx = from_samod_rx;
-        let mut sink = ws_sink;
-        while let Some(bytes) = rx.next().await {
-            if sink.send(Message::Binary(bytes)).await.is_err() {
-                break;
-            }
-        }
-    };
-
-    // Spawn the connection handling tasks
-    let _ws_to_samod_handle = tokio::spawn(ws_to_samod);
-    let _samod_to_ws_handle = tokio::spawn(samod_to_ws);
-
-    // Connect the repo to the sync server
-    let repo_clone = repo.clone();
-    tokio::spawn(async move {
-        let result = repo_clone
-            .connect(
-                to_samod_rx.map(Ok::<_, Infallible>),
-                from_samod_tx,
-                samod::ConnDirection::Outgoing,
-            )
-            .await;
-
-        tracing::debug!("Sync connection finished: {:?}", result);
-    });
+    // Connect to the sync server (reconnecting in the background unless disabled)
+    let connection = connect_repo(
+        &repo,
+        ConnectOptions {
+            reconnect: !cli.no_reconnect_on_eof,
+            auth_token: config.resolve_auth_token(cli.auth_token.as_deref()),
+            peer_name: cli.peer_name.clone(),
+            ..Default::default()
+        },
+    )
+    .await?;
 
     tracing::debug!("Loading document: automerge:{}", doc_id_str);
 
@@ -377,14 +906,17 @@ async fn main() -> Result<()> {
     )?;
 
     // Setup terminal
-    enable_raw_mode()?;
-    let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend)?;
+    let mut terminal = setup_terminal(!cli.no_mouse)?;
 
     // Create app
-    let mut app = App::new(doc_handle.clone())?;
+    let auto_indent = cli.auto_indent.then_some(cli.indent_width);
+    let mut app = App::new(
+        doc_handle.clone(),
+        cli.field,
+        auto_indent,
+        Theme::new(theme_name),
+        backup_path(doc_id_str, cli.field),
+    )?;
 
     // Create event channel
     let (tx, mut rx) = mpsc::channel(100);
@@ -403,7 +935,42 @@ async fn main() -> Result<()> {
         }
     });
 
-    // Spawn periodic tick for checking remote changes
+    // Forward collaborators' ephemeral cursor broadcasts
+    let presence_tx = tx.clone();
+    let mut ephemera = Box::pin(doc_handle.ephemera());
+    tokio::spawn(async move {
+        while let Some(message) = ephemera.next().await {
+            if presence_tx.send(AppEvent::Presence(message)).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    // Surface connection lifecycle changes in the status bar
+    let conn_tx = tx.clone();
+    let mut conn_events = connection.subscribe();
+    tokio::spawn(async move {
+        while let Ok(event) = conn_events.recv().await {
+            if conn_tx.send(AppEvent::Connection(event)).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    // Pick up remote edits as the document changes
+    let change_tx = tx.clone();
+    let change_handle = doc_handle.clone();
+    let poll_interval = Duration::from_millis(cli.poll_changes);
+    tokio::spawn(async move {
+        let mut changes = Box::pin(change_notifications(&change_handle, poll_interval));
+        while changes.next().await.is_some() {
+            if change_tx.send(AppEvent::RemoteChange).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    // Spawn periodic tick for housekeeping (backups, cursor presence)
     let tick_tx = tx.clone();
     tokio::spawn(async move {
         let mut interval = tokio::time::interval(Duration::from_millis(500));
@@ -415,6 +982,21 @@ async fn main() -> Result<()> {
         }
     });
 
+    // Periodically record heads for the local versions list
+    if cli.snapshot_interval > 0 {
+        let snapshot_tx = tx.clone();
+        let period = Duration::from_secs(cli.snapshot_interval);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                if snapshot_tx.send(AppEvent::Snapshot).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
     // Main event loop
     loop {
         terminal.draw(|f| ui(f, &app))?;
@@ -425,10 +1007,32 @@ async fn main() -> Result<()> {
                 AppEvent::Input(evt) => {
                     app.handle_input(evt)?;
                 }
-                AppEvent::Tick => {
-                    // Check for remote changes
+                AppEvent::RemoteChange => {
                     app.apply_remote_changes()?;
                 }
+                AppEvent::Tick => {
+                    if app.last_backup.elapsed() >= AUTOSAVE_INTERVAL {
+                        app.save_backup()?;
+                    }
+                    // Re-announce our cursor so newly joined editors see it
+                    app.prune_remote_cursors();
+                    app.broadcast_cursor(true);
+                }
+                AppEvent::Presence(message) => {
+                    app.handle_presence(&message);
+                }
+                AppEvent::Connection(event) => {
+                    let now = chrono::Local::now().format("%H:%M:%S");
+                    app.status_message = match event {
+                        ConnEvent::Connected => format!("Reconnected at {}", now),
+                        ConnEvent::Disconnected => format!("Disconnected at {} (editing offline)", now),
+                        ConnEvent::Reconnecting => "Reconnecting to sync server...".to_string(),
+                        ConnEvent::ServerError(message) => format!("Server error: {}", message),
+                    };
+                }
+                AppEvent::Snapshot => {
+                    app.record_snapshot();
+                }
             },
             Ok(None) => break, // Channel closed
             Err(_) => {}       // Timeout, continue
@@ -440,13 +1044,68 @@ async fn main() -> Result<()> {
     }
 
     // Restore terminal
-    disable_raw_mode()?;
-    execute!(
-        terminal.backend_mut(),
-        LeaveAlternateScreen,
-        DisableMouseCapture
-    )?;
-    terminal.show_cursor()?;
+    restore_terminal(&mut terminal, !cli.no_mouse)?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An in-memory document whose notes were last modified at `last_modified`
+    async fn handle_with_notes(notes: &str, last_modified: i64) -> (samod::Repo, samod::DocHandle) {
+        let repo = samod::Repo::build_tokio().load().await;
+        let handle = repo.create(automerge::Automerge::new()).await.unwrap();
+        with_hydrated_unstamped(&handle, |state| {
+            state.notes = autosurgeon::Text::with_value(notes);
+            state.metadata.lastModified = Some(last_modified);
+        })
+        .unwrap();
+        (repo, handle)
+    }
+
+    fn backup_file(text: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("autodash-tui-{}-notes.txt", new_id()));
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn enter_keeps_the_current_indent() {
+        assert_eq!(next_line_indent("    let x = 1;", 14, 4), "    ");
+        assert_eq!(next_line_indent("\tfoo();", 7, 4), "\t");
+        assert_eq!(next_line_indent("plain", 5, 4), "");
+    }
+
+    #[test]
+    fn enter_after_an_opening_bracket_indents_one_level() {
+        assert_eq!(next_line_indent("  fn main() {", 13, 4), "      ");
+        assert_eq!(next_line_indent("call(", 5, 2), "  ");
+        // Only the text before the cursor counts
+        assert_eq!(next_line_indent("  {}", 2, 4), "  ");
+    }
+
+    #[tokio::test]
+    async fn a_newer_backup_is_offered_and_applied_on_recovery() {
+        let (_repo, handle) = handle_with_notes("synced text", 1_000).await;
+        let path = backup_file("synced text\nunsynced line");
+
+        let mut app = App::new(handle.clone(), EditField::Notes, None, Theme::new(ThemeName::Dark), path).unwrap();
+        assert_eq!(app.recovery.as_deref(), Some("synced text\nunsynced line"));
+
+        app.handle_recovery_key(KeyCode::Char('y')).unwrap();
+        assert!(app.recovery.is_none());
+        let state: Doc = handle.with_document(|doc| hydrate(&*doc).unwrap());
+        assert_eq!(state.notes.as_str(), "synced text\nunsynced line");
+    }
+
+    #[tokio::test]
+    async fn a_backup_older_than_the_document_is_ignored() {
+        let (_repo, handle) = handle_with_notes("synced text", i64::MAX).await;
+        let path = backup_file("stale text");
+
+        let app = App::new(handle, EditField::Notes, None, Theme::new(ThemeName::Dark), path).unwrap();
+        assert!(app.recovery.is_none());
+    }
+}
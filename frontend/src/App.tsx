@@ -2,7 +2,7 @@ import { useEffect, useState } from "react";
 import { Repo, DocHandle, type AutomergeUrl } from "@automerge/automerge-repo";
 import { BrowserWebSocketClientAdapter } from "@automerge/automerge-repo-network-websocket";
 import { IndexedDBStorageAdapter } from "@automerge/automerge-repo-storage-indexeddb";
-import { ImmutableString } from "@automerge/automerge";
+import { Counter, ImmutableString } from "@automerge/automerge";
 import {
   Card,
   CardContent,
@@ -25,11 +25,12 @@ interface TodoItem {
   id: ImmutableString | string;
   text: ImmutableString | string;
   completed: boolean;
+  tags?: (ImmutableString | string)[];
 }
 
 interface Doc {
-  // Basic scalar types
-  counter: number;
+  // Basic scalar types (counter is a CRDT Counter; older documents have a number)
+  counter: Counter | number;
   temperature: number;
   darkMode: boolean;
 
@@ -105,7 +106,7 @@ function App() {
         handle = repo.create<Doc>();
         handle.change((d: Doc) => {
           // Initialize document with all data types
-          d.counter = 0;
+          d.counter = new Counter(0);
           d.temperature = 20;
           d.darkMode = false;
           d.notes = "";
@@ -162,7 +163,8 @@ function App() {
   const incrementCounter = () => {
     if (!docHandle) return;
     docHandle.change((d: Doc) => {
-      d.counter = (d.counter || 0) + 1;
+      if (d.counter instanceof Counter) d.counter.increment(1);
+      else d.counter = new Counter((d.counter || 0) + 1);
       if (!d.metadata) d.metadata = {};
       d.metadata.lastModified = Date.now();
     });
@@ -171,7 +173,8 @@ function App() {
   const decrementCounter = () => {
     if (!docHandle) return;
     docHandle.change((d: Doc) => {
-      d.counter = (d.counter || 0) - 1;
+      if (d.counter instanceof Counter) d.counter.decrement(1);
+      else d.counter = new Counter((d.counter || 0) - 1);
       if (!d.metadata) d.metadata = {};
       d.metadata.lastModified = Date.now();
     });
@@ -334,7 +337,7 @@ function App() {
                   <Minus className="h-4 w-4" />
                 </Button>
                 <div className="text-5xl font-bold text-primary w-24 text-center">
-                  {doc.counter || 0}
+                  {Number(doc.counter ?? 0)}
                 </div>
                 <Button
                   onClick={incrementCounter}